@@ -21,6 +21,7 @@ fn bench_settings(
 fn bench_step(c: &mut Criterion) {
     let settings = [
         ("balanced", Settings::balanced()),
+        ("cells", Settings::cells()),
         ("chaos", Settings::chaos()),
         ("diversity", Settings::diversity()),
         ("frictionless", Settings::frictionless()),
@@ -28,13 +29,14 @@ fn bench_step(c: &mut Criterion) {
         ("homogeneity", Settings::homogeneity()),
         ("large_clusters", Settings::large_clusters()),
         ("medium_clusters", Settings::medium_clusters()),
+        ("orbits", Settings::orbits()),
         ("quiescence", Settings::quiescence()),
         ("small_clusters", Settings::small_clusters()),
     ];
 
     let mut non_wrapping = c.benchmark_group("non-wrapping");
-    for (name, settings) in settings {
-        bench_settings(&mut non_wrapping, name, settings, false);
+    for (name, settings) in &settings {
+        bench_settings(&mut non_wrapping, name, settings.clone(), false);
     }
     non_wrapping.finish();
 
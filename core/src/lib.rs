@@ -0,0 +1,104 @@
+//! The particle life simulation itself, without any of the windowing or
+//! rendering in the `particle-life` crate.
+//!
+//! This only needs `alloc`, so it can be used in `no_std` environments by
+//! turning off the default `std` feature. `std` adds reading and writing CSV
+//! (see `Sim::load_attraction_csv`) and the `web_config` module, and uses the
+//! standard library's float functions rather than `libm`'s.
+
+#![no_std]
+
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
+
+use bytemuck::Pod;
+use bytemuck::Zeroable;
+use glam::Vec2;
+use palette::LinSrgb;
+
+use sim::Particle;
+
+pub mod settings;
+pub mod sim;
+#[cfg(feature = "std")]
+pub mod web_config;
+
+/// The maximum number of particles in a simulation, which is how many fit in
+/// the GPU's particle buffer.
+pub const MAX_PARTICLES: usize = 600;
+
+/// The particle information sent to the GPU.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, Zeroable, Pod)]
+pub struct GpuParticle {
+    pos: Vec2,
+    color: LinSrgb,
+    /// The particle's radius as a multiple of `RADIUS`.
+    scale: f32,
+    /// How visible the particle is in its trail, as a multiple of the trail's
+    /// usual opacity.
+    trail_alpha: f32,
+}
+
+impl GpuParticle {
+    /// Packs a particle into the form the GPU uses, drawing it with `color` at
+    /// the default radius.
+    ///
+    /// The position is already in clip space, so it's passed through as-is.
+    pub fn from_particle(particle: &Particle, color: LinSrgb) -> Self {
+        Self {
+            pos: particle.pos,
+            color,
+            scale: 1.0,
+            trail_alpha: 1.0,
+        }
+    }
+
+    /// The particle's position in clip space.
+    pub fn pos(&self) -> Vec2 {
+        self.pos
+    }
+
+    /// Returns a copy of this particle moved to `pos`, in clip space.
+    pub fn with_pos(self, pos: Vec2) -> Self {
+        Self { pos, ..self }
+    }
+
+    /// The particle's color.
+    pub fn color(&self) -> LinSrgb {
+        self.color
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::vec2;
+
+    use super::*;
+
+    #[test]
+    fn from_particle_is_bit_exact() {
+        // Values that don't round-trip through anything but an exact copy.
+        let pos = vec2(f32::from_bits(0x3eaa_aaab), -f32::MIN_POSITIVE);
+        let color = LinSrgb::new(0.1, f32::EPSILON, 1.0 / 3.0);
+        let particle = Particle {
+            pos,
+            vel: vec2(3.0, 4.0),
+            kind: 2,
+            ..Particle::default()
+        };
+
+        let gpu = GpuParticle::from_particle(&particle, color);
+        assert_eq!(gpu.pos().x.to_bits(), pos.x.to_bits());
+        assert_eq!(gpu.pos().y.to_bits(), pos.y.to_bits());
+        assert_eq!(gpu.color(), color);
+        assert_eq!(gpu.scale, 1.0);
+        assert_eq!(gpu.trail_alpha, 1.0);
+
+        // The position is what the shader reads first.
+        let bytes = bytemuck::bytes_of(&gpu);
+        assert_eq!(&bytes[..8], bytemuck::bytes_of(&pos));
+        assert_eq!(gpu.with_pos(Vec2::ZERO).color(), color);
+    }
+}
@@ -0,0 +1,498 @@
+use alloc::vec::Vec;
+use core::ops::RangeInclusive;
+
+#[cfg(not(feature = "std"))]
+use num_traits::Float;
+use rand_distr::Normal;
+
+use crate::sim::PaletteSpec;
+
+/// The number of steps per second which the values in `Settings` are
+/// calibrated for, and the default step rate of `State`.
+pub const REFERENCE_STEP_RATE: u32 = 300;
+
+/// Returns how many steps at `REFERENCE_STEP_RATE` each step should advance
+/// the simulation by when running at `step_rate` steps per second, so that it
+/// moves at the same speed in real time whatever the step rate.
+///
+/// A `step_rate` of 0 is treated as 1.
+pub fn real_time_dt(step_rate: u32) -> f32 {
+    REFERENCE_STEP_RATE as f32 / step_rate.max(1) as f32
+}
+
+/// Converts a velocity in pixels per step, as used by `Settings` and `Sim`,
+/// into pixels per second.
+pub fn velocity_per_second(velocity: f32) -> f32 {
+    velocity * REFERENCE_STEP_RATE as f32
+}
+
+/// Converts a velocity in pixels per second into pixels per step.
+pub fn velocity_per_step(velocity: f32) -> f32 {
+    velocity / REFERENCE_STEP_RATE as f32
+}
+
+/// Converts an acceleration (such as an attraction) in pixels per step
+/// squared into pixels per second squared.
+pub fn acceleration_per_second(acceleration: f32) -> f32 {
+    acceleration * (REFERENCE_STEP_RATE * REFERENCE_STEP_RATE) as f32
+}
+
+/// Converts an acceleration in pixels per second squared into pixels per step
+/// squared.
+pub fn acceleration_per_step(acceleration: f32) -> f32 {
+    acceleration / (REFERENCE_STEP_RATE * REFERENCE_STEP_RATE) as f32
+}
+
+/// Converts a friction (the fraction of velocity lost every step) into the
+/// fraction of velocity lost every second.
+pub fn friction_per_second(friction: f32) -> f32 {
+    1.0 - (1.0 - friction).powf(REFERENCE_STEP_RATE as f32)
+}
+
+/// Converts the fraction of velocity lost every second into a friction.
+///
+/// Frictions of 1 or more can't be expressed per second, since they stop or
+/// reverse particles every step.
+pub fn friction_per_step(friction: f32) -> f32 {
+    1.0 - (1.0 - friction).powf(1.0 / REFERENCE_STEP_RATE as f32)
+}
+
+/// How the attractions between each pair of kinds are picked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MatrixKind {
+    /// Sample every attraction from `attraction_distr`.
+    #[default]
+    Random,
+    /// Each kind chases the next kind and runs away from the previous one, so
+    /// that kinds chase each other around in a cycle.
+    Chase,
+    /// Each kind is attracted to itself and the next kind, so that kinds link
+    /// up into long chains.
+    Snake,
+}
+
+impl MatrixKind {
+    /// Returns the matrix kind after this one, wrapping back around to the
+    /// first.
+    pub fn next(self) -> Self {
+        match self {
+            MatrixKind::Random => MatrixKind::Chase,
+            MatrixKind::Chase => MatrixKind::Snake,
+            MatrixKind::Snake => MatrixKind::Random,
+        }
+    }
+}
+
+/// A parameter of `Settings` which can be varied by `Settings::sweep`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SweepParam {
+    /// The mean of `attraction_distr`.
+    AttractionMean,
+    /// The standard deviation of `attraction_distr`.
+    AttractionStdDev,
+    Friction,
+}
+
+impl SweepParam {
+    /// Returns `settings` with this parameter set to `value`.
+    ///
+    /// Negative standard deviations are clamped to 0.
+    pub fn apply(self, mut settings: Settings, value: f32) -> Settings {
+        let distr = settings.attraction_distr;
+        match self {
+            SweepParam::AttractionMean => {
+                settings.attraction_distr = Normal::new(value, distr.std_dev()).unwrap()
+            }
+            SweepParam::AttractionStdDev => {
+                settings.attraction_distr = Normal::new(distr.mean(), value.max(0.0)).unwrap()
+            }
+            SweepParam::Friction => settings.friction = value,
+        }
+        settings
+    }
+}
+
+/// The parameters a `Sim` is generated from.
+///
+/// Time-dependent values are measured in steps of the simulation, which are
+/// calibrated to run at `REFERENCE_STEP_RATE` steps per second. So, to convert
+/// to per-second units, multiply velocities by `REFERENCE_STEP_RATE`,
+/// accelerations (like attractions) by `REFERENCE_STEP_RATE` squared, and raise
+/// `1.0 - friction` to the power of `REFERENCE_STEP_RATE`; `velocity_per_second`,
+/// `acceleration_per_second` and `friction_per_second` (and their inverses) do
+/// this.
+///
+/// Stepping with a `dt` of `real_time_dt(step_rate)` makes these mean the same
+/// thing in real time whatever the step rate.
+#[derive(Clone)]
+pub struct Settings {
+    pub particles: usize,
+    pub kinds: usize,
+
+    /// The distribution of the peak accelerations between kinds, in pixels per
+    /// step squared.
+    pub attraction_distr: Normal<f32>,
+    /// How to arrange the attractions sampled from `attraction_distr`.
+    ///
+    /// For anything but `MatrixKind::Random`, only the magnitudes of the
+    /// samples are used.
+    pub matrix_kind: MatrixKind,
+    /// Whether to flip the sign of every attraction once it's been picked.
+    ///
+    /// Here, positive attractions pull particles together and negative ones
+    /// push them apart; some other particle life implementations use the
+    /// opposite convention, and this makes it possible to use their settings
+    /// directly.
+    pub invert_attraction: bool,
+    /// The range repel distances between different kinds are picked from
+    /// (uniformly), in pixels.
+    ///
+    /// These are kept as ranges rather than `Uniform`s so that their bounds
+    /// can be checked.
+    pub repel_distance_range: RangeInclusive<f32>,
+    /// The range influence radii are picked from (uniformly), in pixels.
+    pub influence_radius_range: RangeInclusive<f32>,
+
+    /// The fraction of their velocity particles lose each step.
+    pub friction: f32,
+    pub flat_force: bool,
+    /// How to pick the colors of each kind.
+    pub palette: PaletteSpec,
+}
+
+// `Normal` doesn't implement `PartialEq`, so compare its parameters instead.
+impl PartialEq for Settings {
+    fn eq(&self, other: &Self) -> bool {
+        self.particles == other.particles
+            && self.kinds == other.kinds
+            && self.attraction_distr.mean() == other.attraction_distr.mean()
+            && self.attraction_distr.std_dev() == other.attraction_distr.std_dev()
+            && self.matrix_kind == other.matrix_kind
+            && self.invert_attraction == other.invert_attraction
+            && self.repel_distance_range == other.repel_distance_range
+            && self.influence_radius_range == other.influence_radius_range
+            && self.friction == other.friction
+            && self.flat_force == other.flat_force
+            && self.palette == other.palette
+    }
+}
+
+impl Settings {
+    /// Returns `steps` variations of these settings, with `param` spread evenly
+    /// across `range` (including both ends), along with the value of `param`
+    /// each one uses.
+    pub fn sweep(
+        self,
+        param: SweepParam,
+        range: RangeInclusive<f32>,
+        steps: usize,
+    ) -> Vec<(f32, Settings)> {
+        let (start, end) = range.into_inner();
+        (0..steps)
+            .map(|i| {
+                let t = if steps > 1 {
+                    i as f32 / (steps - 1) as f32
+                } else {
+                    0.0
+                };
+                let value = start + t * (end - start);
+                (value, param.apply(self.clone(), value))
+            })
+            .collect()
+    }
+
+    /// Returns the name of the preset these settings are identical to, if
+    /// there is one.
+    pub fn matching_preset(&self) -> Option<&'static str> {
+        Settings::preset_names()
+            .iter()
+            .copied()
+            .find(|&name| Settings::by_name(name).as_ref() == Some(self))
+    }
+
+    /// The names of all the presets, as accepted by `by_name`.
+    pub fn preset_names() -> &'static [&'static str] {
+        &[
+            "balanced",
+            "cells",
+            "chaos",
+            "diversity",
+            "frictionless",
+            "gliders",
+            "homogeneity",
+            "large_clusters",
+            "medium_clusters",
+            "orbits",
+            "quiescence",
+            "small_clusters",
+        ]
+    }
+
+    /// Returns the preset with the given name, if there is one.
+    pub fn by_name(name: &str) -> Option<Settings> {
+        match name {
+            "balanced" => Some(Settings::balanced()),
+            "cells" => Some(Settings::cells()),
+            "chaos" => Some(Settings::chaos()),
+            "diversity" => Some(Settings::diversity()),
+            "frictionless" => Some(Settings::frictionless()),
+            "gliders" => Some(Settings::gliders()),
+            "homogeneity" => Some(Settings::homogeneity()),
+            "large_clusters" => Some(Settings::large_clusters()),
+            "medium_clusters" => Some(Settings::medium_clusters()),
+            "orbits" => Some(Settings::orbits()),
+            "quiescence" => Some(Settings::quiescence()),
+            "small_clusters" => Some(Settings::small_clusters()),
+            _ => None,
+        }
+    }
+
+    // Ideally these would be constants, but `Normal` and `Uniform` can't yet be
+    // created in `const` contexts because they're generic.
+    pub fn balanced() -> Settings {
+        Settings {
+            kinds: 9,
+            particles: 400,
+            attraction_distr: Normal::new(-0.02, 0.06).unwrap(),
+            matrix_kind: MatrixKind::Random,
+            invert_attraction: false,
+            repel_distance_range: 0.0..=20.0,
+            influence_radius_range: 20.0..=70.0,
+            friction: 0.05,
+            flat_force: false,
+            palette: PaletteSpec::Full,
+        }
+    }
+
+    pub fn cells() -> Settings {
+        Settings {
+            kinds: 5,
+            particles: 500,
+            attraction_distr: Normal::new(-0.01, 0.05).unwrap(),
+            matrix_kind: MatrixKind::Random,
+            invert_attraction: false,
+            repel_distance_range: 15.0..=25.0,
+            influence_radius_range: 30.0..=70.0,
+            friction: 0.1,
+            flat_force: false,
+            palette: PaletteSpec::Full,
+        }
+    }
+
+    pub fn chaos() -> Settings {
+        Settings {
+            kinds: 6,
+            particles: 400,
+            attraction_distr: Normal::new(0.02, 0.04).unwrap(),
+            matrix_kind: MatrixKind::Random,
+            invert_attraction: false,
+            repel_distance_range: 0.0..=30.0,
+            influence_radius_range: 30.0..=100.0,
+            friction: 0.01,
+            flat_force: false,
+            palette: PaletteSpec::Full,
+        }
+    }
+
+    pub fn diversity() -> Settings {
+        Settings {
+            kinds: 12,
+            particles: 400,
+            attraction_distr: Normal::new(-0.01, 0.04).unwrap(),
+            matrix_kind: MatrixKind::Random,
+            invert_attraction: false,
+            repel_distance_range: 0.0..=20.0,
+            influence_radius_range: 10.0..=60.0,
+            friction: 0.05,
+            flat_force: true,
+            palette: PaletteSpec::Full,
+        }
+    }
+
+    pub fn frictionless() -> Settings {
+        Settings {
+            kinds: 6,
+            particles: 300,
+            attraction_distr: Normal::new(0.01, 0.005).unwrap(),
+            matrix_kind: MatrixKind::Random,
+            invert_attraction: false,
+            repel_distance_range: 10.0..=10.0,
+            influence_radius_range: 10.0..=60.0,
+            friction: 0.0,
+            flat_force: true,
+            palette: PaletteSpec::Full,
+        }
+    }
+
+    pub fn gliders() -> Settings {
+        Settings {
+            kinds: 6,
+            particles: 400,
+            attraction_distr: Normal::new(0.0, 0.06).unwrap(),
+            matrix_kind: MatrixKind::Random,
+            invert_attraction: false,
+            repel_distance_range: 0.0..=20.0,
+            influence_radius_range: 10.0..=50.0,
+            friction: 0.01,
+            flat_force: true,
+            palette: PaletteSpec::Full,
+        }
+    }
+
+    pub fn homogeneity() -> Settings {
+        Settings {
+            kinds: 4,
+            particles: 400,
+            attraction_distr: Normal::new(0.0, 0.04).unwrap(),
+            matrix_kind: MatrixKind::Random,
+            invert_attraction: false,
+            repel_distance_range: 10.0..=10.0,
+            influence_radius_range: 10.0..=80.0,
+            friction: 0.05,
+            flat_force: true,
+            palette: PaletteSpec::Full,
+        }
+    }
+
+    pub fn large_clusters() -> Settings {
+        Settings {
+            kinds: 6,
+            particles: 400,
+            attraction_distr: Normal::new(0.025, 0.02).unwrap(),
+            matrix_kind: MatrixKind::Random,
+            invert_attraction: false,
+            repel_distance_range: 0.0..=30.0,
+            influence_radius_range: 30.0..=100.0,
+            friction: 0.2,
+            flat_force: false,
+            palette: PaletteSpec::Full,
+        }
+    }
+
+    pub fn medium_clusters() -> Settings {
+        Settings {
+            kinds: 6,
+            particles: 400,
+            attraction_distr: Normal::new(0.02, 0.05).unwrap(),
+            matrix_kind: MatrixKind::Random,
+            invert_attraction: false,
+            repel_distance_range: 0.0..=20.0,
+            influence_radius_range: 20.0..=50.0,
+            friction: 0.05,
+            flat_force: false,
+            palette: PaletteSpec::Full,
+        }
+    }
+
+    pub fn orbits() -> Settings {
+        Settings {
+            kinds: 3,
+            particles: 300,
+            attraction_distr: Normal::new(0.01, 0.05).unwrap(),
+            matrix_kind: MatrixKind::Random,
+            invert_attraction: false,
+            repel_distance_range: 10.0..=15.0,
+            influence_radius_range: 40.0..=90.0,
+            friction: 0.005,
+            flat_force: false,
+            palette: PaletteSpec::Full,
+        }
+    }
+
+    pub fn quiescence() -> Settings {
+        Settings {
+            kinds: 6,
+            particles: 300,
+            attraction_distr: Normal::new(-0.02, 0.1).unwrap(),
+            matrix_kind: MatrixKind::Random,
+            invert_attraction: false,
+            repel_distance_range: 10.0..=20.0,
+            influence_radius_range: 20.0..=60.0,
+            friction: 0.2,
+            flat_force: false,
+            palette: PaletteSpec::Full,
+        }
+    }
+
+    pub fn small_clusters() -> Settings {
+        Settings {
+            kinds: 6,
+            particles: 600,
+            attraction_distr: Normal::new(-0.005, 0.01).unwrap(),
+            matrix_kind: MatrixKind::Random,
+            invert_attraction: false,
+            repel_distance_range: 10.0..=10.0,
+            influence_radius_range: 20.0..=50.0,
+            friction: 0.01,
+            flat_force: false,
+            palette: PaletteSpec::Full,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::Sim;
+
+    #[test]
+    fn every_preset_is_valid() {
+        for &name in Settings::preset_names() {
+            let settings = Settings::by_name(name).unwrap();
+            if let Err(e) = Sim::try_from_seed(settings, 0) {
+                panic!("preset {name} is invalid: {e}");
+            }
+        }
+        // These were added later, so make sure they're reachable.
+        assert!(Settings::by_name("orbits").is_some());
+        assert!(Settings::by_name("cells").is_some());
+        assert!(Settings::by_name("nonexistent").is_none());
+    }
+
+    #[test]
+    fn sweeps_evenly_across_range() {
+        let runs = Settings::balanced().sweep(SweepParam::Friction, 0.0..=0.2, 5);
+        let values: Vec<f32> = runs.iter().map(|&(value, _)| value).collect();
+        assert_eq!(values, [0.0, 0.05, 0.1, 0.15, 0.2]);
+        for (value, settings) in &runs {
+            assert_eq!(settings.friction, *value);
+        }
+
+        let runs = Settings::balanced().sweep(SweepParam::AttractionMean, -0.05..=0.05, 3);
+        for ((value, settings), expected) in runs.iter().zip([-0.05, 0.0, 0.05]) {
+            assert_eq!(*value, expected);
+            assert_eq!(settings.attraction_distr.mean(), expected);
+            assert_eq!(
+                settings.attraction_distr.std_dev(),
+                Settings::balanced().attraction_distr.std_dev()
+            );
+        }
+
+        // Negative standard deviations aren't valid, so they're clamped.
+        let runs = Settings::balanced().sweep(SweepParam::AttractionStdDev, -1.0..=1.0, 2);
+        assert_eq!(runs[0].1.attraction_distr.std_dev(), 0.0);
+        assert_eq!(runs[1].1.attraction_distr.std_dev(), 1.0);
+    }
+
+    #[test]
+    fn each_preset_matches_itself() {
+        for &name in Settings::preset_names() {
+            let settings = Settings::by_name(name).unwrap();
+            assert_eq!(settings.matching_preset(), Some(name));
+
+            let tweaked = Settings {
+                friction: settings.friction + 0.01,
+                ..settings.clone()
+            };
+            assert_eq!(tweaked.matching_preset(), None, "tweaked {name}");
+            let tweaked = SweepParam::AttractionMean
+                .apply(settings.clone(), settings.attraction_distr.mean() + 0.001);
+            assert_eq!(tweaked.matching_preset(), None, "tweaked {name}");
+            let tweaked = Settings {
+                influence_radius_range: 1.0..=2.0,
+                ..settings
+            };
+            assert_eq!(tweaked.matching_preset(), None, "tweaked {name}");
+        }
+    }
+}
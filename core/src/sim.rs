@@ -0,0 +1,4879 @@
+//! The particle simulation itself.
+//!
+//! Particles' positions are stored in clip space, which runs from -1 to 1 along
+//! both axes no matter the size of the window, so that they can be sent
+//! straight to the GPU. Everything else - velocities, forces, distances and
+//! radii - is in logical pixels, so that particles behave the same way at any
+//! aspect ratio.
+//!
+//! `step` converts between the two using the size of the window it's given;
+//! `pixels_to_clip` and `clip_to_pixels` do the same conversion for other
+//! code. Velocities are measured in pixels per step.
+
+use alloc::collections::BTreeSet;
+use alloc::format;
+use alloc::string::String;
+#[cfg(feature = "std")]
+use alloc::string::ToString;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::error::Error;
+use core::f32::consts::PI;
+use core::f32::consts::TAU;
+use core::fmt;
+use core::ops::RangeInclusive;
+#[cfg(feature = "std")]
+use std::io;
+#[cfg(feature = "std")]
+use std::io::BufRead;
+#[cfg(feature = "std")]
+use std::io::BufReader;
+#[cfg(feature = "std")]
+use std::io::Read;
+#[cfg(feature = "std")]
+use std::io::Write;
+
+use glam::vec2;
+use glam::Vec2;
+#[cfg(not(feature = "std"))]
+use num_traits::Float;
+use palette::FromColor;
+use palette::Hsv;
+use palette::LinSrgb;
+use palette::Mix;
+use palette::Oklab;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use rand::SeedableRng;
+use rand_distr::Distribution;
+use rand_distr::Normal;
+use rand_distr::Uniform;
+use rand_distr::WeightedIndex;
+
+use crate::settings::MatrixKind;
+use crate::settings::Settings;
+use crate::GpuParticle;
+use crate::MAX_PARTICLES;
+
+pub const RADIUS: f32 = 5.0;
+pub const DIAMETER: f32 = RADIUS * 2.0;
+pub const R_SMOOTH: f32 = 2.0;
+/// The default value of `Sim::min_distance_sq`.
+pub const DEFAULT_MIN_DISTANCE_SQ: f32 = 0.01;
+
+/// Converts a vector in logical pixels to clip space, in a simulation
+/// `width`x`height` pixels in size.
+pub fn pixels_to_clip(v: Vec2, width: f32, height: f32) -> Vec2 {
+    // Clip space is 2 units across, hence the 2.
+    v * 2.0 / vec2(width, height)
+}
+
+/// Converts a vector in clip space to logical pixels, in a simulation
+/// `width`x`height` pixels in size.
+pub fn clip_to_pixels(v: Vec2, width: f32, height: f32) -> Vec2 {
+    v * 0.5 * vec2(width, height)
+}
+
+/// An extra band of force between a pair of kinds, on top of the usual
+/// repulsion and attraction, such as a second layer of repulsion further out.
+///
+/// The force rises linearly from 0 at `start` to `attraction` halfway through
+/// the band, and falls back to 0 at `end`, whether or not `Sim::flat_force` is
+/// set.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ForceBand {
+    /// The peak attraction within the band; like `PairProps::attraction`,
+    /// negative values push particles apart.
+    pub attraction: f32,
+    /// The distance (in pixels) the band starts at.
+    pub start: f32,
+    /// The distance (in pixels) the band ends at.
+    pub end: f32,
+}
+
+impl ForceBand {
+    /// Returns the force the band exerts between particles `dist` pixels
+    /// apart.
+    pub fn force(&self, dist: f32) -> f32 {
+        if dist <= self.start || dist >= self.end {
+            return 0.0;
+        }
+
+        let peak = 0.5 * (self.start + self.end);
+        let half_width = 0.5 * (self.end - self.start);
+        self.attraction * (1.0 - f32::abs(dist - peak) / half_width)
+    }
+}
+
+/// The properties between a pair of particle kinds.
+/// Everything apart from `attraction` and `outer_band` is the same in both
+/// directions.
+#[derive(Clone, Copy, Debug)]
+pub struct PairProps {
+    /// The peak attraction between two particles.
+    ///
+    /// Positive attractions pull particles together, and negative ones push
+    /// them apart. Unless `Settings::invert_attraction` is set, `Sim::new`
+    /// always makes particles of the same kind push each other apart with
+    /// `MatrixKind::Random` and `MatrixKind::Chase`.
+    pub attraction: f32,
+    /// The distance below which particles begin to unconditionally repel each
+    /// other.
+    pub repel_distance: f32,
+    /// The distance above which particles have no influence on each other,
+    /// apart from through `outer_band`.
+    pub influence_radius: f32,
+    /// An extra band of force, usually further out than `influence_radius`.
+    ///
+    /// Set this with `Sim::set_outer_band`, so that `cutoff_sq` is kept up to
+    /// date in both directions.
+    pub outer_band: Option<ForceBand>,
+
+    // Stuff which is just computed ahead-of-time to improve performance.
+    /// The distance above which particles have no influence on each other,
+    /// squared.
+    pub influence_radius_sq: f32,
+    /// The distance above which neither band of either direction of the pair
+    /// has any influence, squared.
+    pub cutoff_sq: f32,
+    /// The point of maximum force, halfway between `repel_distance` and
+    /// `influence_radius`.
+    pub peak: f32,
+    /// The reciprocal of the distance between
+    /// `repel_distance`/`influence_radius` and `peak`.
+    pub inv_base: f32,
+}
+
+impl PairProps {
+    /// Creates a `PairProps`, computing all the derived fields.
+    pub fn new(attraction: f32, repel_distance: f32, influence_radius: f32) -> Self {
+        Self {
+            attraction,
+            repel_distance,
+            influence_radius,
+            outer_band: None,
+
+            influence_radius_sq: influence_radius * influence_radius,
+            cutoff_sq: influence_radius * influence_radius,
+            peak: 0.5 * (repel_distance + influence_radius),
+            inv_base: 2.0 / (influence_radius - repel_distance),
+        }
+    }
+
+    /// Returns the distance past which this direction of the pair has no
+    /// influence, squared, taking `outer_band` into account.
+    fn own_cutoff_sq(&self) -> f32 {
+        match self.outer_band {
+            Some(band) => self.influence_radius_sq.max(band.end * band.end),
+            None => self.influence_radius_sq,
+        }
+    }
+
+    /// Returns the distance at which the force between the pair is zero, and
+    /// which particles settle towards, or `None` if the pair never attracts.
+    ///
+    /// This ignores `outer_band`.
+    ///
+    /// The repulsive force below `repel_distance` falls to zero right at
+    /// `repel_distance`, where the attractive force (whether triangular or
+    /// flat) takes over; so, as long as there's any attraction, that's where the
+    /// stable separation is.
+    pub fn equilibrium_distance(&self) -> Option<f32> {
+        if self.attraction > 0.0 && self.influence_radius > self.repel_distance {
+            Some(self.repel_distance)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the potential energy of a particle `dist` pixels away from a
+    /// particle it feels these properties from, for a unit mass.
+    ///
+    /// This is the integral of the force out to where it stops, so the force
+    /// is the rate at which it increases with distance and it's 0 past the
+    /// influence radius and `outer_band`. `flat_force` is the shape of the
+    /// attraction if `Sim::flat_force` is set, or `None` for the usual
+    /// triangular one.
+    pub fn potential(&self, dist: f32, flat_force: Option<FlatForce>) -> f32 {
+        let repel_distance = self.repel_distance;
+        let mut integral = 0.0;
+
+        if dist < repel_distance {
+            let smoothed = repel_distance + R_SMOOTH;
+            integral += R_SMOOTH
+                * repel_distance
+                * ((repel_distance - dist) / smoothed - f32::ln(smoothed / (dist + R_SMOOTH)));
+        }
+
+        let width = self.influence_radius - repel_distance;
+        let ramp = match flat_force {
+            None => 0.5 * width,
+            Some(FlatForce::Soft { edge_fraction }) if edge_fraction > 0.0 => {
+                edge_fraction.min(0.5) * width
+            }
+            Some(_) => 0.0,
+        };
+        integral +=
+            self.attraction * trapezoid_integral(dist, repel_distance, self.influence_radius, ramp);
+
+        if let Some(band) = self.outer_band {
+            let ramp = 0.5 * (band.end - band.start);
+            integral += band.attraction * trapezoid_integral(dist, band.start, band.end, ramp);
+        }
+
+        -integral
+    }
+}
+
+/// Returns the integral from `x` to `end` of a trapezoid of height 1, which
+/// ramps up from 0 at `start` over a distance of `ramp`, stays at 1, and then
+/// ramps back down to 0 at `end` over `ramp` again.
+///
+/// A `ramp` of 0 gives a rectangle, and one of half of `end - start` gives a
+/// triangle.
+fn trapezoid_integral(x: f32, start: f32, end: f32, ramp: f32) -> f32 {
+    if x >= end || end <= start {
+        return 0.0;
+    }
+
+    let total = end - start - ramp;
+    let x = x.max(start);
+    let u = x - start;
+    // The area between `start` and `x`.
+    let below = if u < ramp {
+        0.5 * u * u / ramp
+    } else if x <= end - ramp {
+        0.5 * ramp + (u - ramp)
+    } else {
+        let v = end - x;
+        total - 0.5 * v * v / ramp
+    };
+    total - below
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Particle {
+    // This is stored in clip space, so that we can just send it directly to the GPU and it doesn't
+    // require any extra work on resize.
+    pub pos: Vec2,
+    /// The particle's velocity, in logical pixels per step.
+    pub vel: Vec2,
+    pub kind: usize,
+    /// The number of steps in a row this particle has had no neighbours of its
+    /// own kind, for `LifeRules`.
+    pub lonely_steps: u32,
+    /// Whether this particle is held in place. Pinned particles still exert
+    /// forces on other particles.
+    pub pinned: bool,
+    /// The number of steps this particle has existed for.
+    pub age: u32,
+}
+
+impl Particle {
+    pub fn generate<R: Rng>(num_kinds: usize, rng: &mut R) -> Self {
+        let kinds = Uniform::new(0, num_kinds);
+        // This is in clip space, so it ranges from -1 to 1.
+        let pos_dist = Uniform::new(-0.5, 0.5);
+        let vel_dist = Normal::new(0.0, 0.2).unwrap();
+
+        Self {
+            kind: kinds.sample(rng),
+            pos: vec2(pos_dist.sample(rng), pos_dist.sample(rng)),
+            vel: vec2(vel_dist.sample(rng), vel_dist.sample(rng)),
+            lonely_steps: 0,
+            pinned: false,
+            age: 0,
+        }
+    }
+}
+
+/// How particles are positioned when they're regenerated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Placement {
+    /// Place particles uniformly at random.
+    #[default]
+    Random,
+    /// Spread particles out evenly using a Halton sequence, avoiding the clumps
+    /// and gaps that random placement produces with few particles.
+    LowDiscrepancy,
+}
+
+/// Statistics about the work done by `Sim::step_with_stats`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct StepStats {
+    /// The number of pairs of particles close enough to exert a force on each
+    /// other.
+    pub evaluated_pairs: usize,
+    /// The number of pairs of particles which were too far apart (or too close
+    /// together) to exert a force, and so were skipped.
+    pub skipped_pairs: usize,
+}
+
+/// An axis-aligned rectangle.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rect {
+    /// The bottom-left corner.
+    pub min: Vec2,
+    /// The top-right corner.
+    pub max: Vec2,
+}
+
+impl Rect {
+    pub fn new(min: Vec2, max: Vec2) -> Self {
+        Self { min, max }
+    }
+
+    /// Returns whether `point` is inside the rectangle (including its edges).
+    pub fn contains(&self, point: Vec2) -> bool {
+        point.cmpge(self.min).all() && point.cmple(self.max).all()
+    }
+}
+
+/// How `step` integrates the forces on particles to move them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Integrator {
+    /// Apply the forces to the particles' velocities, then move them along
+    /// their new velocities.
+    #[default]
+    SemiImplicitEuler,
+    /// Apply half of the forces, move the particles, then apply the other half
+    /// of the forces at their new positions ("kick-drift-kick").
+    ///
+    /// This keeps orbits much more stable, but calculates the forces twice per
+    /// step, so it's about half as fast.
+    Leapfrog,
+}
+
+/// The shape of the area particles are kept within when the simulation isn't
+/// wrapping.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Arena {
+    /// The whole window, with particles bouncing off each edge.
+    #[default]
+    Rect,
+    /// A disc of radius 1 in clip space (so an ellipse if the window isn't
+    /// square), with particles bouncing off its curved edge.
+    ///
+    /// This avoids particles getting stuck in the corners.
+    Disc,
+}
+
+/// The shape of the attraction between particles when `Sim::flat_force` is
+/// set.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum FlatForce {
+    /// The same attraction across the whole band between `repel_distance` and
+    /// `influence_radius`, cutting off sharply at either end.
+    #[default]
+    Hard,
+    /// The same attraction across the middle of the band, but ramping down
+    /// linearly to zero over `edge_fraction` of its width at either end.
+    ///
+    /// This avoids particles buzzing back and forth across the edge of each
+    /// other's influence radius.
+    Soft { edge_fraction: f32 },
+}
+
+impl FlatForce {
+    /// Returns the fraction of the full attraction between a pair with
+    /// properties `props` at `dist` pixels apart, where `dist` is between
+    /// `repel_distance` and `influence_radius`.
+    pub fn coefficient(self, props: &PairProps, dist: f32) -> f32 {
+        match self {
+            FlatForce::Soft { edge_fraction } if edge_fraction > 0.0 => {
+                // Past half of the band, the ramps would overlap.
+                let edge = edge_fraction.min(0.5) * (props.influence_radius - props.repel_distance);
+                f32::min(dist - props.repel_distance, props.influence_radius - dist).min(edge)
+                    / edge
+            }
+            _ => 1.0,
+        }
+    }
+}
+
+/// A force pushing particles away from the edges of the simulation when it
+/// isn't wrapping, so that they turn around smoothly rather than bouncing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundaryForce {
+    /// How much a particle accelerates inwards per pixel it's inside the
+    /// boundary layer.
+    pub strength: f32,
+    /// How far the boundary layer extends in from each edge, in pixels.
+    pub thickness: f32,
+}
+
+/// Fades out the trails of slow particles, so that only fast particles leave
+/// visible streaks.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AdaptiveTrails {
+    /// The speed (in pixels per step) at and above which a particle's trail is
+    /// fully visible.
+    pub full_speed: f32,
+}
+
+impl AdaptiveTrails {
+    /// Returns how visible the trail of a particle moving at `vel` (in pixels
+    /// per step) should be, from 0 to 1.
+    pub fn alpha(&self, vel: Vec2) -> f32 {
+        if self.full_speed <= 0.0 {
+            return 1.0;
+        }
+        (vel.length() / self.full_speed).min(1.0)
+    }
+}
+
+impl Default for AdaptiveTrails {
+    fn default() -> Self {
+        Self { full_speed: 2.0 }
+    }
+}
+
+/// The order particles are drawn in, which decides which ones end up on top
+/// when they overlap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DrawOrder {
+    /// Draw particles in the order they're stored, which is sorted by kind, so
+    /// later kinds are always on top.
+    #[default]
+    ByKind,
+    /// Draw faster particles on top.
+    BySpeed,
+    /// Draw particles in a random order, so that no kind is always on top.
+    Random,
+}
+
+impl DrawOrder {
+    /// Returns the draw order after this one, wrapping back around to the
+    /// first.
+    pub fn next(self) -> Self {
+        match self {
+            DrawOrder::ByKind => DrawOrder::BySpeed,
+            DrawOrder::BySpeed => DrawOrder::Random,
+            DrawOrder::Random => DrawOrder::ByKind,
+        }
+    }
+}
+
+/// How particles are colored when they're exported for the GPU.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ColorMode {
+    /// Color particles by their kind.
+    #[default]
+    ByKind,
+    /// Color particles by their kind, but fade them out as they get older,
+    /// reaching their dimmest at `max_age` steps old.
+    ByAge { max_age: u32 },
+}
+
+impl ColorMode {
+    /// The fraction of its brightness a particle loses by the time it's
+    /// `max_age` steps old, for `ColorMode::ByAge`.
+    const AGE_FADE: f32 = 0.75;
+
+    /// Returns the color `particle` should be drawn with, given the color of
+    /// its kind.
+    pub fn color(self, particle: &Particle, kind_color: LinSrgb) -> LinSrgb {
+        match self {
+            ColorMode::ByKind => kind_color,
+            ColorMode::ByAge { max_age } => {
+                let age = if max_age == 0 {
+                    1.0
+                } else {
+                    (particle.age as f32 / max_age as f32).min(1.0)
+                };
+                kind_color.mix(LinSrgb::new(0.0, 0.0, 0.0), Self::AGE_FADE * age)
+            }
+        }
+    }
+}
+
+/// Rules for particles being born and dying, applied by
+/// `Sim::apply_life_rules`.
+///
+/// Both are based on how many neighbours of its own kind each particle has.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LifeRules {
+    /// The distance (in pixels) within which particles count as neighbours.
+    pub radius: f32,
+    /// For each kind, how many neighbours a particle needs to have a chance of
+    /// spawning a new particle, or `None` if it never does.
+    pub birth_neighbours: Vec<Option<usize>>,
+    /// The chance each step that a particle with enough neighbours spawns a
+    /// new particle.
+    pub birth_chance: f32,
+    /// For each kind, how many steps in a row a particle can go without any
+    /// neighbours before it dies, or `None` if it never does.
+    pub death_steps: Vec<Option<u32>>,
+}
+
+/// A schedule for adding particles gradually rather than all at once, applied
+/// by `Sim::apply_spawn_schedule`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpawnSchedule {
+    /// The number of particles to stop adding at. This is capped to
+    /// `MAX_PARTICLES`.
+    pub target: usize,
+    /// How many particles to add each step. Fractions accumulate, so `0.25`
+    /// adds one every four steps.
+    pub rate: f32,
+    /// How likely new particles are to be each kind, relative to each other,
+    /// or `None` to pick kinds uniformly.
+    pub kind_weights: Option<Vec<f32>>,
+}
+
+/// How to slowly evolve the attraction matrix over time; see
+/// `Sim::set_evolve`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EvolveConfig {
+    /// How many steps to wait before picking a new pair of kinds to evolve.
+    pub interval: f32,
+    /// The fraction of the remaining distance to its new attraction that the
+    /// evolving pair moves each step.
+    pub blend_rate: f32,
+}
+
+/// A schedule for ramping `Sim::friction` from `start` to `end` over time,
+/// like the cooling schedule of simulated annealing: particles explore while
+/// friction is low, and then freeze into place as it rises.
+///
+/// See `Sim::set_friction_schedule`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrictionSchedule {
+    pub start: f32,
+    pub end: f32,
+    /// How many steps it takes to get from `start` to `end`.
+    pub duration: f32,
+}
+
+impl FrictionSchedule {
+    /// Returns the friction `elapsed` steps into the schedule, interpolating
+    /// linearly between `start` and `end`.
+    pub fn friction_at(&self, elapsed: f32) -> f32 {
+        if self.duration <= 0.0 {
+            return self.end;
+        }
+        let progress = (elapsed / self.duration).clamp(0.0, 1.0);
+        self.start + (self.end - self.start) * progress
+    }
+}
+
+/// The state of an evolving attraction matrix.
+#[derive(Debug, Clone)]
+pub(crate) struct Evolution {
+    config: EvolveConfig,
+    rng: StdRng,
+    /// The range new attractions are picked from.
+    min_attraction: f32,
+    max_attraction: f32,
+    /// How many steps it's been since the evolving pair was picked.
+    elapsed: f32,
+    /// The index in `pair_props` of the pair currently evolving, and the
+    /// attraction it's evolving towards.
+    target: Option<(usize, f32)>,
+}
+
+impl Evolution {
+    /// Starts evolving `pair_props`, picking new attractions from within the
+    /// range of the ones already there.
+    fn new(config: EvolveConfig, rng: StdRng, pair_props: &[PairProps]) -> Self {
+        let attractions = pair_props.iter().map(|props| props.attraction);
+        Self {
+            config,
+            rng,
+            min_attraction: attractions.clone().fold(f32::INFINITY, f32::min),
+            max_attraction: attractions.fold(f32::NEG_INFINITY, f32::max),
+            elapsed: 0.0,
+            target: None,
+        }
+    }
+}
+
+/// How to pick the colors of each kind of particle.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum PaletteSpec {
+    /// Spread the colors' hues evenly around the whole color wheel.
+    #[default]
+    Full,
+    /// Spread the colors' hues evenly between two hues (in degrees), going
+    /// clockwise from `start` to `end`.
+    Range { start: f32, end: f32 },
+    /// Use these colors, repeating them if there are more kinds than colors.
+    Custom(Vec<LinSrgb>),
+}
+
+impl PaletteSpec {
+    /// Returns the colors of each of `kinds` kinds of particle.
+    pub fn colors(&self, kinds: usize) -> Vec<LinSrgb> {
+        let hues: Vec<f32> = match *self {
+            PaletteSpec::Custom(ref colors) if !colors.is_empty() => {
+                return colors.iter().copied().cycle().take(kinds).collect();
+            }
+            // There's nothing to pick from, so fall back to the full color wheel.
+            PaletteSpec::Full | PaletteSpec::Custom(_) => {
+                // The angle between each color's hue.
+                let angle = 360.0 / kinds as f32;
+                (0..kinds).map(|i| angle * i as f32).collect()
+            }
+            PaletteSpec::Range { start, mut end } => {
+                if end < start {
+                    end += 360.0;
+                }
+                // Unlike with the full color wheel, include both ends of the range.
+                let angle = (end - start) / kinds.saturating_sub(1).max(1) as f32;
+                (0..kinds).map(|i| start + angle * i as f32).collect()
+            }
+        };
+
+        hues.into_iter()
+            .enumerate()
+            .map(|(i, hue)| {
+                let value = if i % 2 == 0 { 0.5 } else { 1.0 };
+                LinSrgb::from_color(Hsv::new(hue, 1.0, value))
+            })
+            .collect()
+    }
+}
+
+/// The color space colors are blended in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorSpace {
+    /// Blend the red, green and blue components linearly.
+    #[default]
+    LinearRgb,
+    /// Blend in Oklab, which is perceptually uniform, so that blends don't
+    /// look muddy or dark in the middle.
+    Oklab,
+}
+
+impl ColorSpace {
+    /// Blends `a` into `b` by `factor`, from 0 (all `a`) to 1 (all `b`).
+    pub fn mix(self, a: LinSrgb, b: LinSrgb, factor: f32) -> LinSrgb {
+        match self {
+            ColorSpace::LinearRgb => a.mix(b, factor),
+            ColorSpace::Oklab => {
+                LinSrgb::from_color(Oklab::from_color(a).mix(Oklab::from_color(b), factor))
+            }
+        }
+    }
+}
+
+/// Returns `color` with its HSV value multiplied by `brightness` and its
+/// saturation multiplied by `saturation`.
+///
+/// Saturations are capped at 1, but values aren't, so brightnesses over 1 can
+/// still brighten fully-saturated colors.
+pub fn adjust_color(color: LinSrgb, brightness: f32, saturation: f32) -> LinSrgb {
+    let mut hsv = Hsv::from_color(color);
+    hsv.value *= brightness;
+    hsv.saturation = (hsv.saturation * saturation).clamp(0.0, 1.0);
+    LinSrgb::from_color(hsv)
+}
+
+/// Returns the `index`th element of the Halton sequence with the given base,
+/// which lies in `0.0..1.0`.
+fn halton(mut index: usize, base: usize) -> f32 {
+    let mut fraction = 1.0;
+    let mut result = 0.0;
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
+
+/// Moves the value for each kind `k` in `values` to index `permutation[k]`,
+/// filling in the default (e.g. `None`) for any kinds `values` is missing.
+fn permute_per_kind<T: Copy + Default>(values: &[T], permutation: &[usize]) -> Vec<T> {
+    let mut permuted = vec![T::default(); permutation.len()];
+    for (&value, &k) in values.iter().zip(permutation) {
+        permuted[k] = value;
+    }
+    permuted
+}
+
+/// Returns the offset of each kind's row in a `kinds`x`kinds` table of
+/// `PairProps`.
+fn kind_offsets(kinds: usize) -> Vec<usize> {
+    (0..kinds).map(|kind| kind * kinds).collect()
+}
+
+/// Returns the largest distance (in pixels) at which each kind interacts with
+/// any kind, in a `kinds`x`kinds` table of `PairProps`.
+fn max_influences(pair_props: &[PairProps], kinds: usize) -> Vec<f32> {
+    pair_props
+        .chunks(kinds.max(1))
+        .map(|row| {
+            row.iter()
+                .map(|props| props.cutoff_sq)
+                .fold(0.0, f32::max)
+                .sqrt()
+        })
+        .collect()
+}
+
+/// A universe written out in full, so that it can be kept as a constant and
+/// recreated exactly with `Sim::from_def`, rather than generated from
+/// `Settings`. `Sim::to_rust_source` writes the current universe as one of
+/// these.
+///
+/// Like `Sim::pair_props`, the per-pair slices have an entry for every pair
+/// of kinds, one kind's row after another.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct UniverseDef<'a> {
+    /// The number of particles, which are placed randomly.
+    pub particles: usize,
+    pub friction: f32,
+    pub flat_force: bool,
+    pub wrap: bool,
+    /// The linear red, green and blue of each kind.
+    pub colors: &'a [[f32; 3]],
+    pub attractions: &'a [f32],
+    pub repel_distances: &'a [f32],
+    pub influence_radii: &'a [f32],
+    /// The `PairProps::outer_band` of each pair that has one, as the kinds in
+    /// the pair followed by the band.
+    pub outer_bands: &'a [(usize, usize, ForceBand)],
+}
+
+/// Assembles a universe piece by piece in code, as an alternative to
+/// generating one from `Settings` and then changing it.
+///
+/// To start with, every pair of kinds has no attraction, a repel distance of
+/// `DIAMETER` and an influence radius of 45 pixels; the kinds are colored as
+/// with `PaletteSpec::Full`; friction is 0.05; and there are no particles.
+///
+/// Mistakes such as referring to kinds that don't exist are reported by
+/// `build`, rather than by the methods which make them.
+#[derive(Debug, Clone)]
+pub struct UniverseBuilder {
+    colors: Vec<LinSrgb>,
+    attractions: Vec<f32>,
+    repel_distances: Vec<f32>,
+    influence_radii: Vec<f32>,
+    friction: f32,
+    flat_force: bool,
+    wrap: bool,
+    particles: Vec<Particle>,
+    /// The first mistake made, which `build` returns.
+    error: Option<SimError>,
+}
+
+impl UniverseBuilder {
+    /// Starts building a universe with `kinds` kinds of particle.
+    pub fn new(kinds: usize) -> Self {
+        Self {
+            colors: PaletteSpec::Full.colors(kinds),
+            attractions: vec![0.0; kinds * kinds],
+            repel_distances: vec![DIAMETER; kinds * kinds],
+            influence_radii: vec![45.0; kinds * kinds],
+            friction: 0.05,
+            flat_force: false,
+            wrap: false,
+            particles: Vec::new(),
+            error: None,
+        }
+    }
+
+    /// Records an error if `kind` doesn't exist, returning whether it does.
+    fn check_kind(&mut self, kind: usize) -> bool {
+        let kinds = self.colors.len();
+        if kind >= kinds {
+            self.error
+                .get_or_insert(SimError::InvalidKind { kind, kinds });
+        }
+        kind < kinds
+    }
+
+    /// Sets the color of `kind`.
+    pub fn color(mut self, kind: usize, color: LinSrgb) -> Self {
+        if self.check_kind(kind) {
+            self.colors[kind] = color;
+        }
+        self
+    }
+
+    /// Sets the colors of every kind at once.
+    pub fn colors(mut self, colors: Vec<LinSrgb>) -> Self {
+        if colors.len() == self.colors.len() {
+            self.colors = colors;
+        } else {
+            self.error.get_or_insert(SimError::WrongPaletteSize {
+                len: colors.len(),
+                expected: self.colors.len(),
+            });
+        }
+        self
+    }
+
+    /// Sets how strongly kind `a` is attracted to kind `b`. This only affects
+    /// that one direction.
+    pub fn attraction(mut self, a: usize, b: usize, attraction: f32) -> Self {
+        if self.check_kind(a) && self.check_kind(b) {
+            self.attractions[a * self.colors.len() + b] = attraction;
+        }
+        self
+    }
+
+    /// Sets the attractions between every pair of kinds at once, where
+    /// `matrix[a * kinds + b]` is how strongly kind `a` is attracted to kind
+    /// `b`.
+    pub fn attraction_matrix(mut self, matrix: Vec<f32>) -> Self {
+        if matrix.len() == self.attractions.len() {
+            self.attractions = matrix;
+        } else {
+            self.error.get_or_insert(SimError::WrongMatrixSize {
+                len: matrix.len(),
+                expected: self.attractions.len(),
+            });
+        }
+        self
+    }
+
+    /// Sets the repel distance and influence radius between kinds `a` and
+    /// `b`, in both directions.
+    pub fn radii(mut self, a: usize, b: usize, repel_distance: f32, influence_radius: f32) -> Self {
+        if self.check_kind(a) && self.check_kind(b) {
+            let kinds = self.colors.len();
+            for index in [a * kinds + b, b * kinds + a] {
+                self.repel_distances[index] = repel_distance;
+                self.influence_radii[index] = influence_radius;
+            }
+        }
+        self
+    }
+
+    pub fn friction(mut self, friction: f32) -> Self {
+        self.friction = friction;
+        self
+    }
+
+    pub fn flat_force(mut self, flat_force: bool) -> Self {
+        self.flat_force = flat_force;
+        self
+    }
+
+    pub fn wrap(mut self, wrap: bool) -> Self {
+        self.wrap = wrap;
+        self
+    }
+
+    /// Adds a particle of kind `kind` at rest at `pos`, in clip space.
+    pub fn particle(mut self, kind: usize, pos: Vec2) -> Self {
+        // Still add the particle if its kind is invalid, so that later particles' indices in
+        // `SimError::InvalidPosition` are right.
+        self.check_kind(kind);
+        self.particles.push(Particle {
+            pos,
+            kind,
+            ..Default::default()
+        });
+        self
+    }
+
+    /// Creates the universe, or returns the first mistake made while building
+    /// it.
+    ///
+    /// As with `Sim::new`, the particles are sorted by kind; see
+    /// `Sim::particle_index_map` to find where each one ended up.
+    pub fn build(self) -> Result<Sim, SimError> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        let kinds = self.colors.len();
+        if kinds == 0 {
+            return Err(SimError::NoKinds);
+        }
+        if self.particles.len() > MAX_PARTICLES {
+            return Err(SimError::TooManyParticles {
+                particles: self.particles.len(),
+                max: MAX_PARTICLES,
+            });
+        }
+        if let Some(particle) = self.particles.iter().position(|particle| {
+            !particle.pos.is_finite() || particle.pos.abs().max_element() > 1.0
+        }) {
+            return Err(SimError::InvalidPosition { particle });
+        }
+        let pair_props = checked_pair_props(
+            kinds,
+            &self.attractions,
+            &self.repel_distances,
+            &self.influence_radii,
+        )?;
+
+        let mut sim = Sim::from_parts(
+            self.colors,
+            pair_props,
+            self.particles,
+            self.friction,
+            self.flat_force,
+        );
+        sim.wrap = self.wrap;
+        Ok(sim)
+    }
+}
+
+/// Creates the `PairProps` for every pair of `kinds` kinds from their
+/// attractions, repel distances and influence radii, checking that there's one
+/// of each for every pair and that they're valid.
+fn checked_pair_props(
+    kinds: usize,
+    attractions: &[f32],
+    repel_distances: &[f32],
+    influence_radii: &[f32],
+) -> Result<Vec<PairProps>, SimError> {
+    for len in [
+        attractions.len(),
+        repel_distances.len(),
+        influence_radii.len(),
+    ] {
+        if len != kinds * kinds {
+            return Err(SimError::WrongMatrixSize {
+                len,
+                expected: kinds * kinds,
+            });
+        }
+    }
+    if !attractions.iter().all(|attraction| attraction.is_finite()) {
+        return Err(SimError::InvalidAttraction);
+    }
+    let valid_radius = |&radius: &f32| radius >= 0.0 && radius.is_finite();
+    if !repel_distances.iter().all(valid_radius) || !influence_radii.iter().all(valid_radius) {
+        return Err(SimError::InvalidRadius);
+    }
+
+    Ok((0..kinds * kinds)
+        .map(|i| PairProps::new(attractions[i], repel_distances[i], influence_radii[i]))
+        .collect())
+}
+
+/// The reasons a set of `Settings` can be rejected by `Sim::try_new`, an
+/// attraction matrix by `Sim::set_attraction_matrix`, a palette by
+/// `Sim::set_palette`, or a universe by `Sim::from_def` or
+/// `UniverseBuilder::build`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SimError {
+    /// `kinds` was 0, so there's nothing to pick colors or forces for.
+    NoKinds,
+    /// More particles were asked for than fit in the GPU's particle buffer.
+    TooManyParticles { particles: usize, max: usize },
+    /// The mean of `attraction_distr` was NaN or infinite, or its standard
+    /// deviation was negative; or an attraction in a matrix was NaN or
+    /// infinite.
+    InvalidAttraction,
+    /// A repel distance or influence radius was negative or infinite, or the
+    /// range one was picked from was empty.
+    InvalidRadius,
+    /// An attraction matrix didn't have an entry for every pair of kinds.
+    WrongMatrixSize { len: usize, expected: usize },
+    /// A palette didn't have a color for every kind.
+    WrongPaletteSize { len: usize, expected: usize },
+    /// A kind was referred to which doesn't exist.
+    InvalidKind { kind: usize, kinds: usize },
+    /// A particle was placed outside of the simulation, or at a NaN or
+    /// infinite position.
+    InvalidPosition { particle: usize },
+}
+
+impl fmt::Display for SimError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SimError::NoKinds => write!(f, "there must be at least one kind of particle"),
+            SimError::TooManyParticles { particles, max } => {
+                write!(
+                    f,
+                    "{particles} particles requested, but the maximum is {max}"
+                )
+            }
+            SimError::InvalidAttraction => write!(f, "invalid attraction distribution"),
+            SimError::InvalidRadius => {
+                write!(
+                    f,
+                    "repel distances and influence radii must be non-negative"
+                )
+            }
+            SimError::WrongMatrixSize { len, expected } => {
+                write!(
+                    f,
+                    "attraction matrix has {len} entries, but there should be {expected}"
+                )
+            }
+            SimError::WrongPaletteSize { len, expected } => {
+                write!(
+                    f,
+                    "palette has {len} colors, but there are {expected} kinds"
+                )
+            }
+            SimError::InvalidKind { kind, kinds } => {
+                write!(f, "there is no kind {kind}; there are only {kinds} kinds")
+            }
+            SimError::InvalidPosition { particle } => {
+                write!(f, "particle {particle} is outside of the simulation")
+            }
+        }
+    }
+}
+
+impl Error for SimError {}
+
+/// The reasons `Sim::load_attraction_csv` or `Sim::load_palette_csv` can fail.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub enum CsvError {
+    /// Reading the CSV failed.
+    Io(io::Error),
+    /// A cell wasn't a number. Rows and columns are counted from 0, ignoring
+    /// blank lines.
+    InvalidNumber { row: usize, column: usize },
+    /// A row didn't have one cell for each kind, or for each channel of a
+    /// color.
+    WrongRowLength {
+        row: usize,
+        len: usize,
+        expected: usize,
+    },
+    /// The matrix was rejected by `Sim::set_attraction_matrix`.
+    Matrix(SimError),
+    /// The palette was rejected by `Sim::set_palette`.
+    Palette(SimError),
+}
+
+#[cfg(feature = "std")]
+impl fmt::Display for CsvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CsvError::Io(e) => write!(f, "failed to read CSV: {e}"),
+            CsvError::InvalidNumber { row, column } => {
+                write!(f, "cell {column} of row {row} isn't a number")
+            }
+            CsvError::WrongRowLength { row, len, expected } => {
+                write!(
+                    f,
+                    "row {row} has {len} cells, but there should be {expected}"
+                )
+            }
+            CsvError::Matrix(e) => e.fmt(f),
+            CsvError::Palette(e) => e.fmt(f),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for CsvError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CsvError::Io(e) => Some(e),
+            CsvError::Matrix(e) => Some(e),
+            CsvError::Palette(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<io::Error> for CsvError {
+    fn from(e: io::Error) -> Self {
+        CsvError::Io(e)
+    }
+}
+
+/// Reads CSV where every row has `columns` numbers in it, returning all of
+/// them in order. Whitespace around cells and blank lines are ignored.
+#[cfg(feature = "std")]
+fn read_csv_rows(reader: impl Read, columns: usize) -> Result<Vec<f32>, CsvError> {
+    let mut cells = Vec::new();
+
+    let lines = BufReader::new(reader).lines();
+    let mut row = 0;
+    for line in lines {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let len_before = cells.len();
+        for (column, cell) in line.split(',').enumerate() {
+            let value = cell
+                .trim()
+                .parse()
+                .map_err(|_| CsvError::InvalidNumber { row, column })?;
+            cells.push(value);
+        }
+        let len = cells.len() - len_before;
+        if len != columns {
+            return Err(CsvError::WrongRowLength {
+                row,
+                len,
+                expected: columns,
+            });
+        }
+        row += 1;
+    }
+
+    Ok(cells)
+}
+
+/// The state required for the simulation of the particles.
+///
+/// The `Default` value is an empty universe, with no particles or kinds.
+#[derive(Clone, Default)]
+pub struct Sim {
+    pub wrap: bool,
+    /// The area particles are kept within when not wrapping.
+    pub arena: Arena,
+    pub flat_force: bool,
+    /// The shape of the attraction when `flat_force` is set.
+    pub flat_force_shape: FlatForce,
+    /// The fraction of each particle's velocity which is lost every step.
+    ///
+    /// See `set_friction` for what values outside of `0.0..=1.0` do.
+    pub friction: f32,
+    /// Whether to clamp the amount velocity is multiplied by each step to
+    /// `0.0..=1.0`, so that out-of-range frictions can't flip or amplify
+    /// velocities.
+    pub friction_clamped: bool,
+    /// The schedule `friction` is following, if any.
+    pub(crate) friction_schedule: Option<FrictionSchedule>,
+    /// How many steps `friction_schedule` has been running for.
+    pub(crate) friction_elapsed: f32,
+    /// How to position particles in `regenerate_particles`.
+    pub placement: Placement,
+    /// The region (in clip space) to place each kind of particle within in
+    /// `regenerate_particles`.
+    ///
+    /// Kinds without a region, or all kinds if this is `None`, are placed in
+    /// the middle of the simulation as usual.
+    pub kind_regions: Option<Vec<Rect>>,
+    /// The soft wall to push particles away from the edges with, if any.
+    ///
+    /// Particles still bounce off the edges if they make it through the soft
+    /// wall.
+    pub boundary_force: Option<BoundaryForce>,
+    /// How to move the particles in `step`.
+    pub integrator: Integrator,
+    /// Whether to subtract the particles' average velocity from each of them
+    /// every step, so that the system as a whole doesn't drift off in one
+    /// direction.
+    ///
+    /// Attractions aren't symmetric, so nothing else stops this from happening.
+    pub remove_net_drift: bool,
+    /// The rules `apply_life_rules` uses for particles being born and dying,
+    /// if any.
+    pub life_rules: Option<LifeRules>,
+    /// How the attraction matrix is evolving, if it is.
+    pub(crate) evolution: Option<Evolution>,
+    /// The schedule `apply_spawn_schedule` adds particles on, if any.
+    ///
+    /// Without one, every particle is present from the start; to start with
+    /// fewer, truncate `particles` when setting this.
+    pub spawn_schedule: Option<SpawnSchedule>,
+    /// The fraction of a particle `apply_spawn_schedule` has built up towards
+    /// adding.
+    pub(crate) spawn_progress: f32,
+    /// The squared distance (in pixels squared) below which particles stop
+    /// affecting each other.
+    ///
+    /// This stops `step` from dividing by zero when particles are on top of
+    /// each other, so it should always be positive. `Sim::new` sets it to
+    /// `DEFAULT_MIN_DISTANCE_SQ`, or distances under a tenth of a pixel.
+    pub min_distance_sq: f32,
+
+    pub colors: Vec<LinSrgb>,
+    /// How `export_particles` colors the particles.
+    pub color_mode: ColorMode,
+    pub pair_props: Vec<PairProps>,
+    /// The index of the first of each kind's `PairProps` in `pair_props`, so
+    /// that `step` doesn't have to multiply to find them.
+    ///
+    /// `kind_offset[k]` is always `k * colors.len()`.
+    pub kind_offset: Vec<usize>,
+    /// The result of `max_influence_for_kind` for each kind.
+    pub(crate) max_influence: Vec<f32>,
+
+    /// Whether to leave the particles in the order they were generated in
+    /// `regenerate_particles`, rather than sorting them by kind.
+    ///
+    /// Sorting makes `step` faster, but moves particles to different indices;
+    /// see `particle_index_map` for tracking them across the sort.
+    pub stable_order: bool,
+    pub(crate) index_map: Vec<usize>,
+    pub particles: Vec<Particle>,
+    /// The size of the simulation in pixels, as used by `step_default`, if
+    /// it's been set with `set_size`.
+    pub size: Option<Vec2>,
+
+    /// A buffer for `step` to store the acceleration of each particle in, so
+    /// that it doesn't have to be reallocated every step.
+    pub(crate) accelerations: Vec<Vec2>,
+}
+
+impl Sim {
+    /// Creates a new `Sim`, panicking if `settings` are invalid.
+    ///
+    /// See `try_new` for a non-panicking version.
+    pub fn new<R: Rng>(settings: Settings, rng: &mut R) -> Self {
+        Self::try_new(settings, rng).unwrap()
+    }
+
+    /// Creates a new `Sim` from `seed`, panicking if `settings` are invalid.
+    ///
+    /// The forces between kinds and the particles are generated from separate
+    /// streams derived from `seed` (see `seed_streams`), so the same settings
+    /// and seed always give the same universe.
+    ///
+    /// This is what seeds mean everywhere else too: the seed shown in the
+    /// window's title, `--seed` when running headless, and `PlSettings::seed`
+    /// all give the universe this does.
+    pub fn from_seed(settings: Settings, seed: u64) -> Self {
+        Self::try_from_seed(settings, seed).unwrap()
+    }
+
+    /// Like `from_seed`, but returns an error if `settings` are invalid.
+    pub fn try_from_seed(settings: Settings, seed: u64) -> Result<Self, SimError> {
+        let (forces_seed, particles_seed) = Self::seed_streams(seed);
+        Self::try_from_seeds(settings, forces_seed, particles_seed)
+    }
+
+    /// Like `from_seed`, but with the seeds of the two streams given directly,
+    /// panicking if `settings` are invalid.
+    ///
+    /// Changing only one of the seeds changes only what it generates: a new
+    /// `particles_seed` with the same `forces_seed` re-rolls the particles but
+    /// keeps `pair_props` the same, and vice versa.
+    pub fn from_seeds(settings: Settings, forces_seed: u64, particles_seed: u64) -> Self {
+        Self::try_from_seeds(settings, forces_seed, particles_seed).unwrap()
+    }
+
+    /// Like `from_seeds`, but returns an error if `settings` are invalid.
+    pub fn try_from_seeds(
+        settings: Settings,
+        forces_seed: u64,
+        particles_seed: u64,
+    ) -> Result<Self, SimError> {
+        let mut sim = Self::try_new(settings, &mut StdRng::seed_from_u64(forces_seed))?;
+        sim.regenerate_particles(&mut StdRng::seed_from_u64(particles_seed));
+        Ok(sim)
+    }
+
+    /// Derives the seeds `from_seed` generates the forces and particles from,
+    /// in that order, from `seed`.
+    pub fn seed_streams(seed: u64) -> (u64, u64) {
+        let mut rng = StdRng::seed_from_u64(seed);
+        (rng.gen(), rng.gen())
+    }
+
+    /// Returns the RNG `apply_life_rules` and `apply_spawn_schedule` should use
+    /// for a universe created from `seed`, so that it plays out the same way
+    /// every time.
+    ///
+    /// This is a separate stream from the ones `seed_streams` gives, so it
+    /// doesn't change the forces or particles.
+    pub fn step_rng(seed: u64) -> StdRng {
+        let mut rng = StdRng::seed_from_u64(seed);
+        // Skip past the forces and particles seeds.
+        let _: (u64, u64) = (rng.gen(), rng.gen());
+        StdRng::seed_from_u64(rng.gen())
+    }
+
+    /// Returns the RNG `from_seed` places the particles with, so that they can
+    /// be placed the same way again with `regenerate_particles`.
+    pub fn particles_rng(seed: u64) -> StdRng {
+        let (_, particles_seed) = Self::seed_streams(seed);
+        StdRng::seed_from_u64(particles_seed)
+    }
+
+    /// Creates a new `Sim`, returning an error if `settings` are invalid.
+    pub fn try_new<R: Rng>(settings: Settings, rng: &mut R) -> Result<Self, SimError> {
+        if settings.kinds == 0 {
+            return Err(SimError::NoKinds);
+        }
+
+        if settings.particles > MAX_PARTICLES {
+            return Err(SimError::TooManyParticles {
+                particles: settings.particles,
+                max: MAX_PARTICLES,
+            });
+        }
+
+        // `Normal::new` only rejects infinite standard deviations, not negative ones.
+        let attraction_distr = settings.attraction_distr;
+        if !attraction_distr.mean().is_finite() || attraction_distr.std_dev() < 0.0 {
+            return Err(SimError::InvalidAttraction);
+        }
+        let valid_range = |range: &RangeInclusive<f32>| {
+            range.start().is_finite()
+                && range.end().is_finite()
+                && 0.0 <= *range.start()
+                && range.start() <= range.end()
+        };
+        if !valid_range(&settings.repel_distance_range)
+            || !valid_range(&settings.influence_radius_range)
+        {
+            return Err(SimError::InvalidRadius);
+        }
+        let repel_distance_distr = Uniform::new_inclusive(
+            settings.repel_distance_range.start(),
+            settings.repel_distance_range.end(),
+        );
+        let influence_radius_distr = Uniform::new_inclusive(
+            settings.influence_radius_range.start(),
+            settings.influence_radius_range.end(),
+        );
+
+        let colors = settings.palette.colors(settings.kinds);
+        let mut pair_props: Vec<PairProps> = Vec::with_capacity(settings.kinds * settings.kinds);
+
+        for i in 0..settings.kinds {
+            for j in 0..settings.kinds {
+                let attraction = match settings.matrix_kind {
+                    MatrixKind::Random => {
+                        if i == j {
+                            -f32::abs(settings.attraction_distr.sample(rng))
+                        } else {
+                            settings.attraction_distr.sample(rng)
+                        }
+                    }
+                    MatrixKind::Chase => {
+                        let magnitude = f32::abs(settings.attraction_distr.sample(rng));
+                        if i == j {
+                            -magnitude
+                        } else if j == (i + 1) % settings.kinds {
+                            magnitude
+                        } else if j == (i + settings.kinds - 1) % settings.kinds {
+                            -magnitude
+                        } else {
+                            0.0
+                        }
+                    }
+                    MatrixKind::Snake => {
+                        let magnitude = f32::abs(settings.attraction_distr.sample(rng));
+                        if i == j || j == (i + 1) % settings.kinds {
+                            magnitude
+                        } else {
+                            0.0
+                        }
+                    }
+                };
+                let attraction = if settings.invert_attraction {
+                    -attraction
+                } else {
+                    attraction
+                };
+
+                let (repel_distance, influence_radius) = if j < i {
+                    // We've already generated this one (apart from attraction),
+                    // so re-use that to make it symmetrical.
+                    let props = pair_props[j * settings.kinds + i];
+                    (props.repel_distance, props.influence_radius)
+                } else {
+                    let repel_distance = if i == j {
+                        DIAMETER
+                    } else {
+                        f32::max(repel_distance_distr.sample(rng), DIAMETER)
+                    };
+
+                    let mut influence_radius = influence_radius_distr.sample(rng);
+                    if influence_radius < repel_distance {
+                        influence_radius = repel_distance;
+                    }
+
+                    (repel_distance, influence_radius)
+                };
+                pair_props.push(PairProps::new(attraction, repel_distance, influence_radius));
+            }
+        }
+
+        let particles: Vec<_> = (0..settings.particles)
+            .map(|_| Particle::generate(settings.kinds, rng))
+            .collect();
+
+        Ok(Self::from_parts(
+            colors,
+            pair_props,
+            particles,
+            settings.friction,
+            settings.flat_force,
+        ))
+    }
+
+    /// Recreates the universe described by `def`, placing its particles
+    /// randomly.
+    ///
+    /// Unlike with `try_new`, repel distances and influence radii are used
+    /// exactly as given, apart from being rejected if they're negative.
+    pub fn from_def<R: Rng>(def: &UniverseDef, rng: &mut R) -> Result<Self, SimError> {
+        let kinds = def.colors.len();
+        if kinds == 0 {
+            return Err(SimError::NoKinds);
+        }
+        if def.particles > MAX_PARTICLES {
+            return Err(SimError::TooManyParticles {
+                particles: def.particles,
+                max: MAX_PARTICLES,
+            });
+        }
+        let pair_props = checked_pair_props(
+            kinds,
+            def.attractions,
+            def.repel_distances,
+            def.influence_radii,
+        )?;
+        if let Some(&(a, b, _)) = def
+            .outer_bands
+            .iter()
+            .find(|&&(a, b, _)| a >= kinds || b >= kinds)
+        {
+            return Err(SimError::InvalidKind {
+                kind: a.max(b),
+                kinds,
+            });
+        }
+
+        let colors = def
+            .colors
+            .iter()
+            .map(|&[red, green, blue]| LinSrgb::new(red, green, blue))
+            .collect();
+        let particles = (0..def.particles)
+            .map(|_| Particle::generate(kinds, rng))
+            .collect();
+
+        let mut sim = Self::from_parts(colors, pair_props, particles, def.friction, def.flat_force);
+        sim.wrap = def.wrap;
+        for &(a, b, band) in def.outer_bands {
+            sim.set_outer_band(a, b, Some(band));
+        }
+        Ok(sim)
+    }
+
+    /// Creates a `Sim` with everything apart from what's given set to its
+    /// default, with the particles sorted by kind.
+    fn from_parts(
+        colors: Vec<LinSrgb>,
+        pair_props: Vec<PairProps>,
+        particles: Vec<Particle>,
+        friction: f32,
+        flat_force: bool,
+    ) -> Self {
+        let kinds = colors.len();
+        let mut sim = Self {
+            wrap: false,
+            arena: Arena::Rect,
+            flat_force,
+            flat_force_shape: FlatForce::Hard,
+            friction,
+            friction_clamped: false,
+            friction_schedule: None,
+            friction_elapsed: 0.0,
+            placement: Placement::Random,
+            kind_regions: None,
+            boundary_force: None,
+            integrator: Integrator::SemiImplicitEuler,
+            remove_net_drift: false,
+            life_rules: None,
+            evolution: None,
+            spawn_schedule: None,
+            spawn_progress: 0.0,
+            min_distance_sq: DEFAULT_MIN_DISTANCE_SQ,
+
+            colors,
+            color_mode: ColorMode::ByKind,
+            max_influence: max_influences(&pair_props, kinds),
+            pair_props,
+            kind_offset: kind_offsets(kinds),
+
+            stable_order: false,
+            index_map: Vec::new(),
+            particles,
+            size: None,
+
+            accelerations: Vec::new(),
+        };
+        sim.sort_by_kind();
+
+        sim
+    }
+
+    /// Copies over every option from `old` which isn't part of the universe
+    /// itself, for when a new universe replaces it: everything apart from the
+    /// kinds and the forces between them, the friction and `flat_force`
+    /// (which come from `Settings`), and the particles.
+    ///
+    /// Any friction schedule starts again from the beginning, and an evolving
+    /// matrix carries on evolving within the new matrix's range of
+    /// attractions.
+    pub fn carry_over_options_from(&mut self, old: &Sim) {
+        // This deliberately lists every field, so that new ones have to be handled here.
+        let Sim {
+            wrap,
+            arena,
+            flat_force: _,
+            flat_force_shape,
+            friction: _,
+            friction_clamped,
+            friction_schedule,
+            friction_elapsed: _,
+            placement,
+            kind_regions,
+            boundary_force,
+            integrator,
+            remove_net_drift,
+            life_rules,
+            evolution,
+            spawn_schedule,
+            spawn_progress: _,
+            min_distance_sq,
+            colors: _,
+            color_mode,
+            pair_props: _,
+            kind_offset: _,
+            max_influence: _,
+            stable_order,
+            index_map: _,
+            particles: _,
+            size,
+            accelerations: _,
+        } = old;
+
+        self.wrap = *wrap;
+        self.arena = *arena;
+        self.flat_force_shape = *flat_force_shape;
+        self.friction_clamped = *friction_clamped;
+        self.set_friction_schedule(*friction_schedule);
+        self.placement = *placement;
+        self.kind_regions = kind_regions.clone();
+        self.boundary_force = *boundary_force;
+        self.integrator = *integrator;
+        self.remove_net_drift = *remove_net_drift;
+        self.life_rules = life_rules.clone();
+        self.evolution = evolution.as_ref().map(|evolution| {
+            Evolution::new(evolution.config, evolution.rng.clone(), &self.pair_props)
+        });
+        self.spawn_schedule = spawn_schedule.clone();
+        self.min_distance_sq = *min_distance_sq;
+        self.color_mode = *color_mode;
+        self.stable_order = *stable_order;
+        self.size = *size;
+    }
+
+    pub fn regenerate_particles<R: Rng>(&mut self, rng: &mut R) {
+        for particle in self.particles.iter_mut() {
+            *particle = Particle::generate(self.colors.len(), rng);
+        }
+
+        if self.placement == Placement::LowDiscrepancy {
+            // Shift the whole sequence by a random amount (wrapping around), so that we don't
+            // get the exact same positions every time.
+            let offset = vec2(rng.gen(), rng.gen());
+            for (i, particle) in self.particles.iter_mut().enumerate() {
+                // Skip the first element, since it's 0 for every base.
+                let point = vec2(halton(i + 1, 2), halton(i + 1, 3));
+                // Cover the same area as `Particle::generate`.
+                particle.pos = (point + offset).fract() - 0.5;
+            }
+        }
+
+        if let Some(regions) = &self.kind_regions {
+            for particle in self.particles.iter_mut() {
+                if let Some(region) = regions.get(particle.kind) {
+                    // Stretch the usual placement area (-0.5 to 0.5) over the region, so that
+                    // `placement` still applies within it.
+                    particle.pos = region.min + (particle.pos + 0.5) * (region.max - region.min);
+                }
+            }
+        }
+
+        self.sort_by_kind();
+    }
+
+    /// Sorts the particles by kind, so that the `PairProps` used by
+    /// neighbouring particles in `step` are more likely to be in cache.
+    ///
+    /// Does nothing apart from resetting `particle_index_map` if
+    /// `stable_order` is set.
+    fn sort_by_kind(&mut self) {
+        // `order[new] = old`. This needs to be a stable sort so that particles of the same kind
+        // stay in the same order.
+        let mut order: Vec<usize> = (0..self.particles.len()).collect();
+        if !self.stable_order {
+            order.sort_by_key(|&i| self.particles[i].kind);
+        }
+
+        self.particles = order.iter().map(|&i| self.particles[i]).collect();
+
+        self.index_map = vec![0; order.len()];
+        for (new, &old) in order.iter().enumerate() {
+            self.index_map[old] = new;
+        }
+    }
+
+    /// Returns the permutation applied to the particles the last time they
+    /// were sorted by kind (on creation or in `regenerate_particles`).
+    ///
+    /// The particle which was at index `i` before the sort is now at
+    /// `particle_index_map()[i]`. This is the identity if `stable_order` is
+    /// set.
+    pub fn particle_index_map(&self) -> &[usize] {
+        &self.index_map
+    }
+
+    /// Returns how many particles there are of each kind.
+    ///
+    /// This is a single pass over the particles rather than a search for the
+    /// boundaries between kinds, since they aren't sorted when `stable_order`
+    /// is set or after `apply_life_rules` and `apply_spawn_schedule` add
+    /// particles.
+    pub fn kind_counts(&self) -> Vec<usize> {
+        let mut counts = vec![0; self.colors.len()];
+        for particle in &self.particles {
+            counts[particle.kind] += 1;
+        }
+        counts
+    }
+
+    /// Sets the influence radius between kinds `a` and `b`, in both
+    /// directions.
+    ///
+    /// Setting it to the pair's repel distance disables attraction between
+    /// them entirely, leaving only the short-range repulsion.
+    pub fn set_influence_radius(&mut self, a: usize, b: usize, influence_radius: f32) {
+        let kinds = self.colors.len();
+        for index in [a * kinds + b, b * kinds + a] {
+            let props = &mut self.pair_props[index];
+            debug_assert!(
+                influence_radius >= props.repel_distance,
+                "influence radius must be at least the repel distance"
+            );
+            *props = PairProps {
+                outer_band: props.outer_band,
+                ..PairProps::new(props.attraction, props.repel_distance, influence_radius)
+            };
+        }
+        self.update_cutoff(a, b);
+    }
+
+    /// Sets the extra band of force kind `a` feels from kind `b`, or removes
+    /// it if `band` is `None`.
+    ///
+    /// Unlike the rest of `PairProps`, this only applies in one direction.
+    pub fn set_outer_band(&mut self, a: usize, b: usize, band: Option<ForceBand>) {
+        self.pair_props[a * self.colors.len() + b].outer_band = band;
+        self.update_cutoff(a, b);
+    }
+
+    /// Updates `cutoff_sq` for both directions of the pair of kinds `a` and
+    /// `b`, since `step` only checks one of them.
+    fn update_cutoff(&mut self, a: usize, b: usize) {
+        let kinds = self.colors.len();
+        let cutoff_sq = f32::max(
+            self.pair_props[a * kinds + b].own_cutoff_sq(),
+            self.pair_props[b * kinds + a].own_cutoff_sq(),
+        );
+        self.pair_props[a * kinds + b].cutoff_sq = cutoff_sq;
+        self.pair_props[b * kinds + a].cutoff_sq = cutoff_sq;
+        self.update_max_influence();
+    }
+
+    /// Returns the largest distance (in pixels) at which particles of kind `k`
+    /// interact with particles of any kind, in either direction, including
+    /// through `PairProps::outer_band`.
+    ///
+    /// Particles of kind `k` can be skipped by anything further away than this
+    /// from them, such as a neighbour search.
+    pub fn max_influence_for_kind(&self, k: usize) -> f32 {
+        self.max_influence[k]
+    }
+
+    /// Recomputes `max_influence_for_kind` for every kind. This only needs to
+    /// be called after changing the radii in `pair_props` directly, rather
+    /// than through methods like `set_influence_radius`.
+    pub fn update_max_influence(&mut self) {
+        self.max_influence = max_influences(&self.pair_props, self.colors.len());
+    }
+
+    /// Replaces the attractions between every pair of kinds, where
+    /// `matrix[a * kinds + b]` is how strongly kind `a` is attracted to kind
+    /// `b`.
+    ///
+    /// Unlike generated attractions, these can be as strong as you like. Repel
+    /// distances and influence radii are left as they are. If `matrix` is the
+    /// wrong size or contains NaN or infinite values, nothing is changed.
+    pub fn set_attraction_matrix(&mut self, matrix: &[f32]) -> Result<(), SimError> {
+        if matrix.len() != self.pair_props.len() {
+            return Err(SimError::WrongMatrixSize {
+                len: matrix.len(),
+                expected: self.pair_props.len(),
+            });
+        }
+        if !matrix.iter().all(|attraction| attraction.is_finite()) {
+            return Err(SimError::InvalidAttraction);
+        }
+
+        for (props, &attraction) in self.pair_props.iter_mut().zip(matrix) {
+            props.attraction = attraction;
+        }
+        Ok(())
+    }
+
+    /// Replaces the attractions between every pair of kinds with a matrix read
+    /// from CSV, with a row for each kind and a column for each kind it's
+    /// attracted to, as written by `write_attraction_csv`.
+    ///
+    /// Whitespace around cells and blank lines are ignored. As with
+    /// `set_attraction_matrix`, nothing is changed if the matrix is invalid.
+    #[cfg(feature = "std")]
+    pub fn load_attraction_csv(&mut self, reader: impl Read) -> Result<(), CsvError> {
+        let matrix = read_csv_rows(reader, self.colors.len())?;
+        self.set_attraction_matrix(&matrix)
+            .map_err(CsvError::Matrix)
+    }
+
+    /// Writes the attractions between every pair of kinds as CSV, in the
+    /// format `load_attraction_csv` reads.
+    #[cfg(feature = "std")]
+    pub fn write_attraction_csv(&self, mut writer: impl Write) -> io::Result<()> {
+        for row in self.pair_props.chunks(self.colors.len()) {
+            let cells: Vec<String> = row
+                .iter()
+                .map(|props| props.attraction.to_string())
+                .collect();
+            writeln!(writer, "{}", cells.join(","))?;
+        }
+        Ok(())
+    }
+
+    /// Returns Rust source code for a `UniverseDef` expression describing this
+    /// universe, which can be pasted into a program (for example as a `const`,
+    /// with `UniverseDef` and `ForceBand` in scope) and passed to `from_def`
+    /// to get the same forces and colors back.
+    ///
+    /// Numbers are written with as many digits as it takes to get exactly the
+    /// same values back.
+    pub fn to_rust_source(&self) -> String {
+        let kinds = self.colors.len();
+        // `Debug` always includes a decimal point or exponent, so these are always float literals.
+        let row = |values: &[f32]| {
+            let values: Vec<String> = values.iter().map(|value| format!("{value:?}")).collect();
+            values.join(", ")
+        };
+        let matrix = |value: fn(&PairProps) -> f32| {
+            let rows: String = self
+                .pair_props
+                .chunks(kinds)
+                .map(|props| {
+                    let values: Vec<f32> = props.iter().map(value).collect();
+                    format!("        {},\n", row(&values))
+                })
+                .collect();
+            format!("&[\n{rows}    ]")
+        };
+
+        let colors: String = self
+            .colors
+            .iter()
+            .map(|color| {
+                format!(
+                    "        [{}],\n",
+                    row(&[color.red, color.green, color.blue])
+                )
+            })
+            .collect();
+        let outer_bands: String = self
+            .pair_props
+            .iter()
+            .enumerate()
+            .filter_map(|(i, props)| {
+                let band = props.outer_band?;
+                Some(format!(
+                    "        ({}, {}, ForceBand {{ attraction: {:?}, start: {:?}, end: {:?} }}),\n",
+                    i / kinds,
+                    i % kinds,
+                    band.attraction,
+                    band.start,
+                    band.end,
+                ))
+            })
+            .collect();
+
+        format!(
+            "UniverseDef {{
+    particles: {},
+    friction: {:?},
+    flat_force: {},
+    wrap: {},
+    colors: &[\n{colors}    ],
+    attractions: {},
+    repel_distances: {},
+    influence_radii: {},
+    outer_bands: &[\n{outer_bands}    ],
+}}",
+            self.particles.len(),
+            self.friction,
+            self.flat_force,
+            self.wrap,
+            matrix(|props| props.attraction),
+            matrix(|props| props.repel_distance),
+            matrix(|props| props.influence_radius),
+        )
+    }
+
+    /// Relabels each kind `k` as kind `permutation[k]`, which must contain
+    /// every kind exactly once.
+    ///
+    /// The attractions, particles, life rules, spawn weights and evolving pair
+    /// are all moved over to the new labels, so the simulation behaves exactly
+    /// as before, but `colors`
+    /// (along with `kind_regions`) stays where it is, so each kind is drawn in
+    /// a different color.
+    pub fn permute_kinds(&mut self, permutation: &[usize]) {
+        let kinds = self.colors.len();
+        assert_eq!(permutation.len(), kinds, "permutation has the wrong length");
+        debug_assert!(
+            (0..kinds).all(|k| permutation.contains(&k)),
+            "not a permutation: {permutation:?}"
+        );
+
+        let old_props = self.pair_props.clone();
+        for a in 0..kinds {
+            for b in 0..kinds {
+                self.pair_props[permutation[a] * kinds + permutation[b]] = old_props[a * kinds + b];
+            }
+        }
+        self.update_max_influence();
+
+        for particle in self.particles.iter_mut() {
+            particle.kind = permutation[particle.kind];
+        }
+
+        if let Some(rules) = &mut self.life_rules {
+            rules.birth_neighbours = permute_per_kind(&rules.birth_neighbours, permutation);
+            rules.death_steps = permute_per_kind(&rules.death_steps, permutation);
+        }
+
+        if let Some(SpawnSchedule {
+            kind_weights: Some(weights),
+            ..
+        }) = &mut self.spawn_schedule
+        {
+            // Weights with the wrong length are ignored, so leave them that way
+            // rather than padding them out.
+            if weights.len() == kinds {
+                *weights = permute_per_kind(weights, permutation);
+            }
+        }
+
+        if let Some(Evolution {
+            target: Some((index, _)),
+            ..
+        }) = &mut self.evolution
+        {
+            *index = permutation[*index / kinds] * kinds + permutation[*index % kinds];
+        }
+    }
+
+    /// Relabels the kinds according to a random permutation; see
+    /// `permute_kinds`.
+    pub fn shuffle_kinds<R: Rng>(&mut self, rng: &mut R) {
+        let mut permutation: Vec<usize> = (0..self.colors.len()).collect();
+        permutation.shuffle(rng);
+        self.permute_kinds(&permutation);
+    }
+
+    /// Replaces the colors of each kind with ones picked according to `spec`.
+    pub fn set_palette_spec(&mut self, spec: &PaletteSpec) {
+        self.colors = spec.colors(self.colors.len());
+    }
+
+    /// Returns the color of each kind.
+    pub fn palette(&self) -> &[LinSrgb] {
+        &self.colors
+    }
+
+    /// Replaces the color of each kind, leaving the forces between them alone.
+    ///
+    /// If `palette` doesn't have exactly one color for each kind, nothing is
+    /// changed.
+    pub fn set_palette(&mut self, palette: Vec<LinSrgb>) -> Result<(), SimError> {
+        if palette.len() != self.colors.len() {
+            return Err(SimError::WrongPaletteSize {
+                len: palette.len(),
+                expected: self.colors.len(),
+            });
+        }
+        self.colors = palette;
+        Ok(())
+    }
+
+    /// Replaces the color of each kind with a palette read from CSV, with a
+    /// row for each kind containing its linear red, green and blue, as written
+    /// by `write_palette_csv`.
+    ///
+    /// This is separate from the attraction matrix, so that a palette saved
+    /// from one universe can be loaded into any other with the same number of
+    /// kinds. As with `set_palette`, nothing is changed if there's the wrong
+    /// number of colors.
+    #[cfg(feature = "std")]
+    pub fn load_palette_csv(&mut self, reader: impl Read) -> Result<(), CsvError> {
+        let channels = read_csv_rows(reader, 3)?;
+        let palette = channels
+            .chunks_exact(3)
+            .map(|rgb| LinSrgb::new(rgb[0], rgb[1], rgb[2]))
+            .collect();
+        self.set_palette(palette).map_err(CsvError::Palette)
+    }
+
+    /// Writes the color of each kind as CSV, in the format `load_palette_csv`
+    /// reads.
+    #[cfg(feature = "std")]
+    pub fn write_palette_csv(&self, mut writer: impl Write) -> io::Result<()> {
+        for color in &self.colors {
+            writeln!(writer, "{},{},{}", color.red, color.green, color.blue)?;
+        }
+        Ok(())
+    }
+
+    /// Holds the particle at `index` in place, so that it still exerts forces
+    /// on other particles but doesn't move.
+    pub fn pin(&mut self, index: usize) {
+        self.particles[index].pinned = true;
+        self.particles[index].vel = Vec2::ZERO;
+    }
+
+    /// Lets the particle at `index` move again after `pin`.
+    pub fn unpin(&mut self, index: usize) {
+        self.particles[index].pinned = false;
+    }
+
+    /// Returns the index of the particle closest to `pos` (in clip space), or
+    /// `None` if there are no particles.
+    pub fn nearest_particle(&self, pos: Vec2) -> Option<usize> {
+        self.particles
+            .iter()
+            .map(|p| {
+                let mut delta = p.pos - pos;
+                if self.wrap {
+                    delta.x += -2.0 * f32::floor(0.5 * delta.x + 0.5);
+                    delta.y += -2.0 * f32::floor(0.5 * delta.y + 0.5);
+                }
+                delta.length_squared()
+            })
+            .enumerate()
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(index, _)| index)
+    }
+
+    /// Returns the bottom-left and top-right corners (in clip space) of the
+    /// smallest rectangle containing every particle.
+    ///
+    /// If there aren't any particles, or the simulation wraps around (so that
+    /// there's no single smallest rectangle), this is the whole simulation.
+    pub fn bounding_box(&self) -> (Vec2, Vec2) {
+        if self.wrap || self.particles.is_empty() {
+            return (vec2(-1.0, -1.0), vec2(1.0, 1.0));
+        }
+
+        self.particles.iter().fold(
+            (Vec2::splat(f32::INFINITY), Vec2::splat(f32::NEG_INFINITY)),
+            |(min, max), p| (min.min(p.pos), max.max(p.pos)),
+        )
+    }
+
+    /// Returns the indices of the particles within `radius` (in clip space) of
+    /// the particle at `index`, nearest first.
+    ///
+    /// The particle itself isn't included.
+    pub fn neighbors(&self, index: usize, radius: f32) -> Vec<usize> {
+        let pos = self.particles[index].pos;
+        let radius_sq = radius * radius;
+
+        let mut neighbors: Vec<(usize, f32)> = self
+            .particles
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| i != index)
+            .map(|(i, p)| {
+                let mut delta = p.pos - pos;
+                if self.wrap {
+                    delta.x += -2.0 * f32::floor(0.5 * delta.x + 0.5);
+                    delta.y += -2.0 * f32::floor(0.5 * delta.y + 0.5);
+                }
+                (i, delta.length_squared())
+            })
+            .filter(|&(_, dist_sq)| dist_sq <= radius_sq)
+            .collect();
+
+        neighbors.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+        neighbors.into_iter().map(|(i, _)| i).collect()
+    }
+
+    /// Kicks every particle within `radius` pixels of `center` (in clip
+    /// space) directly away from it, in a simulation `width`x`height` pixels
+    /// in size.
+    ///
+    /// Particles right at `center` get a kick of `strength` pixels per step,
+    /// falling off linearly to nothing at `radius`. A negative `strength`
+    /// pulls particles in instead. Pinned particles aren't affected.
+    pub fn shockwave(&mut self, center: Vec2, radius: f32, strength: f32, width: f32, height: f32) {
+        let scale = 0.5 * vec2(width, height);
+        for p in self.particles.iter_mut().filter(|p| !p.pinned) {
+            let mut delta = p.pos - center;
+            if self.wrap {
+                delta.x += -2.0 * f32::floor(0.5 * delta.x + 0.5);
+                delta.y += -2.0 * f32::floor(0.5 * delta.y + 0.5);
+            }
+            let delta = delta * scale;
+
+            let dist = delta.length();
+            if dist < radius {
+                p.vel += strength * (1.0 - dist / radius) * delta.normalize_or_zero();
+            }
+        }
+    }
+
+    /// Returns how far `particle` moves in clip space each step, in a
+    /// simulation `width`x`height` pixels in size.
+    ///
+    /// `Particle::vel` is in pixels per step; see the module docs.
+    pub fn clip_velocity(&self, particle: &Particle, width: f32, height: f32) -> Vec2 {
+        pixels_to_clip(particle.vel, width, height)
+    }
+
+    /// Negates the velocities of all the particles.
+    ///
+    /// Without friction, this makes the simulation run backwards towards its
+    /// previous states (give or take floating-point error and wall bounces).
+    pub fn reverse(&mut self) {
+        for particle in self.particles.iter_mut() {
+            particle.vel = -particle.vel;
+        }
+    }
+
+    /// Sets the friction of the simulation.
+    ///
+    /// Every step, velocities are multiplied by `1.0 - friction` (or, for a
+    /// step of length `dt`, `1.0 - friction` to the power of `dt`), so:
+    /// - `0.0..=1.0` slows particles down, with `1.0` stopping them dead.
+    /// - Negative frictions speed particles up, injecting energy into the
+    ///   system.
+    /// - Frictions above `1.0` reverse particles' velocities every step, and
+    ///   amplify them above `2.0`.
+    ///
+    /// The latter two can be interesting, but tend to blow up; set
+    /// `friction_clamped` to prevent that.
+    pub fn set_friction(&mut self, friction: f32) {
+        self.friction = friction;
+    }
+
+    /// Returns the total kinetic energy of the particles, with each particle
+    /// having a unit mass and velocities in pixels per step.
+    pub fn kinetic_energy(&self) -> f32 {
+        self.particles
+            .iter()
+            .map(|p| 0.5 * p.vel.length_squared())
+            .sum()
+    }
+
+    /// Returns the total potential energy of the particles, in a simulation
+    /// `width`x`height` pixels in size, in the same units as `kinetic_energy`.
+    ///
+    /// Each pair of particles contributes the average of the potentials they
+    /// feel from each other (see `PairProps::potential`). Their sum with the
+    /// kinetic energy is only conserved when friction is 0, the attractions
+    /// between each pair of kinds are the same in both directions, and nothing
+    /// else pushes the particles around, like the edges or `boundary_force`.
+    pub fn potential_energy(&self, width: f32, height: f32) -> f32 {
+        let scale = 0.5 * vec2(width, height);
+        let flat_force = self.flat_force.then_some(self.flat_force_shape);
+
+        let mut energy = 0.0;
+        for i in 0..self.particles.len() {
+            let p = self.particles[i];
+            for j in i + 1..self.particles.len() {
+                let q = self.particles[j];
+                let props = &self.pair_props[self.kind_offset[p.kind] + q.kind];
+
+                let mut delta = q.pos - p.pos;
+                if self.wrap {
+                    delta.x += -2.0 * f32::floor(0.5 * delta.x + 0.5);
+                    delta.y += -2.0 * f32::floor(0.5 * delta.y + 0.5);
+                }
+                let dist2 = (delta * scale).length_squared();
+                if dist2 >= props.cutoff_sq {
+                    continue;
+                }
+
+                // The force stops below the minimum distance, so the potential stays flat.
+                let dist = dist2.max(self.min_distance_sq).sqrt();
+                let q_props = &self.pair_props[self.kind_offset[q.kind] + p.kind];
+                energy +=
+                    0.5 * (props.potential(dist, flat_force) + q_props.potential(dist, flat_force));
+            }
+        }
+        energy
+    }
+
+    /// Returns the force `step` would apply to a particle of kind `a` because
+    /// of a particle of kind `b` which is `dist` pixels away from it.
+    ///
+    /// Positive forces pull the particle towards the other one, and negative
+    /// forces push it away.
+    pub fn force_between(&self, a: usize, b: usize, dist: f32) -> f32 {
+        let props = self.pair_props[a * self.colors.len() + b];
+        let PairProps {
+            attraction,
+            repel_distance,
+            influence_radius,
+            cutoff_sq,
+            peak,
+            inv_base,
+            ..
+        } = props;
+
+        let dist2 = dist * dist;
+        if dist2 >= cutoff_sq || dist2 < self.min_distance_sq {
+            return 0.0;
+        }
+
+        let force = if dist < repel_distance {
+            R_SMOOTH
+                * repel_distance
+                * (1.0 / (repel_distance + R_SMOOTH) - 1.0 / (dist + R_SMOOTH))
+        } else if dist >= influence_radius {
+            0.0
+        } else if self.flat_force {
+            attraction * self.flat_force_shape.coefficient(&props, dist)
+        } else {
+            attraction * (1.0 - (f32::abs(dist - peak) * inv_base))
+        };
+
+        match props.outer_band {
+            Some(band) => force + band.force(dist),
+            None => force,
+        }
+    }
+
+    /// Samples the net force a particle of kind `probe` would feel at each
+    /// point of a `cols`x`rows` grid covering the simulation.
+    ///
+    /// The forces are returned in row-major order starting from the
+    /// bottom-left corner, sampled at the centers of each grid cell.
+    pub fn sample_force_field(
+        &self,
+        probe: usize,
+        cols: usize,
+        rows: usize,
+        width: f32,
+        height: f32,
+    ) -> Vec<Vec2> {
+        let scale = 0.5 * vec2(width, height);
+        let cell_size = vec2(2.0 / cols as f32, 2.0 / rows as f32);
+
+        let mut field = Vec::with_capacity(cols * rows);
+        for row in 0..rows {
+            for col in 0..cols {
+                let point = vec2(-1.0, -1.0) + cell_size * vec2(col as f32 + 0.5, row as f32 + 0.5);
+
+                let mut force = Vec2::ZERO;
+                for particle in self.particles.iter() {
+                    let mut delta = particle.pos - point;
+                    if self.wrap {
+                        delta.x += -2.0 * f32::floor(0.5 * delta.x + 0.5);
+                        delta.y += -2.0 * f32::floor(0.5 * delta.y + 0.5);
+                    }
+                    delta *= scale;
+
+                    let dist = delta.length();
+                    let f = self.force_between(probe, particle.kind, dist);
+                    if f != 0.0 {
+                        force += f * delta / dist;
+                    }
+                }
+
+                field.push(force);
+            }
+        }
+
+        field
+    }
+
+    /// Returns whether particles `i` and `j` are bonded: close enough to affect
+    /// each other and attracted to each other in both directions.
+    ///
+    /// `scale` converts from clip space to pixels.
+    fn bonded(&self, i: usize, j: usize, scale: Vec2) -> bool {
+        let p = self.particles[i];
+        let q = self.particles[j];
+
+        let to_q = self.pair_props[self.kind_offset[p.kind] + q.kind];
+        let to_p = self.pair_props[self.kind_offset[q.kind] + p.kind];
+        if to_q.attraction <= 0.0 || to_p.attraction <= 0.0 {
+            return false;
+        }
+
+        let mut delta = q.pos - p.pos;
+        if self.wrap {
+            delta.x += -2.0 * f32::floor(0.5 * delta.x + 0.5);
+            delta.y += -2.0 * f32::floor(0.5 * delta.y + 0.5);
+        }
+
+        // `influence_radius` is the same in both directions.
+        (delta * scale).length_squared() < to_q.influence_radius_sq
+    }
+
+    /// Returns the pairs of particles which are bonded - close enough to
+    /// affect each other and attracted to each other in both directions - in a
+    /// simulation `width`x`height` pixels in size.
+    ///
+    /// At most `max_bonds` bonds are returned.
+    pub fn bonds(&self, width: f32, height: f32, max_bonds: usize) -> Vec<(usize, usize)> {
+        let scale = 0.5 * vec2(width, height);
+
+        let mut bonds = Vec::new();
+        for i in 0..self.particles.len() {
+            for j in i + 1..self.particles.len() {
+                if bonds.len() == max_bonds {
+                    return bonds;
+                }
+                if self.bonded(i, j, scale) {
+                    bonds.push((i, j));
+                }
+            }
+        }
+        bonds
+    }
+
+    /// Returns the number of clusters of particles, in a simulation
+    /// `width`x`height` pixels in size.
+    ///
+    /// A cluster is a group of at least two particles joined together by
+    /// bonds (see `bonds`); particles without any bonds don't count.
+    pub fn count_clusters(&self, width: f32, height: f32) -> usize {
+        // A union-find forest, where each particle points towards the root of its cluster.
+        let mut parents: Vec<usize> = (0..self.particles.len()).collect();
+        fn root(parents: &mut [usize], mut i: usize) -> usize {
+            while parents[i] != i {
+                parents[i] = parents[parents[i]];
+                i = parents[i];
+            }
+            i
+        }
+
+        let mut bonded = vec![false; self.particles.len()];
+        for (i, j) in self.bonds(width, height, usize::MAX) {
+            bonded[i] = true;
+            bonded[j] = true;
+            let (i, j) = (root(&mut parents, i), root(&mut parents, j));
+            parents[i] = j;
+        }
+
+        (0..self.particles.len())
+            .filter(|&i| bonded[i] && root(&mut parents, i) == i)
+            .count()
+    }
+
+    /// Computes the radial distribution function g(r) of the particles, in a
+    /// simulation `width`x`height` pixels in size.
+    ///
+    /// The result has `bins` entries evenly covering distances from 0 to
+    /// `max_r` pixels. Each is the number of pairs of particles that far apart,
+    /// relative to how many there would be if the particles were spread out
+    /// uniformly at random; so peaks show the spacings particles prefer.
+    ///
+    /// If `wrap` isn't set, particles near the edges have fewer neighbours than
+    /// they would otherwise, so large distances will be underestimated.
+    pub fn radial_distribution(
+        &self,
+        bins: usize,
+        max_r: f32,
+        width: f32,
+        height: f32,
+    ) -> Vec<f32> {
+        let scale = 0.5 * vec2(width, height);
+        let bin_width = max_r / bins as f32;
+
+        let mut counts = vec![0u32; bins];
+        for (i, p) in self.particles.iter().enumerate() {
+            for q in &self.particles[i + 1..] {
+                let mut delta = q.pos - p.pos;
+                if self.wrap {
+                    delta.x += -2.0 * f32::floor(0.5 * delta.x + 0.5);
+                    delta.y += -2.0 * f32::floor(0.5 * delta.y + 0.5);
+                }
+                delta *= scale;
+
+                let bin = (delta.length() / bin_width) as usize;
+                if bin < bins {
+                    counts[bin] += 1;
+                }
+            }
+        }
+
+        let n = self.particles.len() as f32;
+        let density = n / (width * height);
+        counts
+            .into_iter()
+            .enumerate()
+            .map(|(bin, count)| {
+                let inner = bin as f32 * bin_width;
+                let outer = inner + bin_width;
+                let shell_area = PI * (outer * outer - inner * inner);
+                // Each pair is only counted once, so double it to get the average number of
+                // neighbours each particle has in this shell.
+                let expected = n * density * shell_area;
+                if expected > 0.0 {
+                    2.0 * count as f32 / expected
+                } else {
+                    0.0
+                }
+            })
+            .collect()
+    }
+
+    /// Sets the size (in pixels) that `step_default` runs the simulation at.
+    ///
+    /// Forces and velocities are in pixels, but positions are in clip space, so
+    /// changing the size part-way through a run changes how far particles move
+    /// across the simulation each step, and how far apart they are as far as
+    /// forces are concerned.
+    pub fn set_size(&mut self, width: f32, height: f32) {
+        self.size = Some(vec2(width, height));
+    }
+
+    /// Like `step`, but uses the size set by `set_size`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `set_size` hasn't been called.
+    pub fn step_default(&mut self) {
+        let size = self
+            .size
+            .expect("`set_size` must be called before `step_default`");
+        self.step(size.x, size.y);
+    }
+
+    pub fn step(&mut self, width: f32, height: f32) {
+        self.step_dt(width, height, 1.0);
+    }
+
+    /// Advances the simulation by `dt` steps' worth of time.
+    ///
+    /// Splitting a step up into several smaller ones makes the simulation more
+    /// accurate, at the cost of being slower.
+    pub fn step_dt(&mut self, width: f32, height: f32, dt: f32) {
+        self.step_with_stats(width, height, dt);
+    }
+
+    /// Like `step_dt`, but also returns how many pairs of particles interacted.
+    ///
+    /// With `Integrator::Leapfrog`, the forces are calculated twice, so every
+    /// pair is counted twice.
+    pub fn step_with_stats(&mut self, width: f32, height: f32, dt: f32) -> StepStats {
+        let size = vec2(width, height);
+
+        // The amount we want to scale up clip space by to get to pixel space.
+        // This isn't just width because clip space ranges from -1 to 1, so it's
+        // actually 2x2.
+        let scale = 0.5 * size;
+
+        // The inverse of `x_scale` and `y_scale`, to go from pixel space to clip space.
+        let inv_scale = 2.0 / size;
+
+        // Figure out the width/height of the particles in clip space.
+        let clip_size = RADIUS * inv_scale;
+
+        // The amount to multiply velocities by to apply friction. A whole step multiplies them
+        // by `1.0 - friction`, so `dt` steps multiply them by that to the power of `dt`,
+        // which means splitting a step up doesn't change how much friction is applied.
+        let mut per_step = 1.0 - self.friction;
+        if self.friction_clamped {
+            per_step = per_step.clamp(0.0, 1.0);
+        }
+        // Negative numbers don't have real fractional powers, so frictions above `1.0` still
+        // reverse velocities every step, however long it is.
+        let damping = per_step.abs().powf(dt).copysign(per_step);
+
+        // Take the buffer out of `self` so that we can borrow it alongside `self`.
+        let mut accelerations = core::mem::take(&mut self.accelerations);
+
+        let mut stats = StepStats::default();
+        match self.integrator {
+            Integrator::SemiImplicitEuler => {
+                self.compute_accelerations(scale, clip_size, &mut accelerations, &mut stats);
+                self.kick(dt, &accelerations);
+                self.drift(dt, inv_scale, clip_size);
+            }
+            Integrator::Leapfrog => {
+                self.compute_accelerations(scale, clip_size, &mut accelerations, &mut stats);
+                self.kick(0.5 * dt, &accelerations);
+                self.drift(dt, inv_scale, clip_size);
+                self.compute_accelerations(scale, clip_size, &mut accelerations, &mut stats);
+                self.kick(0.5 * dt, &accelerations);
+            }
+        }
+
+        for p in self.particles.iter_mut() {
+            p.vel *= damping;
+            p.age = p.age.saturating_add(1);
+        }
+
+        if self.remove_net_drift && !self.particles.is_empty() {
+            let mean_vel =
+                self.particles.iter().map(|p| p.vel).sum::<Vec2>() / self.particles.len() as f32;
+            for p in self.particles.iter_mut() {
+                p.vel -= mean_vel;
+            }
+        }
+
+        self.accelerations = accelerations;
+
+        self.evolve(dt);
+        self.advance_friction_schedule(dt);
+
+        stats
+    }
+
+    /// Starts ramping `friction` according to `schedule`, or stops if
+    /// `schedule` is `None`, leaving `friction` where it is.
+    ///
+    /// `friction` is set to `schedule.start` straight away, and then updated
+    /// after every step until it reaches `schedule.end`, at which point the
+    /// schedule is removed. While the schedule is running, it overrides any
+    /// other changes to `friction`.
+    pub fn set_friction_schedule(&mut self, schedule: Option<FrictionSchedule>) {
+        if let Some(schedule) = schedule {
+            self.friction = schedule.start;
+        }
+        self.friction_schedule = schedule;
+        self.friction_elapsed = 0.0;
+    }
+
+    /// Returns the schedule `friction` is following, if it's still running.
+    pub fn friction_schedule(&self) -> Option<FrictionSchedule> {
+        self.friction_schedule
+    }
+
+    /// Moves `friction` `dt` steps further along `friction_schedule`.
+    fn advance_friction_schedule(&mut self, dt: f32) {
+        let Some(schedule) = self.friction_schedule else {
+            return;
+        };
+
+        self.friction_elapsed += dt;
+        self.friction = schedule.friction_at(self.friction_elapsed);
+        if self.friction_elapsed >= schedule.duration {
+            self.friction_schedule = None;
+        }
+    }
+
+    /// Starts slowly evolving the attraction matrix, or stops if `config` is
+    /// `None`.
+    ///
+    /// Every `config.interval` steps, a random pair of kinds is picked along
+    /// with a new attraction for it, which `step` then blends the pair's
+    /// attraction towards. New attractions are picked from between the weakest
+    /// and strongest attractions in the matrix at the time this is called, so
+    /// the matrix never leaves that range. The same `seed` always picks the
+    /// same pairs and attractions.
+    pub fn set_evolve(&mut self, config: Option<EvolveConfig>, seed: u64) {
+        self.evolution = config
+            .map(|config| Evolution::new(config, StdRng::seed_from_u64(seed), &self.pair_props));
+    }
+
+    /// Returns how the attraction matrix is evolving, if it is.
+    pub fn evolve_config(&self) -> Option<EvolveConfig> {
+        self.evolution.as_ref().map(|evolution| evolution.config)
+    }
+
+    /// Moves the evolving pair's attraction `dt` steps' worth towards its new
+    /// value, first picking a new pair if it's time to.
+    fn evolve(&mut self, dt: f32) {
+        let Some(evolution) = &mut self.evolution else {
+            return;
+        };
+        if self.pair_props.is_empty() {
+            return;
+        }
+
+        evolution.elapsed += dt;
+        if evolution.target.is_none() || evolution.elapsed >= evolution.config.interval {
+            evolution.elapsed = 0.0;
+            let index = evolution.rng.gen_range(0..self.pair_props.len());
+            let attraction = evolution
+                .rng
+                .gen_range(evolution.min_attraction..=evolution.max_attraction);
+            evolution.target = Some((index, attraction));
+        }
+
+        if let Some((index, target)) = evolution.target {
+            if let Some(props) = self.pair_props.get_mut(index) {
+                let factor = f32::min(evolution.config.blend_rate * dt, 1.0);
+                props.attraction += (target - props.attraction) * factor;
+            }
+        }
+    }
+
+    /// Fills `accelerations` with the acceleration of each particle (in pixels
+    /// per step squared) at their current positions, adding the pairs of
+    /// particles considered to `stats`.
+    fn compute_accelerations(
+        &self,
+        scale: Vec2,
+        clip_size: Vec2,
+        accelerations: &mut Vec<Vec2>,
+        stats: &mut StepStats,
+    ) {
+        accelerations.clear();
+        accelerations.resize(self.particles.len(), Vec2::ZERO);
+
+        let n = self.particles.len();
+        let mut evaluated_pairs = 0;
+
+        for i in 0..self.particles.len() {
+            let p = self.particles[i];
+            let p_offset = self.kind_offset[p.kind];
+            for j in i + 1..self.particles.len() {
+                let q = self.particles[j];
+
+                let mut delta = q.pos - p.pos;
+
+                if self.wrap {
+                    // These are faster one-line versions of:
+                    // if delta > 1.0 {
+                    //     delta -= 2.0;
+                    // } else if delta < -1.0 {
+                    //     delta += 2.0
+                    // }
+                    //
+                    // Multiplying by 0.5, adding 0.5, then flooring means that we get this mapping:
+                    // -3.0 - -1.0 -> -1.0
+                    // -1.0 -  1.0 ->  0.0
+                    //  1.0 -  3.0 ->  1.0
+                    //
+                    // So, by then multiplying that by -2.0 we get the effect we want.
+                    delta.x += -2.0 * f32::floor(0.5 * delta.x + 0.5);
+                    delta.y += -2.0 * f32::floor(0.5 * delta.y + 0.5);
+                }
+
+                // The positions are in clip space, but velocities are in pixel space, so we
+                // need to scale these up.
+                delta *= scale;
+
+                let dist2 = delta.length_squared();
+
+                let props = &self.pair_props[p_offset + q.kind];
+                let &PairProps {
+                    attraction: p_attr,
+                    repel_distance,
+                    influence_radius,
+                    cutoff_sq,
+                    peak,
+                    inv_base,
+                    ..
+                } = props;
+
+                // Disallow small distances to avoid division by zero, since we divide by this
+                // to normalize the vector later on. See `min_distance_sq`.
+                //
+                // The upper bound is exclusive so that if `influence_radius == repel_distance`,
+                // we never hit the attraction branch with an infinite `inv_base`.
+                if dist2 >= cutoff_sq || dist2 < self.min_distance_sq {
+                    continue;
+                }
+
+                let dist = dist2.sqrt();
+                let q_props = &self.pair_props[self.kind_offset[q.kind] + p.kind];
+
+                let (mut f1, mut f2) = if dist < repel_distance {
+                    let f = R_SMOOTH
+                        * repel_distance
+                        * (1.0 / (repel_distance + R_SMOOTH) - 1.0 / (dist + R_SMOOTH));
+                    (f, f)
+                } else if dist >= influence_radius {
+                    // Only `outer_band` reaches this far.
+                    (0.0, 0.0)
+                } else {
+                    let mut f1 = p_attr;
+                    let mut f2 = q_props.attraction;
+
+                    let coefficient = if self.flat_force {
+                        self.flat_force_shape.coefficient(props, dist)
+                    } else {
+                        1.0 - (f32::abs(dist - peak) * inv_base)
+                    };
+                    f1 *= coefficient;
+                    f2 *= coefficient;
+
+                    (f1, f2)
+                };
+                if let Some(band) = props.outer_band {
+                    f1 += band.force(dist);
+                }
+                if let Some(band) = q_props.outer_band {
+                    f2 += band.force(dist);
+                }
+
+                let direction = delta / dist;
+
+                accelerations[i] += f1 * direction;
+                accelerations[j] += f2 * -direction;
+                evaluated_pairs += 1;
+            }
+        }
+
+        stats.evaluated_pairs += evaluated_pairs;
+        stats.skipped_pairs += n * n.saturating_sub(1) / 2 - evaluated_pairs;
+
+        if let (false, Some(boundary)) = (self.wrap, self.boundary_force) {
+            for (p, acceleration) in self.particles.iter().zip(accelerations.iter_mut()) {
+                // How far the edge of the particle is from the left/bottom and right/top walls,
+                // in pixels.
+                let near = (p.pos + 1.0 - clip_size) * scale;
+                let far = (1.0 - clip_size - p.pos) * scale;
+
+                // Push the particle inwards in proportion to how far it is into the boundary
+                // layer.
+                let push = (boundary.thickness - near).max(Vec2::ZERO)
+                    - (boundary.thickness - far).max(Vec2::ZERO);
+                *acceleration += boundary.strength * push;
+            }
+        }
+    }
+
+    /// Updates the particles' velocities from their `accelerations` over `dt`
+    /// steps.
+    fn kick(&mut self, dt: f32, accelerations: &[Vec2]) {
+        for (p, acceleration) in self.particles.iter_mut().zip(accelerations) {
+            if !p.pinned {
+                p.vel += dt * *acceleration;
+            }
+        }
+    }
+
+    /// Moves the particles along their velocities for `dt` steps, then wraps
+    /// them around or bounces them off the edges.
+    fn drift(&mut self, dt: f32, inv_scale: Vec2, clip_size: Vec2) {
+        for p in self.particles.iter_mut() {
+            if p.pinned {
+                // Velocity can still sneak in from things like `reverse` or `remove_net_drift`.
+                p.vel = Vec2::ZERO;
+                continue;
+            }
+
+            let mut pos = p.pos;
+            let mut vel = p.vel;
+
+            pos += dt * vel * inv_scale;
+
+            if self.wrap {
+                pos.x += -2.0 * f32::floor(0.5 * pos.x + 0.5);
+                pos.y += -2.0 * f32::floor(0.5 * pos.y + 0.5);
+            } else if self.arena == Arena::Disc {
+                let max_dist = 1.0 - clip_size.max_element();
+                if pos.length() > max_dist {
+                    pos = pos.normalize() * max_dist;
+
+                    // The velocity's in pixels, where the disc is really an ellipse, so the
+                    // normal has to be scaled to match.
+                    let normal = (pos * inv_scale).normalize();
+                    let outward = vel.dot(normal);
+                    if outward > 0.0 {
+                        vel -= 2.0 * outward * normal;
+                    }
+                }
+            } else {
+                if pos.x + clip_size.x > 1.0 {
+                    pos.x = 1.0 - clip_size.x;
+                    vel.x *= -1.0;
+                } else if pos.x - clip_size.x < -1.0 {
+                    pos.x = -1.0 + clip_size.x;
+                    vel.x *= -1.0;
+                }
+
+                if pos.y + clip_size.y > 1.0 {
+                    pos.y = 1.0 - clip_size.y;
+                    vel.y *= -1.0;
+                } else if pos.y - clip_size.y < -1.0 {
+                    pos.y = -1.0 + clip_size.y;
+                    vel.y *= -1.0;
+                }
+            }
+
+            p.pos = pos;
+            p.vel = vel;
+        }
+    }
+
+    /// Spawns and kills particles according to `life_rules`, in a simulation
+    /// `width`x`height` pixels in size. This should be called after every
+    /// step.
+    ///
+    /// New particles are added to the end of `particles`, and there are never
+    /// more than `MAX_PARTICLES` particles.
+    pub fn apply_life_rules<R: Rng>(&mut self, width: f32, height: f32, rng: &mut R) {
+        let Some(rules) = &self.life_rules else {
+            return;
+        };
+
+        let scale = 0.5 * vec2(width, height);
+        let inv_scale = 2.0 / vec2(width, height);
+        let radius_sq = rules.radius * rules.radius;
+
+        let mut neighbours = vec![0; self.particles.len()];
+        for i in 0..self.particles.len() {
+            let p = self.particles[i];
+            for j in i + 1..self.particles.len() {
+                let q = self.particles[j];
+                if p.kind != q.kind {
+                    continue;
+                }
+
+                let mut delta = q.pos - p.pos;
+                if self.wrap {
+                    delta.x += -2.0 * f32::floor(0.5 * delta.x + 0.5);
+                    delta.y += -2.0 * f32::floor(0.5 * delta.y + 0.5);
+                }
+
+                if (delta * scale).length_squared() < radius_sq {
+                    neighbours[i] += 1;
+                    neighbours[j] += 1;
+                }
+            }
+        }
+
+        let mut births = Vec::new();
+        for (p, &count) in self.particles.iter_mut().zip(&neighbours) {
+            if count == 0 {
+                p.lonely_steps += 1;
+            } else {
+                p.lonely_steps = 0;
+            }
+
+            if let Some(threshold) = rules.birth_neighbours.get(p.kind).copied().flatten() {
+                if count >= threshold && rng.gen::<f32>() < rules.birth_chance {
+                    // Put the new particle just touching its parent, moving along with it so
+                    // that it stays in the same cluster. It's never pinned, even if its parent
+                    // is (in which case it starts at rest).
+                    let angle = rng.gen_range(0.0..TAU);
+                    births.push(Particle {
+                        pos: p.pos + DIAMETER * Vec2::from_angle(angle) * inv_scale,
+                        vel: p.vel,
+                        kind: p.kind,
+                        lonely_steps: 0,
+                        pinned: false,
+                        age: 0,
+                    });
+                }
+            }
+        }
+
+        self.particles
+            .retain(|p| match rules.death_steps.get(p.kind).copied().flatten() {
+                Some(max_steps) => p.lonely_steps <= max_steps,
+                None => true,
+            });
+
+        let room = MAX_PARTICLES.saturating_sub(self.particles.len());
+        self.particles.extend(births.into_iter().take(room));
+    }
+
+    /// Adds particles at random positions according to `spawn_schedule`, until
+    /// there are `target` of them. This should be called after every step.
+    ///
+    /// New particles are added to the end of `particles`. Kind weights which
+    /// don't have an entry for every kind, or are all zero, are ignored.
+    pub fn apply_spawn_schedule<R: Rng>(&mut self, rng: &mut R) {
+        let Some(schedule) = &self.spawn_schedule else {
+            return;
+        };
+        let kinds = self.colors.len();
+        let room = schedule
+            .target
+            .min(MAX_PARTICLES)
+            .saturating_sub(self.particles.len());
+        if room == 0 || kinds == 0 {
+            self.spawn_progress = 0.0;
+            return;
+        }
+
+        self.spawn_progress += schedule.rate.max(0.0);
+        let count = (self.spawn_progress as usize).min(room);
+        self.spawn_progress -= count as f32;
+
+        let weights = schedule
+            .kind_weights
+            .as_ref()
+            .filter(|weights| weights.len() == kinds)
+            .and_then(|weights| WeightedIndex::new(weights).ok());
+        for _ in 0..count {
+            let mut particle = Particle::generate(kinds, rng);
+            if let Some(weights) = &weights {
+                particle.kind = weights.sample(rng);
+            }
+            self.particles.push(particle);
+        }
+    }
+
+    /// Returns the indices of the particles in the order they should be drawn
+    /// in for `order`, from bottom to top, or `None` if that's the order
+    /// they're stored in.
+    ///
+    /// `rng` is used to shuffle them for `DrawOrder::Random`.
+    pub fn draw_order<R: Rng>(&self, order: DrawOrder, rng: &mut R) -> Option<Vec<usize>> {
+        let mut indices: Vec<usize> = (0..self.particles.len()).collect();
+        match order {
+            DrawOrder::ByKind => return None,
+            DrawOrder::BySpeed => indices.sort_by(|&a, &b| {
+                let a = self.particles[a].vel.length_squared();
+                let b = self.particles[b].vel.length_squared();
+                a.total_cmp(&b)
+            }),
+            DrawOrder::Random => indices.shuffle(rng),
+        }
+        Some(indices)
+    }
+
+    /// Returns the representation of each particle used by the GPU, drawing
+    /// each kind with the radius (in pixels) given by `kind_radii`.
+    ///
+    /// If `visible_kinds` is given, particles of any other kind are skipped.
+    /// If `adaptive_trails` is given, it's used to fade out slow particles'
+    /// trails. If `order` is given (as returned by `draw_order`), the
+    /// particles are returned in that order.
+    fn gpu_particles<'a>(
+        &'a self,
+        kind_radii: &'a [f32],
+        kind_colors: &'a [LinSrgb],
+        visible_kinds: Option<&'a BTreeSet<usize>>,
+        adaptive_trails: Option<AdaptiveTrails>,
+        order: Option<&'a [usize]>,
+    ) -> impl Iterator<Item = GpuParticle> + 'a {
+        // The order might be out of date if particles have been born or died since.
+        let order = order.filter(|order| order.len() == self.particles.len());
+        (0..self.particles.len())
+            .map(move |i| &self.particles[order.map_or(i, |order| order[i])])
+            .filter(move |particle| match visible_kinds {
+                Some(kinds) => kinds.contains(&particle.kind),
+                None => true,
+            })
+            .map(move |particle| GpuParticle {
+                scale: kind_radii[particle.kind] / RADIUS,
+                trail_alpha: adaptive_trails.map_or(1.0, |trails| trails.alpha(particle.vel)),
+                ..GpuParticle::from_particle(
+                    particle,
+                    self.color_mode.color(particle, kind_colors[particle.kind]),
+                )
+            })
+    }
+
+    /// Convert the current state of the particles into the representation used
+    /// by the GPU.
+    ///
+    /// `kind_radii` gives the radius (in pixels) to draw each kind of particle
+    /// with. If `kind_colors` is given, it's used to color each kind instead of
+    /// `colors`, before `color_mode` is applied.
+    ///
+    /// If `visible_kinds` is given, only particles of those kinds are exported.
+    /// The rest of the buffer is filled with zero-sized particles, which don't
+    /// show up when drawn.
+    ///
+    /// If `adaptive_trails` is given, each particle's trail is faded out based
+    /// on its speed. If `order` is given (as returned by `draw_order`), the
+    /// particles are exported in that order.
+    pub fn export_particles(
+        &self,
+        buffer: &mut [GpuParticle; MAX_PARTICLES],
+        kind_radii: &[f32],
+        kind_colors: Option<&[LinSrgb]>,
+        visible_kinds: Option<&BTreeSet<usize>>,
+        adaptive_trails: Option<AdaptiveTrails>,
+        order: Option<&[usize]>,
+    ) {
+        debug_assert!(
+            self.particles.len() <= MAX_PARTICLES,
+            "more particles than fit in the buffer"
+        );
+        let kind_colors = kind_colors.unwrap_or(&self.colors);
+        let mut particles = self.gpu_particles(
+            kind_radii,
+            kind_colors,
+            visible_kinds,
+            adaptive_trails,
+            order,
+        );
+        for slot in buffer.iter_mut() {
+            *slot = particles.next().unwrap_or_default();
+        }
+    }
+
+    /// Like `export_particles`, but replaces the contents of a `Vec` instead,
+    /// reusing its allocation.
+    pub fn export_particles_into(&self, buffer: &mut Vec<GpuParticle>, kind_radii: &[f32]) {
+        buffer.clear();
+        buffer.extend(self.gpu_particles(kind_radii, &self.colors, None, None, None));
+    }
+
+    /// Like `export_particles_into`, but returns a new `Vec`.
+    pub fn to_gpu_particles(&self, kind_radii: &[f32]) -> Vec<GpuParticle> {
+        self.gpu_particles(kind_radii, &self.colors, None, None, None)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+
+    #[test]
+    fn palette_range_stays_in_range() {
+        for kinds in [1, 2, 5, 9] {
+            for color in (PaletteSpec::Range {
+                start: 0.0,
+                end: 60.0,
+            })
+            .colors(kinds)
+            {
+                let hue = Hsv::from_color(color).hue.into_positive_degrees();
+                assert!(
+                    (0.0..=60.01).contains(&hue) || hue > 359.99,
+                    "hue {hue} is outside of 0-60"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn try_new_uses_settings_palette() {
+        let colors = vec![LinSrgb::new(1.0, 0.0, 0.0), LinSrgb::new(0.0, 1.0, 0.0)];
+        let settings = Settings {
+            palette: PaletteSpec::Custom(colors.clone()),
+            ..Settings::balanced()
+        };
+        let sim = Sim::try_new(settings.clone(), &mut StdRng::seed_from_u64(0)).unwrap();
+        assert_eq!(
+            sim.colors,
+            PaletteSpec::Custom(colors).colors(settings.kinds)
+        );
+    }
+
+    #[test]
+    fn carry_over_keeps_options() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut old = Sim::new(Settings::balanced(), &mut rng);
+        old.wrap = true;
+        old.friction_clamped = true;
+        old.placement = Placement::LowDiscrepancy;
+        old.kind_regions = Some(vec![Rect::new(vec2(-1.0, -1.0), vec2(0.0, 0.0))]);
+        old.spawn_schedule = Some(SpawnSchedule {
+            target: 100,
+            rate: 0.5,
+            kind_weights: None,
+        });
+        old.set_friction_schedule(Some(FrictionSchedule {
+            start: 0.01,
+            end: 0.2,
+            duration: 100.0,
+        }));
+        old.set_size(800.0, 600.0);
+        old.stable_order = true;
+
+        let mut new = Sim::new(Settings::cells(), &mut rng);
+        new.carry_over_options_from(&old);
+        assert!(new.wrap);
+        assert!(new.friction_clamped);
+        assert_eq!(new.placement, Placement::LowDiscrepancy);
+        assert_eq!(new.kind_regions, old.kind_regions);
+        assert_eq!(new.spawn_schedule, old.spawn_schedule);
+        assert_eq!(new.friction_schedule(), old.friction_schedule());
+        assert_eq!(new.friction, 0.01);
+        assert_eq!(new.size, old.size);
+        assert!(new.stable_order);
+        // The universe itself comes from the new settings.
+        assert_eq!(new.colors.len(), Settings::cells().kinds);
+    }
+
+    #[test]
+    fn particles_seed_only_changes_particles() {
+        let a = Sim::from_seeds(Settings::balanced(), 1, 2);
+        let b = Sim::from_seeds(Settings::balanced(), 1, 3);
+        // `PairProps` isn't `PartialEq`, so compare its debug output instead.
+        assert_eq!(format!("{:?}", a.pair_props), format!("{:?}", b.pair_props));
+        assert_eq!(a.colors, b.colors);
+        let positions = |sim: &Sim| sim.particles.iter().map(|p| p.pos).collect::<Vec<_>>();
+        assert_ne!(positions(&a), positions(&b));
+    }
+
+    #[test]
+    fn from_seed_is_deterministic() {
+        let a = Sim::from_seed(Settings::balanced(), 42);
+        let b = Sim::from_seed(Settings::balanced(), 42);
+        assert_eq!(format!("{:?}", a.pair_props), format!("{:?}", b.pair_props));
+        let positions = |sim: &Sim| sim.particles.iter().map(|p| p.pos).collect::<Vec<_>>();
+        assert_eq!(positions(&a), positions(&b));
+
+        // Placing the particles again with `particles_rng` gives the same ones.
+        let mut c = Sim::from_seed(Settings::balanced(), 42);
+        c.regenerate_particles(&mut Sim::particles_rng(42));
+        assert_eq!(positions(&a), positions(&c));
+    }
+
+    #[test]
+    fn try_new_rejects_invalid_settings() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let try_new = |change: fn(&mut Settings)| {
+            let mut settings = Settings::balanced();
+            change(&mut settings);
+            Sim::try_new(settings, &mut StdRng::seed_from_u64(0)).err()
+        };
+
+        assert_eq!(try_new(|_| {}), None);
+        assert_eq!(try_new(|s| s.kinds = 0), Some(SimError::NoKinds));
+        assert_eq!(
+            try_new(|s| s.particles = MAX_PARTICLES + 1),
+            Some(SimError::TooManyParticles {
+                particles: MAX_PARTICLES + 1,
+                max: MAX_PARTICLES,
+            })
+        );
+        assert_eq!(
+            try_new(|s| s.attraction_distr = Normal::new(f32::NAN, 0.1).unwrap()),
+            Some(SimError::InvalidAttraction)
+        );
+        assert_eq!(
+            try_new(|s| s.attraction_distr = Normal::new(f32::INFINITY, 0.1).unwrap()),
+            Some(SimError::InvalidAttraction)
+        );
+        // `Normal::new` accepts this, so it has to be checked separately.
+        assert_eq!(
+            try_new(|s| s.attraction_distr = Normal::new(0.0, -0.1).unwrap()),
+            Some(SimError::InvalidAttraction)
+        );
+        assert_eq!(
+            try_new(|s| s.repel_distance_range = -10.0..=-5.0),
+            Some(SimError::InvalidRadius)
+        );
+        assert_eq!(
+            try_new(|s| s.influence_radius_range = -10.0..=-5.0),
+            Some(SimError::InvalidRadius)
+        );
+        // These are caught even though they might never sample a negative radius.
+        assert_eq!(
+            try_new(|s| s.repel_distance_range = -5.0..=1000.0),
+            Some(SimError::InvalidRadius)
+        );
+        assert_eq!(
+            try_new(|s| s.influence_radius_range = 30.0..=f32::INFINITY),
+            Some(SimError::InvalidRadius)
+        );
+        assert_eq!(
+            try_new(|s| s.influence_radius_range = 70.0..=20.0),
+            Some(SimError::InvalidRadius)
+        );
+
+        // The rest can't come from `Settings`, only from changing a universe or
+        // building one by hand.
+        let mut sim = Sim::new(Settings::balanced(), &mut rng);
+        let kinds = sim.colors.len();
+        assert_eq!(
+            sim.set_attraction_matrix(&[0.0; 3]),
+            Err(SimError::WrongMatrixSize {
+                len: 3,
+                expected: kinds * kinds,
+            })
+        );
+        assert_eq!(
+            sim.set_attraction_matrix(&vec![f32::NAN; kinds * kinds]),
+            Err(SimError::InvalidAttraction)
+        );
+        assert_eq!(
+            sim.set_palette(vec![LinSrgb::new(1.0, 0.0, 0.0)]),
+            Err(SimError::WrongPaletteSize {
+                len: 1,
+                expected: kinds,
+            })
+        );
+        assert_eq!(
+            UniverseBuilder::new(2).attraction(0, 2, 0.1).build().err(),
+            Some(SimError::InvalidKind { kind: 2, kinds: 2 })
+        );
+        assert_eq!(
+            UniverseBuilder::new(2)
+                .particle(0, vec2(0.0, 0.0))
+                .particle(1, vec2(1.5, 0.0))
+                .build()
+                .err(),
+            Some(SimError::InvalidPosition { particle: 1 })
+        );
+        assert_eq!(
+            UniverseBuilder::new(2)
+                .radii(0, 1, -1.0, 10.0)
+                .build()
+                .err(),
+            Some(SimError::InvalidRadius)
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn attraction_csv_round_trips() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let from = Sim::new(Settings::balanced(), &mut rng);
+        let mut to = Sim::new(Settings::balanced(), &mut rng);
+
+        let mut csv = Vec::new();
+        from.write_attraction_csv(&mut csv).unwrap();
+        to.load_attraction_csv(csv.as_slice()).unwrap();
+        let attractions = |sim: &Sim| -> Vec<f32> {
+            sim.pair_props
+                .iter()
+                .map(|props| props.attraction)
+                .collect()
+        };
+        assert_eq!(attractions(&to), attractions(&from));
+
+        let mut csv = Vec::new();
+        from.write_palette_csv(&mut csv).unwrap();
+        to.load_palette_csv(csv.as_slice()).unwrap();
+        assert_eq!(to.colors, from.colors);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn reads_messy_csv() {
+        let mut sim = UniverseBuilder::new(2).build().unwrap();
+        sim.load_attraction_csv(" 1, -0.5 \r\n\n0.25,0\n\n".as_bytes())
+            .unwrap();
+        let attractions: Vec<f32> = sim
+            .pair_props
+            .iter()
+            .map(|props| props.attraction)
+            .collect();
+        assert_eq!(attractions, [1.0, -0.5, 0.25, 0.0]);
+
+        assert!(matches!(
+            sim.load_attraction_csv("1,2\n3".as_bytes()),
+            Err(CsvError::WrongRowLength {
+                row: 1,
+                len: 1,
+                expected: 2,
+            })
+        ));
+        assert!(matches!(
+            sim.load_attraction_csv("1,2\n3,x".as_bytes()),
+            Err(CsvError::InvalidNumber { row: 1, column: 1 })
+        ));
+        assert!(matches!(
+            sim.load_attraction_csv("1,2\n".as_bytes()),
+            Err(CsvError::Matrix(SimError::WrongMatrixSize {
+                len: 2,
+                expected: 4,
+            }))
+        ));
+        // Nothing is changed by a failed load.
+        assert_eq!(sim.pair_props[0].attraction, 1.0);
+    }
+
+    #[test]
+    fn permuting_kinds_keeps_forces_consistent() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut sim = Sim::new(Settings::balanced(), &mut rng);
+        sim.regenerate_particles(&mut rng);
+        let kinds = sim.colors.len();
+        sim.spawn_schedule = Some(SpawnSchedule {
+            target: MAX_PARTICLES,
+            rate: 1.0,
+            kind_weights: Some((0..kinds).map(|k| k as f32 + 1.0).collect()),
+        });
+        sim.set_evolve(
+            Some(EvolveConfig {
+                interval: 100.0,
+                blend_rate: 0.01,
+            }),
+            0,
+        );
+        // Pick the pair to evolve.
+        sim.step(800.0, 600.0);
+        let old = sim.clone();
+
+        // Rotate the kinds round by one.
+        let permutation: Vec<usize> = (0..kinds).map(|k| (k + 1) % kinds).collect();
+        sim.permute_kinds(&permutation);
+
+        for a in 0..kinds {
+            for b in 0..kinds {
+                let new = sim.pair_props[permutation[a] * kinds + permutation[b]];
+                let old = old.pair_props[a * kinds + b];
+                assert_eq!(new.attraction, old.attraction);
+                assert_eq!(new.repel_distance, old.repel_distance);
+                assert_eq!(new.influence_radius, old.influence_radius);
+            }
+        }
+        for (new, old) in sim.particles.iter().zip(&old.particles) {
+            assert_eq!(new.kind, permutation[old.kind]);
+            assert_eq!(new.pos, old.pos);
+        }
+        // The colors stay put, so each kind's behaviour now has a different color.
+        assert_eq!(sim.colors, old.colors);
+
+        let weights = sim.spawn_schedule.as_ref().unwrap().kind_weights.as_ref();
+        let old_weights = old.spawn_schedule.as_ref().unwrap().kind_weights.as_ref();
+        for (&new_kind, old_weight) in permutation.iter().zip(old_weights.unwrap()) {
+            assert_eq!(weights.unwrap()[new_kind], *old_weight);
+        }
+
+        // The same pair keeps evolving towards the same attraction.
+        let (index, target) = sim.evolution.as_ref().unwrap().target.unwrap();
+        let (old_index, old_target) = old.evolution.as_ref().unwrap().target.unwrap();
+        assert_eq!(target, old_target);
+        assert_eq!(
+            index,
+            permutation[old_index / kinds] * kinds + permutation[old_index % kinds]
+        );
+        assert_eq!(
+            sim.pair_props[index].attraction,
+            old.pair_props[old_index].attraction
+        );
+    }
+
+    #[test]
+    fn shuffling_kinds_is_a_permutation() {
+        let mut sim = Sim::from_seed(Settings::balanced(), 3);
+        let old = sim.clone();
+        sim.shuffle_kinds(&mut StdRng::seed_from_u64(3));
+
+        // Work out which kind each old kind became from the particles, and
+        // check that it's used consistently.
+        let kinds = sim.colors.len();
+        let mut permutation = vec![None; kinds];
+        for (new, old) in sim.particles.iter().zip(&old.particles) {
+            let mapped = permutation[old.kind].get_or_insert(new.kind);
+            assert_eq!(*mapped, new.kind);
+        }
+        let permutation: Vec<usize> = permutation.into_iter().map(Option::unwrap).collect();
+        let mut sorted = permutation.clone();
+        sorted.sort();
+        assert_eq!(sorted, (0..kinds).collect::<Vec<_>>());
+
+        for a in 0..kinds {
+            for b in 0..kinds {
+                assert_eq!(
+                    sim.pair_props[permutation[a] * kinds + permutation[b]].attraction,
+                    old.pair_props[a * kinds + b].attraction
+                );
+            }
+        }
+
+        // The same RNG always gives the same shuffle.
+        let mut again = old.clone();
+        again.shuffle_kinds(&mut StdRng::seed_from_u64(3));
+        let kinds_of = |sim: &Sim| sim.particles.iter().map(|p| p.kind).collect::<Vec<_>>();
+        assert_eq!(kinds_of(&again), kinds_of(&sim));
+    }
+
+    #[test]
+    fn splitting_steps_keeps_damping() {
+        for friction in [0.05, 0.2, 0.5, 1.5] {
+            let mut whole = UniverseBuilder::new(1)
+                .friction(friction)
+                .particle(0, vec2(0.0, 0.0))
+                .build()
+                .unwrap();
+            whole.particles[0].vel = vec2(3.0, -1.0);
+            let mut split = whole.clone();
+
+            for _ in 0..10 {
+                whole.step_dt(800.0, 600.0, 1.0);
+            }
+            for _ in 0..100 {
+                split.step_dt(800.0, 600.0, 0.1);
+            }
+            let expected = vec2(3.0, -1.0) * (1.0 - friction).powi(10);
+            assert!(
+                whole.particles[0].vel.abs_diff_eq(expected, 1e-5),
+                "{friction}: {}",
+                whole.particles[0].vel
+            );
+            // Splitting the steps up gives the same damping. With a friction of 1.5,
+            // there's an even number of steps either way, so the sign ends up the same too.
+            assert!(
+                split.particles[0].vel.abs_diff_eq(expected, 1e-4),
+                "{friction}: {}",
+                split.particles[0].vel
+            );
+        }
+    }
+
+    #[test]
+    fn doubling_step_rate_keeps_real_time_trajectory() {
+        use crate::settings::real_time_dt;
+        use crate::settings::REFERENCE_STEP_RATE;
+
+        // Two particles too far apart to affect each other, so that their trajectories only
+        // depend on friction.
+        let mut slow = UniverseBuilder::new(2)
+            .friction(0.05)
+            .particle(0, vec2(-0.5, 0.0))
+            .particle(1, vec2(0.5, 0.0))
+            .build()
+            .unwrap();
+        slow.particles[0].vel = vec2(-2.0, 1.0);
+        slow.particles[1].vel = vec2(1.0, 3.0);
+        let start = slow.particles.clone();
+        let mut fast = slow.clone();
+
+        let rate = REFERENCE_STEP_RATE / 2;
+        // Compare them every tenth of a second.
+        for _ in 0..10 {
+            for _ in 0..rate / 10 {
+                slow.step_dt(800.0, 600.0, real_time_dt(rate));
+            }
+            for _ in 0..2 * rate / 10 {
+                fast.step_dt(800.0, 600.0, real_time_dt(2 * rate));
+            }
+            for ((slow, fast), start) in slow.particles.iter().zip(&fast.particles).zip(&start) {
+                assert!(
+                    slow.vel.abs_diff_eq(fast.vel, 1e-4 * start.vel.length()),
+                    "{} != {}",
+                    slow.vel,
+                    fast.vel
+                );
+                // Positions can't match exactly, since longer steps integrate velocity less
+                // accurately.
+                let travelled = clip_to_pixels(fast.pos - start.pos, 800.0, 600.0).length();
+                let error = clip_to_pixels(slow.pos - fast.pos, 800.0, 600.0).length();
+                assert!(error < 0.05 * travelled, "{error} of {travelled} pixels");
+            }
+        }
+    }
+
+    #[test]
+    fn substeps_follow_an_orbit_more_closely() {
+        // Two particles orbiting each other, with no friction to hide any errors.
+        let mut orbit = UniverseBuilder::new(1)
+            .attraction(0, 0, 0.02)
+            .radii(0, 0, DIAMETER, 100.0)
+            .friction(0.0)
+            .particle(0, vec2(-0.05, 0.0))
+            .particle(0, vec2(0.05, 0.0))
+            .build()
+            .unwrap();
+        orbit.particles[0].vel = vec2(0.0, -0.6);
+        orbit.particles[1].vel = vec2(0.0, 0.6);
+
+        let run = |substeps: u32| {
+            let mut sim = orbit.clone();
+            for _ in 0..1000 {
+                for _ in 0..substeps {
+                    sim.step_dt(800.0, 600.0, 1.0 / substeps as f32);
+                }
+            }
+            sim.particles
+        };
+        // Very short steps are as close to the exact orbit as we can get.
+        let exact = run(64);
+        let error = |particles: Vec<Particle>| -> f32 {
+            particles
+                .iter()
+                .zip(&exact)
+                .map(|(p, exact)| clip_to_pixels(p.pos - exact.pos, 800.0, 600.0).length())
+                .sum()
+        };
+
+        let whole = error(run(1));
+        let split = error(run(4));
+        assert!(
+            split < whole / 2.0,
+            "{split} pixels off with substeps, {whole} without"
+        );
+    }
+
+    #[test]
+    fn life_rules_grow_clusters_and_kill_isolated_particles() {
+        let isolated = vec2(0.8, 0.8);
+        let mut sim = UniverseBuilder::new(1)
+            .particle(0, vec2(0.0, 0.0))
+            .particle(0, vec2(0.01, 0.0))
+            .particle(0, vec2(0.0, 0.01))
+            .particle(0, vec2(0.01, 0.01))
+            .particle(0, isolated)
+            .build()
+            .unwrap();
+        sim.life_rules = Some(LifeRules {
+            radius: 20.0,
+            birth_neighbours: vec![Some(2)],
+            birth_chance: 1.0,
+            death_steps: vec![Some(3)],
+        });
+        sim.particles[1].vel = vec2(0.1, 0.0);
+        sim.pin(0);
+
+        let mut rng = StdRng::seed_from_u64(0);
+        let has_isolated = |sim: &Sim| sim.particles.iter().any(|p| p.pos == isolated);
+        for _ in 0..3 {
+            sim.apply_life_rules(800.0, 600.0, &mut rng);
+            assert!(has_isolated(&sim));
+        }
+        sim.apply_life_rules(800.0, 600.0, &mut rng);
+        assert!(!has_isolated(&sim), "isolated particle should have died");
+
+        // Each member of the cluster spawned a child every time.
+        assert!(sim.particles.len() > 4, "cluster didn't grow");
+        let children = &sim.particles[4..];
+        assert!(children.iter().all(|p| !p.pinned && p.age == 0));
+        // Children move along with their parents.
+        assert!(children.iter().any(|p| p.vel == vec2(0.1, 0.0)));
+        assert!(children.iter().any(|p| p.vel == Vec2::ZERO));
+    }
+
+    #[test]
+    fn exports_each_kind_at_its_radius() {
+        let sim = UniverseBuilder::new(2)
+            .particle(0, vec2(-0.5, 0.0))
+            .particle(1, vec2(0.5, 0.0))
+            .build()
+            .unwrap();
+        let mut buffer = [GpuParticle::default(); MAX_PARTICLES];
+        sim.export_particles(&mut buffer, &[RADIUS, 2.5 * RADIUS], None, None, None, None);
+        assert_eq!(buffer[0].scale, 1.0);
+        assert_eq!(buffer[1].scale, 2.5);
+        // The rest of the buffer is left with zero-sized particles, which aren't drawn.
+        assert!(buffer[2..].iter().all(|particle| particle.scale == 0.0));
+    }
+
+    #[test]
+    fn clamping_friction_keeps_velocities_bounded() {
+        let velocity_after_step = |friction: f32, clamped: bool| {
+            let mut sim = UniverseBuilder::new(1)
+                .particle(0, Vec2::ZERO)
+                .build()
+                .unwrap();
+            sim.particles[0].vel = vec2(1.0, 0.0);
+            sim.set_friction(friction);
+            sim.friction_clamped = clamped;
+            sim.step(800.0, 600.0);
+            sim.particles[0].vel.x
+        };
+
+        // Negative friction speeds particles up, unless it's clamped to 0.
+        assert!((velocity_after_step(-0.5, false) - 1.5).abs() < 1e-6);
+        assert_eq!(velocity_after_step(-0.5, true), 1.0);
+        // Friction above 1 reverses particles, unless it's clamped to 1.
+        assert!((velocity_after_step(1.5, false) + 0.5).abs() < 1e-6);
+        assert_eq!(velocity_after_step(1.5, true), 0.0);
+        // In between, clamping doesn't change anything.
+        assert_eq!(
+            velocity_after_step(0.25, true),
+            velocity_after_step(0.25, false)
+        );
+    }
+
+    #[test]
+    fn force_between_agrees_with_step() {
+        let (width, height) = (800.0, 600.0);
+        for dist in [3.0, 8.0, 20.0, 35.0, 55.0, 80.0] {
+            let mut sim = UniverseBuilder::new(2)
+                .attraction(0, 1, 0.05)
+                .attraction(1, 0, -0.03)
+                .radii(0, 1, 10.0, 60.0)
+                .friction(0.0)
+                .particle(0, Vec2::ZERO)
+                .particle(1, pixels_to_clip(vec2(dist, 0.0), width, height))
+                .build()
+                .unwrap();
+            let towards_b = sim.force_between(0, 1, dist);
+            let towards_a = sim.force_between(1, 0, dist);
+            sim.step(width, height);
+
+            // Starting from rest, the velocity after one step is the force.
+            let vel_a = sim.particles[0].vel;
+            let vel_b = sim.particles[1].vel;
+            assert!(
+                (vel_a.x - towards_b).abs() < 1e-6,
+                "{dist} pixels apart: step gave {vel_a}, force_between {towards_b}"
+            );
+            assert!(
+                (vel_b.x + towards_a).abs() < 1e-6,
+                "{dist} pixels apart: step gave {vel_b}, force_between {towards_a}"
+            );
+        }
+    }
+
+    #[test]
+    fn halton_sequence() {
+        let base_2: Vec<f32> = (1..=6).map(|i| halton(i, 2)).collect();
+        assert_eq!(base_2, [0.5, 0.25, 0.75, 0.125, 0.625, 0.375]);
+        let base_3: Vec<f32> = (1..=4).map(|i| halton(i, 3)).collect();
+        assert_eq!(base_3, [1.0 / 3.0, 2.0 / 3.0, 1.0 / 9.0, 4.0 / 9.0]);
+        assert_eq!(halton(0, 2), 0.0);
+    }
+
+    #[test]
+    fn low_discrepancy_placement_spreads_particles_evenly() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut sim = Sim::new(
+            Settings {
+                particles: 256,
+                ..Settings::balanced()
+            },
+            &mut rng,
+        );
+        sim.placement = Placement::LowDiscrepancy;
+        for _ in 0..10 {
+            sim.regenerate_particles(&mut rng);
+
+            // Split the placement area into a 4x4 grid; each cell should get
+            // close to its share of 16 particles.
+            let mut cells = [0; 16];
+            for particle in &sim.particles {
+                assert!(particle.pos.abs().max_element() <= 0.5);
+                let cell = ((particle.pos + 0.5) * 4.0)
+                    .min(Vec2::splat(3.0))
+                    .as_uvec2();
+                cells[(cell.y * 4 + cell.x) as usize] += 1;
+            }
+            assert!(
+                cells.iter().all(|&count| (12..=20).contains(&count)),
+                "uneven placement: {cells:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn clones_step_identically() {
+        let mut sim = Sim::from_seed(Settings::balanced(), 11);
+        for _ in 0..20 {
+            sim.step(800.0, 600.0);
+        }
+        let mut clone = sim.clone();
+        for _ in 0..100 {
+            sim.step(800.0, 600.0);
+            clone.step(800.0, 600.0);
+        }
+        for (a, b) in sim.particles.iter().zip(&clone.particles) {
+            assert_eq!(a.pos, b.pos);
+            assert_eq!(a.vel, b.vel);
+        }
+
+        // The default, empty universe can be stepped too.
+        let mut empty = Sim::default();
+        empty.step(800.0, 600.0);
+        assert!(empty.particles.is_empty());
+    }
+
+    #[test]
+    fn reversing_retraces_steps() {
+        let mut sim = Sim::from_seed(
+            Settings {
+                particles: 100,
+                ..Settings::balanced()
+            },
+            5,
+        );
+        // Leapfrog is time-reversible, and friction would throw energy away.
+        sim.integrator = Integrator::Leapfrog;
+        sim.set_friction(0.0);
+        sim.wrap = true;
+        let start = sim.particles.clone();
+
+        for _ in 0..50 {
+            sim.step(800.0, 600.0);
+        }
+        sim.reverse();
+        for _ in 0..50 {
+            sim.step(800.0, 600.0);
+        }
+
+        // Floating-point error builds up a little, especially between
+        // particles that get close enough to repel each other strongly.
+        for (particle, start) in sim.particles.iter().zip(&start) {
+            let error = clip_to_pixels(particle.pos - start.pos, 800.0, 600.0);
+            assert!(error.length() < 0.05, "{error} pixels off");
+            let vel_error = particle.vel + start.vel;
+            assert!(vel_error.length() < 0.05, "{vel_error} pixels per step off");
+        }
+    }
+
+    #[test]
+    fn samples_force_field_on_grid() {
+        // A 4x4 grid over an 800x800 simulation has cells 200 pixels across.
+        // Put one particle in the middle of the cell at column 1, row 1.
+        let sim = UniverseBuilder::new(2)
+            .attraction(0, 1, 0.05)
+            .radii(0, 1, 10.0, 300.0)
+            .particle(1, vec2(-0.25, -0.25))
+            .build()
+            .unwrap();
+        let field = sim.sample_force_field(0, 4, 4, 800.0, 800.0);
+        assert_eq!(field.len(), 16);
+
+        let force = sim.force_between(0, 1, 200.0);
+        assert!(force > 0.0);
+        let at = |col: usize, row: usize| field[row * 4 + col];
+        // Right of the particle, and above it, the probe is pulled back towards it.
+        assert!((at(2, 1) - vec2(-force, 0.0)).length() < 1e-6);
+        assert!((at(1, 2) - vec2(0.0, -force)).length() < 1e-6);
+        // The particle itself exerts no force where it is, or out of range.
+        assert_eq!(at(1, 1), Vec2::ZERO);
+        assert_eq!(at(3, 1), Vec2::ZERO);
+        assert_eq!(at(1, 3), Vec2::ZERO);
+    }
+
+    #[test]
+    fn influence_radius_at_repel_distance_disables_attraction() {
+        let mut sim = UniverseBuilder::new(2)
+            .attraction(0, 1, 0.05)
+            .attraction(1, 0, 0.05)
+            .radii(0, 1, 20.0, 60.0)
+            .friction(0.0)
+            .particle(0, Vec2::ZERO)
+            .particle(1, pixels_to_clip(vec2(30.0, 0.0), 800.0, 600.0))
+            .build()
+            .unwrap();
+        assert!(sim.force_between(0, 1, 30.0) > 0.0);
+
+        sim.set_influence_radius(0, 1, 20.0);
+        for dist in [20.0, 25.0, 30.0, 45.0, 59.0, 80.0] {
+            assert_eq!(sim.force_between(0, 1, dist), 0.0, "{dist} pixels apart");
+            assert_eq!(sim.force_between(1, 0, dist), 0.0, "{dist} pixels apart");
+        }
+        // The repulsion is still there.
+        assert!(sim.force_between(0, 1, 10.0) < 0.0);
+
+        sim.step(800.0, 600.0);
+        assert_eq!(sim.particles[0].vel, Vec2::ZERO);
+        assert_eq!(sim.particles[1].vel, Vec2::ZERO);
+    }
+
+    #[test]
+    fn kind_offsets_index_rows_of_pair_props() {
+        assert_eq!(kind_offsets(3), [0, 3, 6]);
+        assert!(kind_offsets(0).is_empty());
+
+        let sims = [
+            Sim::from_seed(Settings::balanced(), 0),
+            Sim::from_seed(Settings::chaos(), 0),
+            UniverseBuilder::new(7).build().unwrap(),
+            Sim::default(),
+        ];
+        for sim in sims {
+            let kinds = sim.colors.len();
+            assert_eq!(sim.kind_offset.len(), kinds);
+            for a in 0..kinds {
+                for b in 0..kinds {
+                    assert_eq!(sim.kind_offset[a] + b, a * kinds + b);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn boundary_force_pushes_particles_back_smoothly() {
+        let (width, height) = (800.0, 600.0);
+        let clip_radius = RADIUS * 2.0 / width;
+        let boundary = BoundaryForce {
+            strength: 0.01,
+            thickness: 20.0,
+        };
+        let mut sim = UniverseBuilder::new(1)
+            .friction(0.0)
+            // The edge of this particle is 5 pixels from the right wall.
+            .particle(0, vec2(1.0 - clip_radius - 5.0 / 400.0, 0.0))
+            .particle(0, vec2(-0.5, 0.0))
+            .build()
+            .unwrap();
+        sim.boundary_force = Some(boundary);
+
+        let mut wrapping = sim.clone();
+        wrapping.wrap = true;
+        wrapping.step(width, height);
+        assert_eq!(wrapping.particles[0].vel, Vec2::ZERO);
+
+        sim.step(width, height);
+        // It's 15 pixels into the boundary layer.
+        assert!((sim.particles[0].vel.x + 15.0 * boundary.strength).abs() < 1e-5);
+        assert_eq!(sim.particles[0].vel.y, 0.0);
+        // Particles outside the boundary layer aren't affected.
+        assert_eq!(sim.particles[1].vel, Vec2::ZERO);
+
+        // A particle heading into the wall turns around before it reaches it,
+        // rather than bouncing off it.
+        let mut sim = UniverseBuilder::new(1)
+            .friction(0.0)
+            .particle(0, vec2(0.8, 0.0))
+            .build()
+            .unwrap();
+        sim.boundary_force = Some(boundary);
+        sim.particles[0].vel = vec2(1.0, 0.0);
+        let mut last_vel = 1.0;
+        for _ in 0..200 {
+            sim.step(width, height);
+            let vel = sim.particles[0].vel.x;
+            assert!(last_vel - vel <= boundary.strength * boundary.thickness + 1e-5);
+            assert!(sim.particles[0].pos.x < 1.0 - clip_radius);
+            last_vel = vel;
+        }
+        assert!(last_vel < 0.0, "particle didn't turn around");
+    }
+
+    #[test]
+    fn index_map_tracks_particles_across_sort() {
+        let positions = [
+            vec2(0.1, 0.0),
+            vec2(0.2, 0.0),
+            vec2(0.3, 0.0),
+            vec2(0.4, 0.0),
+        ];
+        let kinds = [2, 0, 1, 0];
+        let mut builder = UniverseBuilder::new(3);
+        for (&kind, &pos) in kinds.iter().zip(&positions) {
+            builder = builder.particle(kind, pos);
+        }
+        let sim = builder.build().unwrap();
+
+        let kinds_after: Vec<usize> = sim.particles.iter().map(|p| p.kind).collect();
+        assert_eq!(kinds_after, [0, 0, 1, 2]);
+        for (i, &new) in sim.particle_index_map().iter().enumerate() {
+            assert_eq!(sim.particles[new].pos, positions[i]);
+        }
+
+        // With `stable_order`, particles stay in the order they're generated in.
+        let mut sorted = Sim::from_seed(Settings::balanced(), 3);
+        let mut stable = sorted.clone();
+        stable.stable_order = true;
+        sorted.regenerate_particles(&mut StdRng::seed_from_u64(1));
+        stable.regenerate_particles(&mut StdRng::seed_from_u64(1));
+        assert!(stable
+            .particle_index_map()
+            .iter()
+            .enumerate()
+            .all(|(i, &new)| i == new));
+        assert!(stable.particles.windows(2).any(|w| w[0].kind > w[1].kind));
+        for (i, &new) in sorted.particle_index_map().iter().enumerate() {
+            assert_eq!(sorted.particles[new].pos, stable.particles[i].pos);
+        }
+    }
+
+    #[test]
+    fn leapfrog_follows_an_orbit_more_closely() {
+        let mut orbit = UniverseBuilder::new(1)
+            .attraction(0, 0, 0.02)
+            .radii(0, 0, DIAMETER, 100.0)
+            .friction(0.0)
+            .particle(0, vec2(-0.05, 0.0))
+            .particle(0, vec2(0.05, 0.0))
+            .build()
+            .unwrap();
+        orbit.particles[0].vel = vec2(0.0, -0.6);
+        orbit.particles[1].vel = vec2(0.0, 0.6);
+
+        let run = |integrator: Integrator, substeps: u32| {
+            let mut sim = orbit.clone();
+            sim.integrator = integrator;
+            for _ in 0..300 {
+                for _ in 0..substeps {
+                    sim.step_dt(800.0, 600.0, 1.0 / substeps as f32);
+                }
+            }
+            sim.particles
+        };
+        let exact = run(Integrator::Leapfrog, 64);
+        let error = |particles: Vec<Particle>| -> f32 {
+            particles
+                .iter()
+                .zip(&exact)
+                .map(|(p, exact)| clip_to_pixels(p.pos - exact.pos, 800.0, 600.0).length())
+                .sum()
+        };
+
+        let euler = error(run(Integrator::SemiImplicitEuler, 1));
+        let leapfrog = error(run(Integrator::Leapfrog, 1));
+        assert!(
+            leapfrog < euler / 2.0,
+            "{leapfrog} pixels off with leapfrog, {euler} with Euler"
+        );
+    }
+
+    #[test]
+    fn radial_distribution_is_relative_to_uniform() {
+        let (width, height) = (800.0, 600.0);
+        let mut sim = Sim::from_seed(
+            Settings {
+                particles: MAX_PARTICLES,
+                ..Settings::balanced()
+            },
+            0,
+        );
+        sim.wrap = true;
+        let mut rng = StdRng::seed_from_u64(0);
+        let bins = 10;
+        let mut total = vec![0.0; bins];
+        for _ in 0..10 {
+            for particle in &mut sim.particles {
+                particle.pos = vec2(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+            }
+            let g = sim.radial_distribution(bins, 200.0, width, height);
+            for (total, g) in total.iter_mut().zip(g) {
+                *total += g / 10.0;
+            }
+        }
+        for (bin, g) in total.iter().enumerate() {
+            assert!((g - 1.0).abs() < 0.15, "g = {g} in bin {bin}");
+        }
+
+        // On a grid, nothing is closer than the grid spacing.
+        let mut builder = UniverseBuilder::new(1).wrap(true);
+        for x in 0..20 {
+            for y in 0..15 {
+                let pixels = vec2(x as f32, y as f32) * 40.0 - vec2(400.0, 300.0);
+                builder = builder.particle(0, pixels_to_clip(pixels, width, height));
+            }
+        }
+        let g = builder
+            .build()
+            .unwrap()
+            .radial_distribution(10, 70.0, width, height);
+        // Bins are 7 pixels wide, so the nearest neighbours are in bin 5.
+        assert!(g[..5].iter().all(|&g| g == 0.0), "{g:?}");
+        assert!(g[5] > 1.0, "{g:?}");
+    }
+
+    #[test]
+    fn structured_matrices_follow_their_sign_patterns() {
+        let kinds = 5;
+        let attractions = |matrix_kind| {
+            let settings = Settings {
+                kinds,
+                matrix_kind,
+                ..Settings::balanced()
+            };
+            let sim = Sim::try_new(settings, &mut StdRng::seed_from_u64(0)).unwrap();
+            sim.pair_props
+                .iter()
+                .map(|props| props.attraction)
+                .collect::<Vec<_>>()
+        };
+
+        let chase = attractions(MatrixKind::Chase);
+        for a in 0..kinds {
+            for b in 0..kinds {
+                let attraction = chase[a * kinds + b];
+                if a == b || b == (a + kinds - 1) % kinds {
+                    // Each kind keeps away from its own kind and the one chasing it...
+                    assert!(attraction < 0.0, "{a} -> {b}: {attraction}");
+                } else if b == (a + 1) % kinds {
+                    // ...and chases the next one along.
+                    assert!(attraction > 0.0, "{a} -> {b}: {attraction}");
+                } else {
+                    assert_eq!(attraction, 0.0, "{a} -> {b}");
+                }
+            }
+        }
+
+        let snake = attractions(MatrixKind::Snake);
+        for a in 0..kinds {
+            for b in 0..kinds {
+                let attraction = snake[a * kinds + b];
+                if a == b || b == (a + 1) % kinds {
+                    assert!(attraction > 0.0, "{a} -> {b}: {attraction}");
+                } else {
+                    assert_eq!(attraction, 0.0, "{a} -> {b}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn skips_pairs_closer_than_min_distance() {
+        let (width, height) = (800.0, 600.0);
+        let speed_after_step = |dist: f32| {
+            let mut sim = UniverseBuilder::new(1)
+                .friction(0.0)
+                .particle(0, Vec2::ZERO)
+                .particle(0, pixels_to_clip(vec2(dist, 0.0), width, height))
+                .build()
+                .unwrap();
+            sim.min_distance_sq = 4.0;
+            sim.step(width, height);
+            sim.particles[0].vel.length()
+        };
+
+        // The cutoff is 2 pixels.
+        assert_eq!(speed_after_step(1.9), 0.0);
+        assert!(speed_after_step(2.1) > 0.0);
+    }
+
+    #[test]
+    fn exports_into_a_reused_buffer() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let sim = Sim::new(Settings::balanced(), &mut rng);
+        let kind_radii = vec![RADIUS; sim.colors.len()];
+
+        let mut buffer = Vec::with_capacity(MAX_PARTICLES);
+        buffer.push(GpuParticle::default());
+        let capacity = buffer.capacity();
+        let ptr = buffer.as_ptr();
+        sim.export_particles_into(&mut buffer, &kind_radii);
+
+        // `GpuParticle` isn't `PartialEq`, so compare the bytes sent to the GPU instead.
+        assert_eq!(
+            bytemuck::cast_slice::<_, u8>(&buffer),
+            bytemuck::cast_slice::<_, u8>(&sim.to_gpu_particles(&kind_radii))
+        );
+        assert_eq!(buffer.len(), sim.particles.len());
+        assert_eq!(buffer.capacity(), capacity);
+        assert_eq!(buffer.as_ptr(), ptr);
+    }
+
+    #[test]
+    fn removing_net_drift_keeps_momentum_near_zero() {
+        // Kind 0 chases kind 1 while kind 1 runs away, so the pair drifts off together.
+        let chase = UniverseBuilder::new(2)
+            .attraction(0, 1, 0.5)
+            .attraction(1, 0, -0.5)
+            .wrap(true)
+            .particle(0, vec2(-0.02, 0.0))
+            .particle(1, vec2(0.02, 0.0))
+            .build()
+            .unwrap();
+        let momentum_after_steps = |remove_net_drift: bool| {
+            let mut sim = chase.clone();
+            sim.remove_net_drift = remove_net_drift;
+            for _ in 0..500 {
+                sim.step(800.0, 600.0);
+            }
+            sim.particles.iter().map(|p| p.vel).sum::<Vec2>().length()
+        };
+
+        assert!(momentum_after_steps(false) > 0.1);
+        assert!(momentum_after_steps(true) < 1e-4);
+    }
+
+    #[test]
+    fn pinned_particles_attract_without_moving() {
+        let (width, height) = (800.0, 600.0);
+        let mut sim = UniverseBuilder::new(1)
+            .attraction(0, 0, 0.5)
+            .particle(0, Vec2::ZERO)
+            .particle(0, pixels_to_clip(vec2(30.0, 0.0), width, height))
+            .build()
+            .unwrap();
+        sim.pin(0);
+
+        let start = sim.particles[1].pos;
+        for _ in 0..20 {
+            sim.step(width, height);
+            assert_eq!(sim.particles[0].pos, Vec2::ZERO);
+            assert_eq!(sim.particles[0].vel, Vec2::ZERO);
+        }
+        assert!(sim.particles[1].pos.x < start.x);
+
+        sim.unpin(0);
+        sim.step(width, height);
+        assert_ne!(sim.particles[0].pos, Vec2::ZERO);
+    }
+
+    #[test]
+    fn unit_conversions_are_inverses() {
+        let (width, height) = (800.0, 600.0);
+        for v in [vec2(0.0, 0.0), vec2(1.0, -2.0), vec2(-350.5, 12.25)] {
+            let round_tripped = clip_to_pixels(pixels_to_clip(v, width, height), width, height);
+            assert!(
+                (round_tripped - v).length() < 1e-4,
+                "{v} became {round_tripped}"
+            );
+            let round_tripped = pixels_to_clip(clip_to_pixels(v, width, height), width, height);
+            assert!(
+                (round_tripped - v).length() < 1e-4,
+                "{v} became {round_tripped}"
+            );
+        }
+        // The whole of clip space is the size of the simulation.
+        assert_eq!(
+            clip_to_pixels(vec2(2.0, 2.0), width, height),
+            vec2(width, height)
+        );
+
+        // `clip_velocity` is how far a particle moves each step.
+        let mut sim = UniverseBuilder::new(1)
+            .friction(0.0)
+            .particle(0, Vec2::ZERO)
+            .build()
+            .unwrap();
+        sim.particles[0].vel = vec2(4.0, -3.0);
+        let clip_velocity = sim.clip_velocity(&sim.particles[0], width, height);
+        sim.step(width, height);
+        assert!((sim.particles[0].pos - clip_velocity).length() < 1e-6);
+    }
+
+    #[test]
+    fn bonds_mutually_attracted_close_pairs() {
+        let (width, height) = (800.0, 600.0);
+        let at = |x: f32, y: f32| pixels_to_clip(vec2(x, y), width, height);
+        let sim = UniverseBuilder::new(2)
+            .attraction(0, 0, 0.5)
+            .attraction(0, 1, 0.5)
+            .attraction(1, 0, -0.5)
+            .radii(0, 0, DIAMETER, 45.0)
+            .wrap(true)
+            // Close enough to each other.
+            .particle(0, at(0.0, 0.0))
+            .particle(0, at(30.0, 0.0))
+            // Too far away.
+            .particle(0, at(-100.0, 0.0))
+            // Close together across the edge.
+            .particle(0, at(-395.0, 200.0))
+            .particle(0, at(395.0, 200.0))
+            // Only attracted one way.
+            .particle(1, at(0.0, 30.0))
+            .build()
+            .unwrap();
+
+        assert_eq!(sim.bonds(width, height, usize::MAX), vec![(0, 1), (3, 4)]);
+        assert_eq!(sim.bonds(width, height, 1), vec![(0, 1)]);
+    }
+
+    #[test]
+    fn finds_neighbors_nearest_first() {
+        let sim = UniverseBuilder::new(1)
+            .wrap(true)
+            .particle(0, vec2(0.9, 0.0))
+            .particle(0, vec2(0.7, 0.0))
+            .particle(0, vec2(0.0, 0.0))
+            // Just across the edge from the first particle.
+            .particle(0, vec2(-0.95, 0.0))
+            .particle(0, vec2(0.9, 0.25))
+            .build()
+            .unwrap();
+
+        assert_eq!(sim.neighbors(0, 0.3), vec![3, 1, 4]);
+        assert_eq!(sim.neighbors(0, 0.18), vec![3]);
+        assert_eq!(sim.neighbors(2, 0.3), Vec::<usize>::new());
+
+        // Without wrapping, the edge is in the way.
+        let mut sim = sim;
+        sim.wrap = false;
+        assert_eq!(sim.neighbors(0, 0.3), vec![1, 4]);
+    }
+
+    #[test]
+    fn exports_only_visible_kinds() {
+        let sim = UniverseBuilder::new(3)
+            .particle(0, vec2(0.0, 0.0))
+            .particle(1, vec2(0.1, 0.0))
+            .particle(2, vec2(0.2, 0.0))
+            .particle(2, vec2(0.3, 0.0))
+            .particle(0, vec2(0.4, 0.0))
+            .build()
+            .unwrap();
+        let visible_kinds = BTreeSet::from([2]);
+        let mut buffer = [GpuParticle::default(); MAX_PARTICLES];
+        sim.export_particles(
+            &mut buffer,
+            &[RADIUS; 3],
+            None,
+            Some(&visible_kinds),
+            None,
+            None,
+        );
+
+        let drawn: Vec<Vec2> = buffer
+            .iter()
+            .filter(|particle| particle.scale > 0.0)
+            .map(|particle| particle.pos())
+            .collect();
+        assert_eq!(drawn, [vec2(0.2, 0.0), vec2(0.3, 0.0)]);
+    }
+
+    #[test]
+    fn adaptive_trails_fade_with_speed() {
+        let trails = AdaptiveTrails { full_speed: 2.0 };
+        assert_eq!(trails.alpha(Vec2::ZERO), 0.0);
+        assert_eq!(trails.alpha(vec2(0.6, -0.8)), 0.5);
+        assert_eq!(trails.alpha(vec2(0.0, 2.0)), 1.0);
+        assert_eq!(trails.alpha(vec2(30.0, 40.0)), 1.0);
+        // A full speed of 0 would divide by 0, so every trail is fully shown instead.
+        assert_eq!(AdaptiveTrails { full_speed: 0.0 }.alpha(Vec2::ZERO), 1.0);
+
+        let mut sim = UniverseBuilder::new(1)
+            .particle(0, vec2(0.0, 0.0))
+            .particle(0, vec2(0.5, 0.0))
+            .build()
+            .unwrap();
+        sim.particles[1].vel = vec2(1.0, 0.0);
+        let mut buffer = [GpuParticle::default(); MAX_PARTICLES];
+        sim.export_particles(&mut buffer, &[RADIUS], None, None, Some(trails), None);
+        assert_eq!(buffer[0].trail_alpha, 0.0);
+        assert_eq!(buffer[1].trail_alpha, 0.5);
+    }
+
+    #[test]
+    fn counts_interacting_pairs() {
+        let (width, height) = (800.0, 600.0);
+        let stats_at = |dist: f32, integrator: Integrator| {
+            let mut sim = UniverseBuilder::new(1)
+                .radii(0, 0, DIAMETER, 45.0)
+                .particle(0, Vec2::ZERO)
+                .particle(0, pixels_to_clip(vec2(dist, 0.0), width, height))
+                .build()
+                .unwrap();
+            sim.integrator = integrator;
+            sim.step_with_stats(width, height, 1.0)
+        };
+
+        let evaluated = StepStats {
+            evaluated_pairs: 1,
+            skipped_pairs: 0,
+        };
+        let skipped = StepStats {
+            evaluated_pairs: 0,
+            skipped_pairs: 1,
+        };
+        assert_eq!(stats_at(30.0, Integrator::SemiImplicitEuler), evaluated);
+        assert_eq!(stats_at(60.0, Integrator::SemiImplicitEuler), skipped);
+        // Particles right on top of each other don't interact either.
+        assert_eq!(stats_at(0.0, Integrator::SemiImplicitEuler), skipped);
+        // Leapfrog works out the forces twice.
+        assert_eq!(
+            stats_at(30.0, Integrator::Leapfrog),
+            StepStats {
+                evaluated_pairs: 2,
+                skipped_pairs: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn bounding_box_contains_every_particle() {
+        let mut sim = UniverseBuilder::new(2)
+            .particle(0, vec2(-0.5, 0.25))
+            .particle(1, vec2(0.75, -0.5))
+            .particle(0, vec2(0.1, 0.3))
+            .build()
+            .unwrap();
+        assert_eq!(sim.bounding_box(), (vec2(-0.5, -0.5), vec2(0.75, 0.3)));
+
+        // Wrapping simulations don't have edges to put the box against.
+        sim.wrap = true;
+        assert_eq!(sim.bounding_box(), (vec2(-1.0, -1.0), vec2(1.0, 1.0)));
+
+        sim.wrap = false;
+        sim.particles.clear();
+        assert_eq!(sim.bounding_box(), (vec2(-1.0, -1.0), vec2(1.0, 1.0)));
+    }
+
+    #[test]
+    fn mixes_in_color_space() {
+        let black = LinSrgb::new(0.0, 0.0, 0.0);
+        let white = LinSrgb::new(1.0, 1.0, 1.0);
+        let close = |a: LinSrgb, b: LinSrgb| {
+            (a.red - b.red).abs() < 1e-3
+                && (a.green - b.green).abs() < 1e-3
+                && (a.blue - b.blue).abs() < 1e-3
+        };
+
+        for space in [ColorSpace::LinearRgb, ColorSpace::Oklab] {
+            assert!(close(space.mix(black, white, 0.0), black));
+            assert!(close(space.mix(black, white, 1.0), white));
+        }
+
+        // Halfway between black and white has half the light in linear RGB...
+        let linear = ColorSpace::LinearRgb.mix(black, white, 0.5);
+        assert!(close(linear, LinSrgb::new(0.5, 0.5, 0.5)));
+        // ...but Oklab's lightness is roughly the cube root of that, so its midpoint is much darker.
+        let oklab = ColorSpace::Oklab.mix(black, white, 0.5);
+        assert!(close(oklab, LinSrgb::new(0.125, 0.125, 0.125)), "{oklab:?}");
+    }
+
+    #[test]
+    fn attraction_matrix_round_trips() {
+        let mut sim = Sim::from_seed(Settings::balanced(), 0);
+        let kinds = sim.colors.len();
+        let matrix: Vec<f32> = (0..kinds * kinds)
+            .map(|i| (i as f32 - 10.0) * 0.5)
+            .collect();
+        sim.set_attraction_matrix(&matrix).unwrap();
+        for a in 0..kinds {
+            for b in 0..kinds {
+                assert_eq!(
+                    sim.pair_props[a * kinds + b].attraction,
+                    matrix[a * kinds + b]
+                );
+            }
+        }
+
+        // Invalid matrices are rejected without changing anything.
+        assert_eq!(
+            sim.set_attraction_matrix(&[0.0; 3]),
+            Err(SimError::WrongMatrixSize {
+                len: 3,
+                expected: kinds * kinds,
+            })
+        );
+        let mut invalid = matrix.clone();
+        invalid[1] = f32::NAN;
+        assert_eq!(
+            sim.set_attraction_matrix(&invalid),
+            Err(SimError::InvalidAttraction)
+        );
+        let attractions: Vec<f32> = sim
+            .pair_props
+            .iter()
+            .map(|props| props.attraction)
+            .collect();
+        assert_eq!(attractions, matrix);
+    }
+
+    #[test]
+    fn draws_faster_particles_on_top() {
+        let mut sim = UniverseBuilder::new(1)
+            .particle(0, vec2(0.0, 0.0))
+            .particle(0, vec2(0.1, 0.0))
+            .particle(0, vec2(0.2, 0.0))
+            .particle(0, vec2(0.3, 0.0))
+            .build()
+            .unwrap();
+        for (p, vel) in sim.particles.iter_mut().zip([
+            vec2(3.0, 0.0),
+            vec2(0.0, -1.0),
+            vec2(-4.0, 4.0),
+            vec2(0.5, 0.5),
+        ]) {
+            p.vel = vel;
+        }
+
+        let mut rng = StdRng::seed_from_u64(0);
+        assert_eq!(sim.draw_order(DrawOrder::ByKind, &mut rng), None);
+        let order = sim.draw_order(DrawOrder::BySpeed, &mut rng).unwrap();
+        assert_eq!(order, [3, 1, 0, 2]);
+
+        let mut buffer = [GpuParticle::default(); MAX_PARTICLES];
+        sim.export_particles(&mut buffer, &[RADIUS], None, None, None, Some(&order));
+        let speeds: Vec<f32> = buffer[..4]
+            .iter()
+            .map(|exported| {
+                let p = sim
+                    .particles
+                    .iter()
+                    .find(|p| p.pos == exported.pos())
+                    .unwrap();
+                p.vel.length()
+            })
+            .collect();
+        assert!(
+            speeds.windows(2).all(|pair| pair[0] <= pair[1]),
+            "{speeds:?}"
+        );
+
+        let mut shuffled = sim.draw_order(DrawOrder::Random, &mut rng).unwrap();
+        shuffled.sort();
+        assert_eq!(shuffled, [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn converges_to_equilibrium_distance() {
+        let (width, height) = (800.0, 600.0);
+        let mut sim = UniverseBuilder::new(1)
+            .attraction(0, 0, 0.3)
+            .radii(0, 0, 12.0, 50.0)
+            .friction(0.1)
+            .particle(0, Vec2::ZERO)
+            .particle(0, pixels_to_clip(vec2(45.0, 0.0), width, height))
+            .build()
+            .unwrap();
+        let equilibrium = sim.pair_props[0].equilibrium_distance().unwrap();
+        assert_eq!(equilibrium, 12.0);
+
+        for _ in 0..2000 {
+            sim.step(width, height);
+        }
+        let dist =
+            clip_to_pixels(sim.particles[1].pos - sim.particles[0].pos, width, height).length();
+        assert!(
+            (dist - equilibrium).abs() < 0.5,
+            "settled {dist} pixels apart rather than {equilibrium}"
+        );
+
+        // Pairs which never attract don't have one.
+        let repulsive = UniverseBuilder::new(1)
+            .attraction(0, 0, -0.3)
+            .build()
+            .unwrap();
+        assert_eq!(repulsive.pair_props[0].equilibrium_distance(), None);
+    }
+
+    #[test]
+    fn soft_flat_force_is_continuous() {
+        let mut sim = UniverseBuilder::new(1)
+            .attraction(0, 0, 0.5)
+            .radii(0, 0, 10.0, 50.0)
+            .flat_force(true)
+            .build()
+            .unwrap();
+        let eps = 1e-3;
+
+        // The hard flat force jumps at both edges...
+        assert!(sim.force_between(0, 0, 50.0 - eps) > 0.4);
+        assert!(sim.force_between(0, 0, 10.0 + eps) > 0.4);
+
+        // ...but the soft one ramps down to nothing.
+        sim.flat_force_shape = FlatForce::Soft { edge_fraction: 0.2 };
+        assert!(sim.force_between(0, 0, 50.0 - eps).abs() < 1e-3);
+        assert_eq!(sim.force_between(0, 0, 50.0), 0.0);
+        assert!(sim.force_between(0, 0, 10.0 + eps).abs() < 1e-3);
+        assert!(sim.force_between(0, 0, 10.0 - eps).abs() < 1e-3);
+        // It's still flat in the middle.
+        assert_eq!(sim.force_between(0, 0, 20.0), 0.5);
+        assert_eq!(sim.force_between(0, 0, 40.0), 0.5);
+        // Halfway through the ramp, it's at half strength.
+        assert!((sim.force_between(0, 0, 46.0) - 0.25).abs() < 1e-4);
+    }
+
+    #[test]
+    fn inverting_attraction_negates_matrix() {
+        for settings in [Settings::balanced(), Settings::cells(), Settings::gliders()] {
+            let sim = Sim::from_seed(settings.clone(), 3);
+            let inverted = Sim::from_seed(
+                Settings {
+                    invert_attraction: true,
+                    ..settings
+                },
+                3,
+            );
+            assert_eq!(inverted.pair_props.len(), sim.pair_props.len());
+            for (props, inverted) in sim.pair_props.iter().zip(&inverted.pair_props) {
+                assert_eq!(inverted.attraction, -props.attraction);
+                assert_eq!(inverted.repel_distance, props.repel_distance);
+                assert_eq!(inverted.influence_radius, props.influence_radius);
+            }
+        }
+    }
+
+    #[test]
+    fn ages_particles_each_step() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut sim = UniverseBuilder::new(2)
+            .particle(0, vec2(-0.5, 0.0))
+            .particle(1, vec2(0.5, 0.0))
+            .build()
+            .unwrap();
+        assert!(sim.particles.iter().all(|p| p.age == 0));
+        for _ in 0..3 {
+            sim.step(800.0, 600.0);
+        }
+        assert!(sim.particles.iter().all(|p| p.age == 3));
+
+        // New particles start from scratch.
+        sim.spawn_schedule = Some(SpawnSchedule {
+            target: 3,
+            rate: 1.0,
+            kind_weights: None,
+        });
+        sim.apply_spawn_schedule(&mut rng);
+        assert_eq!(sim.particles.len(), 3);
+        assert_eq!(sim.particles[2].age, 0);
+        sim.step(800.0, 600.0);
+        let ages: Vec<u32> = sim.particles.iter().map(|p| p.age).collect();
+        assert_eq!(ages, [4, 4, 1]);
+
+        sim.regenerate_particles(&mut rng);
+        assert!(sim.particles.iter().all(|p| p.age == 0));
+
+        // Older particles are drawn darker.
+        let red = LinSrgb::new(1.0, 0.0, 0.0);
+        let mode = ColorMode::ByAge { max_age: 100 };
+        let color_at = |age| {
+            mode.color(
+                &Particle {
+                    age,
+                    ..sim.particles[0]
+                },
+                red,
+            )
+            .red
+        };
+        assert_eq!(color_at(0), 1.0);
+        assert!((color_at(50) - 0.625).abs() < 1e-6);
+        assert!((color_at(100) - 0.25).abs() < 1e-6);
+        assert!((color_at(1000) - 0.25).abs() < 1e-6);
+    }
+
+    #[test]
+    fn shockwave_pushes_nearby_particles_outwards() {
+        let (width, height) = (800.0, 600.0);
+        let at = |x: f32, y: f32| pixels_to_clip(vec2(x, y), width, height);
+        let mut sim = UniverseBuilder::new(1)
+            .wrap(true)
+            .particle(0, at(20.0, 0.0))
+            .particle(0, at(0.0, -50.0))
+            .particle(0, at(150.0, 0.0))
+            .particle(0, at(-30.0, 0.0))
+            // Close to a shockwave near the other edge.
+            .particle(0, at(-390.0, 0.0))
+            .build()
+            .unwrap();
+        sim.pin(3);
+
+        sim.shockwave(Vec2::ZERO, 100.0, 10.0, width, height);
+        let vels: Vec<Vec2> = sim.particles.iter().map(|p| p.vel).collect();
+        // The kick falls off linearly with distance.
+        assert!((vels[0] - vec2(8.0, 0.0)).length() < 1e-4, "{}", vels[0]);
+        assert!((vels[1] - vec2(0.0, -5.0)).length() < 1e-4, "{}", vels[1]);
+        // Particles outside the radius, and pinned particles, aren't affected.
+        assert_eq!(vels[2], Vec2::ZERO);
+        assert_eq!(vels[3], Vec2::ZERO);
+        assert_eq!(vels[4], Vec2::ZERO);
+
+        // The push goes the short way around the edge.
+        sim.shockwave(at(390.0, 0.0), 100.0, 10.0, width, height);
+        assert!(sim.particles[4].vel.x > 0.0);
+    }
+
+    #[test]
+    fn adjusts_palette_brightness_and_saturation() {
+        for color in PaletteSpec::Full.colors(6) {
+            let hsv = Hsv::from_color(color);
+
+            let dimmed = Hsv::from_color(adjust_color(color, 0.5, 1.0));
+            assert!((dimmed.value - 0.5 * hsv.value).abs() < 1e-5);
+            assert!((dimmed.saturation - hsv.saturation).abs() < 1e-5);
+
+            let faded = Hsv::from_color(adjust_color(color, 1.0, 0.5));
+            assert!((faded.saturation - 0.5 * hsv.saturation).abs() < 1e-5);
+            assert!((faded.value - hsv.value).abs() < 1e-5);
+
+            // The defaults leave colors alone.
+            let unchanged = adjust_color(color, 1.0, 1.0);
+            assert!((unchanged.red - color.red).abs() < 1e-5);
+            assert!((unchanged.green - color.green).abs() < 1e-5);
+            assert!((unchanged.blue - color.blue).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn disc_arena_reflects_off_curved_wall() {
+        let size = 600.0;
+        let max_dist = 1.0 - RADIUS / (0.5 * size);
+
+        let mut sim = UniverseBuilder::new(1)
+            .friction(0.0)
+            .particle(0, vec2(0.95, 0.0))
+            .build()
+            .unwrap();
+        sim.arena = Arena::Disc;
+        sim.particles[0].vel = vec2(30.0, 10.0);
+        sim.step(size, size);
+        // It's put back on the edge, and only the part of its velocity going through the wall
+        // is reflected.
+        let p = sim.particles[0];
+        assert!((p.pos.length() - max_dist).abs() < 1e-5);
+        let normal = p.pos.normalize();
+        let expected = vec2(30.0, 10.0) - 2.0 * vec2(30.0, 10.0).dot(normal) * normal;
+        assert!((p.vel - expected).length() < 1e-4, "{}", p.vel);
+        assert!(p.vel.dot(normal) < 0.0);
+
+        // Particles flying around in all directions never leave it.
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut sim = Sim::new(Settings::chaos(), &mut rng);
+        sim.arena = Arena::Disc;
+        for p in sim.particles.iter_mut() {
+            p.vel = vec2(rng.gen_range(-20.0..20.0), rng.gen_range(-20.0..20.0));
+        }
+        for _ in 0..200 {
+            sim.step(size, size);
+            for p in &sim.particles {
+                assert!(p.pos.length() <= max_dist + 1e-5, "{} left the disc", p.pos);
+            }
+        }
+    }
+
+    #[test]
+    fn step_default_uses_size() {
+        let mut sim = Sim::from_seed(Settings::balanced(), 0);
+        let mut expected = sim.clone();
+        sim.set_size(640.0, 480.0);
+        for _ in 0..10 {
+            sim.step_default();
+            expected.step(640.0, 480.0);
+        }
+        for (p, expected) in sim.particles.iter().zip(&expected.particles) {
+            assert_eq!(p.pos, expected.pos);
+            assert_eq!(p.vel, expected.vel);
+        }
+    }
+
+    #[test]
+    fn two_band_force_has_expected_signs() {
+        let (width, height) = (800.0, 600.0);
+        let pair_at = |dist: f32| {
+            let mut sim = UniverseBuilder::new(1)
+                .attraction(0, 0, 0.4)
+                .radii(0, 0, 10.0, 40.0)
+                .friction(0.0)
+                .particle(0, Vec2::ZERO)
+                .particle(0, pixels_to_clip(vec2(dist, 0.0), width, height))
+                .build()
+                .unwrap();
+            sim.set_outer_band(
+                0,
+                0,
+                Some(ForceBand {
+                    attraction: -0.3,
+                    start: 60.0,
+                    end: 100.0,
+                }),
+            );
+            sim
+        };
+        let sign = |force: f32| {
+            if force > 0.0 {
+                1
+            } else if force < 0.0 {
+                -1
+            } else {
+                0
+            }
+        };
+
+        // Repelled up close, attracted at medium range, nothing in the gap, then repelled again
+        // by the outer band, and nothing past that.
+        for (dist, expected) in [(5.0, -1), (25.0, 1), (50.0, 0), (80.0, -1), (120.0, 0)] {
+            let mut sim = pair_at(dist);
+            assert_eq!(
+                sign(sim.force_between(0, 0, dist)),
+                expected,
+                "at {dist} pixels"
+            );
+
+            // `step` agrees: being attracted moves the first particle towards the second, in
+            // the positive x direction.
+            sim.step(width, height);
+            assert_eq!(sign(sim.particles[0].vel.x), expected, "at {dist} pixels");
+        }
+    }
+
+    #[test]
+    fn evolving_changes_matrix_within_bounds() {
+        let attractions = |sim: &Sim| -> Vec<f32> {
+            sim.pair_props
+                .iter()
+                .map(|props| props.attraction)
+                .collect()
+        };
+        let config = EvolveConfig {
+            interval: 5.0,
+            blend_rate: 0.4,
+        };
+
+        let mut sim = Sim::from_seed(Settings::balanced(), 0);
+        let original = attractions(&sim);
+        let min = original.iter().copied().fold(f32::INFINITY, f32::min);
+        let max = original.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        let mut still = sim.clone();
+        sim.set_evolve(Some(config), 1);
+        let mut again = sim.clone();
+
+        for _ in 0..300 {
+            sim.step(800.0, 600.0);
+            again.step(800.0, 600.0);
+            still.step(800.0, 600.0);
+            assert!(attractions(&sim)
+                .iter()
+                .all(|attraction| (min..=max).contains(attraction)));
+        }
+
+        let changed = attractions(&sim)
+            .iter()
+            .zip(&original)
+            .filter(|(a, b)| a != b)
+            .count();
+        // Pairs are picked at random, so some get picked more than once.
+        assert!(
+            changed > original.len() / 4,
+            "only {changed} attractions changed"
+        );
+        // It's deterministic, and doesn't happen without being turned on.
+        assert_eq!(attractions(&again), attractions(&sim));
+        assert_eq!(attractions(&still), original);
+    }
+
+    #[test]
+    fn ramps_particle_count_to_target() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut sim = Sim::from_seed(Settings::balanced(), 0);
+        sim.particles.truncate(10);
+        sim.spawn_schedule = Some(SpawnSchedule {
+            target: 60,
+            rate: 2.5,
+            kind_weights: None,
+        });
+
+        for step in 1..=30 {
+            sim.step(800.0, 600.0);
+            sim.apply_spawn_schedule(&mut rng);
+            // Fractions of a particle carry over to the next step.
+            let expected = (10 + (2.5 * step as f32) as usize).min(60);
+            assert_eq!(sim.particles.len(), expected, "after {step} steps");
+        }
+
+        // Weights pick which kinds get added.
+        let kinds = sim.colors.len();
+        let mut weights = vec![0.0; kinds];
+        weights[1] = 1.0;
+        sim.particles.truncate(10);
+        sim.spawn_schedule = Some(SpawnSchedule {
+            target: 20,
+            rate: 10.0,
+            kind_weights: Some(weights),
+        });
+        sim.apply_spawn_schedule(&mut rng);
+        assert_eq!(sim.particles.len(), 20);
+        assert!(sim.particles[10..].iter().all(|p| p.kind == 1));
+    }
+
+    #[test]
+    fn leapfrog_conserves_energy() {
+        let (width, height) = (800.0, 600.0);
+        let orbit = UniverseBuilder::new(1)
+            .attraction(0, 0, 0.02)
+            .radii(0, 0, DIAMETER, 100.0)
+            .friction(0.0)
+            .particle(0, vec2(-0.05, 0.0))
+            .particle(0, vec2(0.05, 0.0))
+            .build()
+            .unwrap();
+        let energy = |sim: &Sim| sim.kinetic_energy() + sim.potential_energy(width, height);
+
+        let max_drift = |integrator: Integrator| {
+            let mut sim = orbit.clone();
+            sim.integrator = integrator;
+            sim.particles[0].vel = vec2(0.0, -0.6);
+            sim.particles[1].vel = vec2(0.0, 0.6);
+            let start = energy(&sim);
+
+            let mut max_drift: f32 = 0.0;
+            for _ in 0..600 {
+                sim.step_dt(width, height, 0.5);
+                max_drift = max_drift.max((energy(&sim) - start).abs());
+            }
+            max_drift / start.abs()
+        };
+
+        let leapfrog = max_drift(Integrator::Leapfrog);
+        let euler = max_drift(Integrator::SemiImplicitEuler);
+        assert!(leapfrog < 1e-3, "energy drifted by {}%", leapfrog * 100.0);
+        assert!(
+            leapfrog < euler / 10.0,
+            "energy drifted by {}% with leapfrog, {}% with Euler",
+            leapfrog * 100.0,
+            euler * 100.0
+        );
+    }
+
+    #[test]
+    fn computes_max_influence_per_kind() {
+        let mut sim = UniverseBuilder::new(3)
+            .radii(0, 0, DIAMETER, 20.0)
+            .radii(0, 1, DIAMETER, 40.0)
+            .radii(0, 2, DIAMETER, 15.0)
+            .radii(1, 1, DIAMETER, 25.0)
+            .radii(1, 2, DIAMETER, 70.0)
+            .radii(2, 2, DIAMETER, 30.0)
+            .build()
+            .unwrap();
+        let max_influences =
+            |sim: &Sim| -> Vec<f32> { (0..3).map(|k| sim.max_influence_for_kind(k)).collect() };
+        assert_eq!(max_influences(&sim), [40.0, 70.0, 70.0]);
+
+        sim.set_outer_band(
+            2,
+            2,
+            Some(ForceBand {
+                attraction: 0.1,
+                start: 80.0,
+                end: 90.0,
+            }),
+        );
+        sim.set_influence_radius(0, 0, 50.0);
+        assert_eq!(max_influences(&sim), [50.0, 70.0, 90.0]);
+
+        // Permuting the kinds moves their maximums along with them.
+        sim.permute_kinds(&[1, 2, 0]);
+        let brute_force: Vec<f32> = (0..3)
+            .map(|k| {
+                (0..3)
+                    .map(|other| sim.pair_props[k * 3 + other].cutoff_sq.sqrt())
+                    .fold(0.0, f32::max)
+            })
+            .collect();
+        assert_eq!(max_influences(&sim), brute_force);
+        let mut sorted = max_influences(&sim);
+        sorted.sort_by(f32::total_cmp);
+        assert_eq!(sorted, [50.0, 70.0, 90.0]);
+    }
+
+    #[test]
+    fn rust_source_reproduces_universe() {
+        let mut sim = UniverseBuilder::new(2)
+            .colors(vec![
+                LinSrgb::new(1.0, 0.5, 0.0),
+                LinSrgb::new(0.1, 0.2, 0.3),
+            ])
+            .attraction(0, 1, 1.0 / 3.0)
+            .attraction(1, 0, -0.7)
+            .radii(1, 1, 15.5, 60.0)
+            .friction(0.02)
+            .wrap(true)
+            .particle(0, Vec2::ZERO)
+            .particle(1, Vec2::X)
+            .particle(1, Vec2::Y)
+            .build()
+            .unwrap();
+        sim.set_outer_band(
+            1,
+            0,
+            Some(ForceBand {
+                attraction: -0.25,
+                start: 60.0,
+                end: 90.5,
+            }),
+        );
+        let source = r#"UniverseDef {
+    particles: 3,
+    friction: 0.02,
+    flat_force: false,
+    wrap: true,
+    colors: &[
+        [1.0, 0.5, 0.0],
+        [0.1, 0.2, 0.3],
+    ],
+    attractions: &[
+        0.0, 0.33333334,
+        -0.7, 0.0,
+    ],
+    repel_distances: &[
+        10.0, 10.0,
+        10.0, 15.5,
+    ],
+    influence_radii: &[
+        45.0, 45.0,
+        45.0, 60.0,
+    ],
+    outer_bands: &[
+        (1, 0, ForceBand { attraction: -0.25, start: 60.0, end: 90.5 }),
+    ],
+}"#;
+        assert_eq!(sim.to_rust_source(), source);
+
+        // The same source, pasted in as code.
+        #[rustfmt::skip]
+        const DEF: UniverseDef = UniverseDef {
+            particles: 3,
+            friction: 0.02,
+            flat_force: false,
+            wrap: true,
+            colors: &[
+                [1.0, 0.5, 0.0],
+                [0.1, 0.2, 0.3],
+            ],
+            attractions: &[
+                0.0, 0.33333334,
+                -0.7, 0.0,
+            ],
+            repel_distances: &[
+                10.0, 10.0,
+                10.0, 15.5,
+            ],
+            influence_radii: &[
+                45.0, 45.0,
+                45.0, 60.0,
+            ],
+            outer_bands: &[
+                (1, 0, ForceBand { attraction: -0.25, start: 60.0, end: 90.5 }),
+            ],
+        };
+        let loaded = Sim::from_def(&DEF, &mut StdRng::seed_from_u64(0)).unwrap();
+        for (loaded, props) in loaded.pair_props.iter().zip(&sim.pair_props) {
+            assert_eq!(loaded.attraction, props.attraction);
+            assert_eq!(loaded.repel_distance, props.repel_distance);
+            assert_eq!(loaded.influence_radius, props.influence_radius);
+            assert_eq!(loaded.outer_band, props.outer_band);
+        }
+        assert_eq!(loaded.colors, sim.colors);
+        assert_eq!(loaded.friction, sim.friction);
+        assert_eq!(loaded.wrap, sim.wrap);
+        assert_eq!(loaded.particles.len(), sim.particles.len());
+    }
+
+    #[test]
+    fn friction_follows_schedule() {
+        let schedule = FrictionSchedule {
+            start: 0.01,
+            end: 0.21,
+            duration: 10.0,
+        };
+        assert_eq!(schedule.friction_at(0.0), 0.01);
+        assert!((schedule.friction_at(5.0) - 0.11).abs() < 1e-6);
+        assert_eq!(schedule.friction_at(10.0), 0.21);
+        // It stays at either end outside of the schedule.
+        assert_eq!(schedule.friction_at(-5.0), 0.01);
+        assert_eq!(schedule.friction_at(20.0), 0.21);
+        let instant = FrictionSchedule {
+            duration: 0.0,
+            ..schedule
+        };
+        assert_eq!(instant.friction_at(0.0), 0.21);
+
+        let mut sim = UniverseBuilder::new(1)
+            .particle(0, Vec2::ZERO)
+            .build()
+            .unwrap();
+        sim.set_friction_schedule(Some(schedule));
+        assert_eq!(sim.friction, 0.01);
+        for step in 1..=10 {
+            sim.step(100.0, 100.0);
+            assert!((sim.friction - schedule.friction_at(step as f32)).abs() < 1e-6);
+        }
+        assert_eq!(sim.friction, 0.21);
+        // The schedule ends once it reaches `end`, and stops overriding
+        // `friction`.
+        assert_eq!(sim.friction_schedule(), None);
+        sim.friction = 0.5;
+        sim.step(100.0, 100.0);
+        assert_eq!(sim.friction, 0.5);
+    }
+
+    #[test]
+    fn counts_particles_of_each_kind() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut sim = UniverseBuilder::new(3)
+            .particle(2, vec2(0.1, 0.0))
+            .particle(0, vec2(0.2, 0.0))
+            .particle(1, vec2(0.3, 0.0))
+            .particle(2, vec2(0.4, 0.0))
+            .particle(0, vec2(0.5, 0.0))
+            .particle(2, vec2(0.6, 0.0))
+            .build()
+            .unwrap();
+        assert_eq!(sim.kind_counts(), [2, 1, 3]);
+
+        // New particles are placed with kinds weighted 1:0:3.
+        sim.spawn_schedule = Some(SpawnSchedule {
+            target: 406,
+            rate: 400.0,
+            kind_weights: Some(vec![1.0, 0.0, 3.0]),
+        });
+        sim.apply_spawn_schedule(&mut rng);
+        let counts = sim.kind_counts();
+        assert_eq!(counts.iter().sum::<usize>(), sim.particles.len());
+        assert_eq!(counts[1], 1);
+        let share = (counts[2] - 3) as f32 / 400.0;
+        assert!(
+            (0.7..0.8).contains(&share),
+            "{share} of new particles were kind 2"
+        );
+    }
+}
@@ -0,0 +1,298 @@
+//! Importing universes from the JSON exported by the popular particle life web
+//! simulator, which describes them as a list of `colors` and a matrix of
+//! `rules` between them.
+//!
+//! Fields other than `colors` and `rules` are ignored.
+
+use std::borrow::ToOwned;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use std::string::String;
+use std::vec::Vec;
+
+use palette::LinSrgb;
+use palette::Srgb;
+use rand::rngs::OsRng;
+use rand::Rng;
+use serde_json::Value;
+
+use crate::sim::Particle;
+use crate::sim::Sim;
+use crate::sim::SimError;
+use crate::sim::UniverseBuilder;
+use crate::sim::DIAMETER;
+
+/// The amount a rule from the web simulator is multiplied by to get an
+/// attraction. Its rules range from -1 to 1, and negative rules attract.
+pub const WEB_ATTRACTION_SCALE: f32 = -0.1;
+/// The influence radius of every pair of kinds in an imported universe, in
+/// pixels. The web simulator has one fixed range rather than a radius per
+/// pair, and this is its default.
+pub const WEB_INFLUENCE_RADIUS: f32 = 80.0;
+/// The number of particles placed in an imported universe.
+pub const WEB_PARTICLES: usize = 400;
+
+impl Sim {
+    /// Creates a universe from the JSON config exported by the particle life
+    /// web simulator, placing its particles randomly.
+    ///
+    /// `colors` is a list of CSS colors, either hex (`"#ff8000"`) or named
+    /// (`"green"`), with one kind created for each. `rules` gives the force
+    /// between each pair of kinds, either as a matrix where `rules[a][b]` is
+    /// the force on kind `a` from kind `b`, or as an object of objects indexed
+    /// by the colors' names in the same order. Each rule becomes an attraction
+    /// of `rule * WEB_ATTRACTION_SCALE`.
+    ///
+    /// The web simulator doesn't have per-pair radii, so every pair is given a
+    /// repel distance of `DIAMETER` and an influence radius of
+    /// `WEB_INFLUENCE_RADIUS`. Everything else is as with `UniverseBuilder`,
+    /// with `WEB_PARTICLES` particles.
+    pub fn from_web_config(json: &str) -> Result<Self, WebConfigError> {
+        Self::from_web_config_with_rng(json, &mut OsRng)
+    }
+
+    /// Like `from_web_config`, but places the particles using `rng`, so that
+    /// the same config and seed always give the same universe.
+    pub fn from_web_config_with_rng<R: Rng>(
+        json: &str,
+        rng: &mut R,
+    ) -> Result<Self, WebConfigError> {
+        let config: Value = serde_json::from_str(json).map_err(WebConfigError::Json)?;
+
+        let Some(color_names) = config.get("colors").and_then(Value::as_array) else {
+            return Err(WebConfigError::InvalidField("colors"));
+        };
+        let color_names = color_names
+            .iter()
+            .map(|name| name.as_str().ok_or(WebConfigError::InvalidField("colors")))
+            .collect::<Result<Vec<_>, _>>()?;
+        let colors = color_names
+            .iter()
+            .map(|&name| parse_css_color(name))
+            .collect::<Result<Vec<_>, _>>()?;
+        let kinds = colors.len();
+
+        let rules = match config.get("rules") {
+            Some(Value::Array(rows)) => {
+                let mut rules = Vec::with_capacity(kinds * kinds);
+                for row in rows {
+                    let Value::Array(row) = row else {
+                        return Err(WebConfigError::InvalidField("rules"));
+                    };
+                    if row.len() != kinds {
+                        return Err(WebConfigError::InvalidField("rules"));
+                    }
+                    for rule in row {
+                        rules.push(rule_value(rule)?);
+                    }
+                }
+                rules
+            }
+            Some(rules @ Value::Object(_)) => {
+                let mut matrix = Vec::with_capacity(kinds * kinds);
+                for &a in &color_names {
+                    for &b in &color_names {
+                        let rule = rules.get(a).and_then(|row| row.get(b)).ok_or_else(|| {
+                            WebConfigError::MissingRule {
+                                a: a.to_owned(),
+                                b: b.to_owned(),
+                            }
+                        })?;
+                        matrix.push(rule_value(rule)?);
+                    }
+                }
+                matrix
+            }
+            _ => return Err(WebConfigError::InvalidField("rules")),
+        };
+
+        let mut builder = UniverseBuilder::new(kinds)
+            .colors(colors)
+            .attraction_matrix(
+                rules
+                    .iter()
+                    .map(|rule| rule * WEB_ATTRACTION_SCALE)
+                    .collect(),
+            );
+        for a in 0..kinds {
+            for b in a..kinds {
+                builder = builder.radii(a, b, DIAMETER, WEB_INFLUENCE_RADIUS);
+            }
+        }
+        if kinds > 0 {
+            for _ in 0..WEB_PARTICLES {
+                let particle = Particle::generate(kinds, rng);
+                builder = builder.particle(particle.kind, particle.pos);
+            }
+        }
+        builder.build().map_err(WebConfigError::Sim)
+    }
+}
+
+/// Returns the value of a rule, or an error if it isn't a number.
+fn rule_value(rule: &Value) -> Result<f32, WebConfigError> {
+    rule.as_f64()
+        .map(|rule| rule as f32)
+        .ok_or(WebConfigError::InvalidField("rules"))
+}
+
+/// Parses a CSS color, either in hex or by name, into linear RGB.
+fn parse_css_color(color: &str) -> Result<LinSrgb, WebConfigError> {
+    let color = color.trim();
+    let srgb = if color.starts_with('#') {
+        Srgb::<u8>::from_str(color).ok()
+    } else {
+        palette::named::from_str(&color.to_ascii_lowercase())
+    };
+    srgb.map(|srgb| srgb.into_linear())
+        .ok_or_else(|| WebConfigError::UnknownColor(color.to_owned()))
+}
+
+/// The reasons `Sim::from_web_config` can fail.
+#[derive(Debug)]
+pub enum WebConfigError {
+    /// The config wasn't valid JSON.
+    Json(serde_json::Error),
+    /// A field was missing or had the wrong type, or a row of `rules` didn't
+    /// have a rule for each color.
+    InvalidField(&'static str),
+    /// `rules` was an object, but didn't have a rule for kind `a` from kind
+    /// `b`.
+    MissingRule { a: String, b: String },
+    /// A color wasn't a hex color or a CSS color name.
+    UnknownColor(String),
+    /// The universe was rejected by `UniverseBuilder::build`.
+    Sim(SimError),
+}
+
+impl fmt::Display for WebConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WebConfigError::Json(e) => write!(f, "invalid JSON: {e}"),
+            WebConfigError::InvalidField(field) => write!(f, "missing or invalid {field:?}"),
+            WebConfigError::MissingRule { a, b } => {
+                write!(f, "there is no rule for {a:?} from {b:?}")
+            }
+            WebConfigError::UnknownColor(color) => write!(f, "unknown color {color:?}"),
+            WebConfigError::Sim(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for WebConfigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            WebConfigError::Json(e) => Some(e),
+            WebConfigError::Sim(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+
+    fn import(json: &str) -> Result<Sim, WebConfigError> {
+        Sim::from_web_config_with_rng(json, &mut StdRng::seed_from_u64(0))
+    }
+
+    fn attractions(sim: &Sim) -> Vec<f32> {
+        sim.pair_props
+            .iter()
+            .map(|props| props.attraction)
+            .collect()
+    }
+
+    #[test]
+    fn imports_matrix() {
+        let sim = import(
+            r##"{
+                "atoms": {"count": 200, "radius": 1},
+                "colors": ["green", "#FF0000", "Blue"],
+                "rules": [[-0.5, 0.25, 0], [1, 0, -1e-1], [0.3, -0.2, 0.1]],
+                "viscosity": 0.7,
+                "note": "ignored \"fields\" é"
+            }"##,
+        )
+        .unwrap();
+
+        let rules = [-0.5, 0.25, 0.0, 1.0, 0.0, -0.1, 0.3, -0.2, 0.1];
+        let expected: Vec<f32> = rules
+            .iter()
+            .map(|rule| rule * WEB_ATTRACTION_SCALE)
+            .collect();
+        assert_eq!(attractions(&sim), expected);
+        // Negative rules attract.
+        assert!(sim.pair_props[0].attraction > 0.0);
+
+        assert_eq!(
+            sim.colors,
+            [
+                Srgb::new(0u8, 128, 0).into_linear(),
+                LinSrgb::new(1.0, 0.0, 0.0),
+                LinSrgb::new(0.0, 0.0, 1.0),
+            ]
+        );
+        for props in &sim.pair_props {
+            assert_eq!(props.repel_distance, DIAMETER);
+            assert_eq!(props.influence_radius, WEB_INFLUENCE_RADIUS);
+        }
+        assert_eq!(sim.particles.len(), WEB_PARTICLES);
+    }
+
+    #[test]
+    fn imports_named_rules() {
+        let sim = import(
+            r#"{
+                "colors": ["red", "blue"],
+                "rules": {
+                    "blue": {"red": 0.5, "blue": 0},
+                    "red": {"blue": -1, "red": 1}
+                }
+            }"#,
+        )
+        .unwrap();
+        let expected: Vec<f32> = [1.0, -1.0, 0.5, 0.0]
+            .iter()
+            .map(|rule| rule * WEB_ATTRACTION_SCALE)
+            .collect();
+        assert_eq!(attractions(&sim), expected);
+    }
+
+    #[test]
+    fn rejects_invalid_configs() {
+        assert!(matches!(
+            import(r#"{"colors": ["red"], "rules": [[1]"#),
+            Err(WebConfigError::Json(_))
+        ));
+        assert!(matches!(
+            import(r#"{"rules": [[1]]}"#),
+            Err(WebConfigError::InvalidField("colors"))
+        ));
+        assert!(matches!(
+            import(r#"{"colors": ["nope"], "rules": [[1]]}"#),
+            Err(WebConfigError::UnknownColor(_))
+        ));
+        assert!(matches!(
+            import(r#"{"colors": ["red", "blue"], "rules": [[1, 2]]}"#),
+            Err(WebConfigError::Sim(SimError::WrongMatrixSize { .. }))
+        ));
+        assert!(matches!(
+            import(r#"{"colors": ["red", "blue"], "rules": [[1], [2, 3]]}"#),
+            Err(WebConfigError::InvalidField("rules"))
+        ));
+        assert!(matches!(
+            import(r#"{"colors": ["red"], "rules": [["1"]]}"#),
+            Err(WebConfigError::InvalidField("rules"))
+        ));
+        assert!(matches!(
+            import(r#"{"colors": ["red", "blue"], "rules": {"red": {"red": 1}}}"#),
+            Err(WebConfigError::MissingRule { .. })
+        ));
+    }
+}
@@ -0,0 +1,388 @@
+//! A C-compatible interface to the simulation, for embedding it in programs
+//! written in other languages.
+//!
+//! This is only built with the `ffi` feature. To get a library C can link
+//! against, build with
+//! `cargo rustc --lib --release --features ffi --crate-type cdylib` (or
+//! `staticlib`).
+//!
+//! The matching C declarations are:
+//!
+//! ```c
+//! typedef struct PlSim PlSim;
+//!
+//! typedef struct {
+//!     size_t particles;
+//!     size_t kinds;
+//!     float attraction_mean;
+//!     float attraction_std_dev;
+//!     float repel_distance_min;
+//!     float repel_distance_max;
+//!     float influence_radius_min;
+//!     float influence_radius_max;
+//!     float friction;
+//!     bool flat_force;
+//!     bool wrap;
+//!     float width;
+//!     float height;
+//!     uint64_t seed;
+//! } PlSettings;
+//!
+//! #define PL_OK 0
+//! #define PL_NULL_POINTER -1
+//! #define PL_INVALID_SETTINGS -2
+//! #define PL_BUFFER_TOO_SMALL -3
+//! #define PL_PANICKED -4
+//!
+//! int pl_sim_new(const PlSettings *settings, PlSim **out);
+//! int pl_sim_step(PlSim *sim, uint32_t steps);
+//! size_t pl_sim_particle_count(const PlSim *sim);
+//! int pl_sim_read_particles(const PlSim *sim, float *positions, uint32_t *kinds, size_t len);
+//! void pl_sim_free(PlSim *sim);
+//! ```
+//!
+//! None of these functions panic across the boundary; they return one of the
+//! `PL_*` error codes instead.
+
+use std::ffi::c_int;
+use std::panic;
+use std::panic::AssertUnwindSafe;
+
+use glam::vec2;
+use rand_distr::Normal;
+
+use crate::settings::MatrixKind;
+use crate::settings::Settings;
+use crate::sim::clip_to_pixels;
+use crate::sim::PaletteSpec;
+use crate::sim::Sim;
+
+/// The function succeeded.
+pub const PL_OK: c_int = 0;
+/// A pointer which mustn't be null was null.
+pub const PL_NULL_POINTER: c_int = -1;
+/// The settings passed to `pl_sim_new` were invalid.
+pub const PL_INVALID_SETTINGS: c_int = -2;
+/// The buffers passed to `pl_sim_read_particles` were too small to fit every
+/// particle.
+pub const PL_BUFFER_TOO_SMALL: c_int = -3;
+/// The simulation panicked; it shouldn't be used again, apart from freeing it.
+pub const PL_PANICKED: c_int = -4;
+
+/// An opaque handle to a simulation, created by `pl_sim_new`.
+pub struct PlSim {
+    sim: Sim,
+}
+
+/// The settings to create a simulation with, flattened out so that they can
+/// be filled in from C.
+///
+/// Attractions are sampled from a normal distribution, and repel distances and
+/// influence radii from uniform distributions, as in `Settings`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlSettings {
+    pub particles: usize,
+    pub kinds: usize,
+    pub attraction_mean: f32,
+    pub attraction_std_dev: f32,
+    pub repel_distance_min: f32,
+    pub repel_distance_max: f32,
+    pub influence_radius_min: f32,
+    pub influence_radius_max: f32,
+    pub friction: f32,
+    pub flat_force: bool,
+    pub wrap: bool,
+    /// The size of the simulation in pixels.
+    pub width: f32,
+    pub height: f32,
+    /// The seed to generate the attractions and particles from, as in
+    /// `Sim::from_seed`.
+    pub seed: u64,
+}
+
+impl PlSettings {
+    /// Converts these into `Settings`, or returns `None` if they're invalid.
+    fn to_settings(self) -> Option<Settings> {
+        let valid_range =
+            |min: f32, max: f32| min.is_finite() && max.is_finite() && 0.0 <= min && min <= max;
+        let valid = valid_range(self.repel_distance_min, self.repel_distance_max)
+            && valid_range(self.influence_radius_min, self.influence_radius_max)
+            && self.width > 0.0
+            && self.height > 0.0;
+        if !valid {
+            return None;
+        }
+
+        Some(Settings {
+            particles: self.particles,
+            kinds: self.kinds,
+            attraction_distr: Normal::new(self.attraction_mean, self.attraction_std_dev).ok()?,
+            matrix_kind: MatrixKind::Random,
+            invert_attraction: false,
+            repel_distance_range: self.repel_distance_min..=self.repel_distance_max,
+            influence_radius_range: self.influence_radius_min..=self.influence_radius_max,
+            friction: self.friction,
+            flat_force: self.flat_force,
+            palette: PaletteSpec::Full,
+        })
+    }
+}
+
+/// Runs `f`, turning any panic into `PL_PANICKED`.
+fn catch_panic(f: impl FnOnce() -> c_int) -> c_int {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(PL_PANICKED)
+}
+
+/// Creates a new simulation from `settings`, storing a handle to it in `out`.
+///
+/// The handle must be freed with `pl_sim_free`.
+///
+/// # Safety
+///
+/// `settings` must be null or point to a valid `PlSettings`, and `out` must be
+/// null or valid to write a pointer to.
+#[no_mangle]
+pub unsafe extern "C" fn pl_sim_new(settings: *const PlSettings, out: *mut *mut PlSim) -> c_int {
+    if settings.is_null() || out.is_null() {
+        return PL_NULL_POINTER;
+    }
+    let settings = *settings;
+
+    catch_panic(|| {
+        let Some(sim_settings) = settings.to_settings() else {
+            return PL_INVALID_SETTINGS;
+        };
+        let Ok(mut sim) = Sim::try_from_seed(sim_settings, settings.seed) else {
+            return PL_INVALID_SETTINGS;
+        };
+        sim.wrap = settings.wrap;
+        sim.set_size(settings.width, settings.height);
+
+        *out = Box::into_raw(Box::new(PlSim { sim }));
+        PL_OK
+    })
+}
+
+/// Advances `sim` by `steps` steps.
+///
+/// # Safety
+///
+/// `sim` must be null or a handle returned by `pl_sim_new` which hasn't been
+/// freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn pl_sim_step(sim: *mut PlSim, steps: u32) -> c_int {
+    let Some(sim) = sim.as_mut() else {
+        return PL_NULL_POINTER;
+    };
+
+    catch_panic(|| {
+        for _ in 0..steps {
+            sim.sim.step_default();
+        }
+        PL_OK
+    })
+}
+
+/// Returns the number of particles in `sim`, or 0 if `sim` is null.
+///
+/// # Safety
+///
+/// `sim` must be null or a handle returned by `pl_sim_new` which hasn't been
+/// freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn pl_sim_particle_count(sim: *const PlSim) -> usize {
+    sim.as_ref().map_or(0, |sim| sim.sim.particles.len())
+}
+
+/// Copies the particles of `sim` out into `positions` and `kinds`, which have
+/// room for `len` particles.
+///
+/// Each particle's position takes up two floats in `positions`, x then y, in
+/// pixels from the top-left corner of the simulation. Either buffer can be
+/// null to skip it. If there are more than `len` particles, nothing is written
+/// and `PL_BUFFER_TOO_SMALL` is returned; see `pl_sim_particle_count`.
+///
+/// # Safety
+///
+/// `sim` must be null or a handle returned by `pl_sim_new` which hasn't been
+/// freed yet, and `positions` and `kinds` must each be null or valid to write
+/// `2 * len` and `len` values to respectively.
+#[no_mangle]
+pub unsafe extern "C" fn pl_sim_read_particles(
+    sim: *const PlSim,
+    positions: *mut f32,
+    kinds: *mut u32,
+    len: usize,
+) -> c_int {
+    let Some(sim) = sim.as_ref() else {
+        return PL_NULL_POINTER;
+    };
+    let particles = &sim.sim.particles;
+    if particles.len() > len {
+        return PL_BUFFER_TOO_SMALL;
+    }
+    // `pl_sim_new` always sets the size.
+    let size = sim.sim.size.unwrap_or_default();
+
+    if !positions.is_null() {
+        let positions = std::slice::from_raw_parts_mut(positions, 2 * particles.len());
+        for (pos, particle) in positions.chunks_exact_mut(2).zip(particles) {
+            // Clip space has its origin in the middle and y going up.
+            let offset = vec2(particle.pos.x + 1.0, 1.0 - particle.pos.y);
+            let pixels = clip_to_pixels(offset, size.x, size.y);
+            pos.copy_from_slice(&pixels.to_array());
+        }
+    }
+    if !kinds.is_null() {
+        let kinds = std::slice::from_raw_parts_mut(kinds, particles.len());
+        for (kind, particle) in kinds.iter_mut().zip(particles) {
+            *kind = particle.kind as u32;
+        }
+    }
+
+    PL_OK
+}
+
+/// Frees `sim`. Does nothing if `sim` is null.
+///
+/// # Safety
+///
+/// `sim` must be null or a handle returned by `pl_sim_new` which hasn't been
+/// freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn pl_sim_free(sim: *mut PlSim) {
+    if !sim.is_null() {
+        drop(Box::from_raw(sim));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+
+    use super::*;
+
+    fn settings() -> PlSettings {
+        PlSettings {
+            particles: 200,
+            kinds: 4,
+            attraction_mean: 0.0,
+            attraction_std_dev: 0.04,
+            repel_distance_min: 2.0,
+            repel_distance_max: 20.0,
+            influence_radius_min: 20.0,
+            influence_radius_max: 70.0,
+            friction: 0.05,
+            flat_force: false,
+            wrap: true,
+            width: 640.0,
+            height: 480.0,
+            seed: 5,
+        }
+    }
+
+    #[test]
+    fn creates_steps_reads_and_frees() {
+        unsafe {
+            let mut sim = ptr::null_mut();
+            assert_eq!(pl_sim_new(&settings(), &mut sim), PL_OK);
+            assert!(!sim.is_null());
+            assert_eq!(pl_sim_step(sim, 10), PL_OK);
+
+            let len = pl_sim_particle_count(sim);
+            assert_eq!(len, 200);
+            let mut positions = vec![f32::NAN; 2 * len];
+            let mut kinds = vec![u32::MAX; len];
+            assert_eq!(
+                pl_sim_read_particles(sim, positions.as_mut_ptr(), kinds.as_mut_ptr(), len),
+                PL_OK
+            );
+            for pos in positions.chunks_exact(2) {
+                assert!((0.0..=640.0).contains(&pos[0]), "{pos:?}");
+                assert!((0.0..=480.0).contains(&pos[1]), "{pos:?}");
+            }
+            assert!(kinds.iter().all(|&kind| kind < 4));
+
+            assert_eq!(
+                pl_sim_read_particles(sim, positions.as_mut_ptr(), ptr::null_mut(), len - 1),
+                PL_BUFFER_TOO_SMALL
+            );
+            // Either buffer can be skipped.
+            assert_eq!(
+                pl_sim_read_particles(sim, ptr::null_mut(), ptr::null_mut(), len),
+                PL_OK
+            );
+
+            pl_sim_free(sim);
+        }
+    }
+
+    #[test]
+    fn uses_the_same_seeds_as_sim() {
+        let settings = settings();
+        let expected = Sim::from_seed(settings.to_settings().unwrap(), settings.seed);
+        unsafe {
+            let mut sim = ptr::null_mut();
+            assert_eq!(pl_sim_new(&settings, &mut sim), PL_OK);
+            let mut kinds = vec![0; pl_sim_particle_count(sim)];
+            assert_eq!(
+                pl_sim_read_particles(sim, ptr::null_mut(), kinds.as_mut_ptr(), kinds.len()),
+                PL_OK
+            );
+            let expected: Vec<u32> = expected.particles.iter().map(|p| p.kind as u32).collect();
+            assert_eq!(kinds, expected);
+            pl_sim_free(sim);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_arguments() {
+        unsafe {
+            let mut sim = ptr::null_mut();
+            assert_eq!(pl_sim_new(ptr::null(), &mut sim), PL_NULL_POINTER);
+            assert_eq!(pl_sim_new(&settings(), ptr::null_mut()), PL_NULL_POINTER);
+
+            let invalid = [
+                PlSettings {
+                    kinds: 0,
+                    ..settings()
+                },
+                PlSettings {
+                    width: 0.0,
+                    ..settings()
+                },
+                PlSettings {
+                    repel_distance_min: 30.0,
+                    repel_distance_max: 10.0,
+                    ..settings()
+                },
+                PlSettings {
+                    influence_radius_min: -5.0,
+                    influence_radius_max: 5.0,
+                    ..settings()
+                },
+                PlSettings {
+                    attraction_std_dev: -1.0,
+                    ..settings()
+                },
+            ];
+            for settings in invalid {
+                assert_eq!(
+                    pl_sim_new(&settings, &mut sim),
+                    PL_INVALID_SETTINGS,
+                    "{settings:?}"
+                );
+            }
+            assert!(sim.is_null());
+
+            assert_eq!(pl_sim_step(ptr::null_mut(), 1), PL_NULL_POINTER);
+            assert_eq!(pl_sim_particle_count(ptr::null()), 0);
+            assert_eq!(
+                pl_sim_read_particles(ptr::null(), ptr::null_mut(), ptr::null_mut(), 0),
+                PL_NULL_POINTER
+            );
+            pl_sim_free(ptr::null_mut());
+        }
+    }
+}
@@ -0,0 +1,311 @@
+//! Saving the gallery of universes kept in `State::gallery` to a file, so that
+//! it's still there the next time the app is opened.
+//!
+//! The gallery is written as JSON, with a `slots` array holding either `null`
+//! or a saved universe for each slot. Each universe is stored as the fields of
+//! a `UniverseDef` along with the slot's name, so like with `Sim::from_def`,
+//! its particles are placed randomly again when it's loaded.
+
+use std::error::Error;
+use std::fmt;
+
+use rand::Rng;
+use serde_json::json;
+use serde_json::Value;
+
+use crate::sim::ForceBand;
+use crate::sim::Sim;
+use crate::sim::SimError;
+use crate::sim::UniverseDef;
+use crate::GALLERY_SLOTS;
+
+/// A universe saved into a slot of the gallery.
+#[derive(Clone)]
+pub struct GallerySlot {
+    /// The name shown when the universe is recalled.
+    pub name: String,
+    pub sim: Sim,
+}
+
+/// Writes out the universes in `gallery` as JSON, to be read back with
+/// `gallery_from_json`.
+pub fn gallery_to_json(gallery: &[Option<GallerySlot>]) -> String {
+    let slots: Vec<Value> = gallery
+        .iter()
+        .map(|slot| match slot {
+            Some(slot) => json!({
+                "name": slot.name,
+                "universe": universe_to_json(&slot.sim),
+            }),
+            None => Value::Null,
+        })
+        .collect();
+    serde_json::to_string_pretty(&json!({ "slots": slots }))
+        .expect("a JSON value should always serialize")
+}
+
+/// Reads a gallery written by `gallery_to_json`, placing the particles of each
+/// universe using `rng`.
+///
+/// Any slots past the end of `slots` are left empty, and any past
+/// `GALLERY_SLOTS` are ignored.
+pub fn gallery_from_json<R: Rng>(
+    json: &str,
+    rng: &mut R,
+) -> Result<[Option<GallerySlot>; GALLERY_SLOTS], GalleryError> {
+    let gallery: Value = serde_json::from_str(json).map_err(GalleryError::Json)?;
+    let Some(slots) = gallery.get("slots").and_then(Value::as_array) else {
+        return Err(GalleryError::InvalidField("slots"));
+    };
+
+    let mut result: [Option<GallerySlot>; GALLERY_SLOTS] = Default::default();
+    for (i, slot) in slots.iter().take(GALLERY_SLOTS).enumerate() {
+        if slot.is_null() {
+            continue;
+        }
+        let Some(name) = slot.get("name").and_then(Value::as_str) else {
+            return Err(GalleryError::InvalidField("name"));
+        };
+        let Some(universe) = slot.get("universe") else {
+            return Err(GalleryError::InvalidField("universe"));
+        };
+        result[i] = Some(GallerySlot {
+            name: name.to_owned(),
+            sim: universe_from_json(universe, rng)?,
+        });
+    }
+    Ok(result)
+}
+
+/// Returns the fields of the `UniverseDef` describing `sim` as a JSON object.
+fn universe_to_json(sim: &Sim) -> Value {
+    let kinds = sim.colors.len();
+    let colors: Vec<[f32; 3]> = sim
+        .colors
+        .iter()
+        .map(|color| [color.red, color.green, color.blue])
+        .collect();
+    let outer_bands: Vec<Value> = sim
+        .pair_props
+        .iter()
+        .enumerate()
+        .filter_map(|(i, props)| {
+            let band = props.outer_band?;
+            Some(json!({
+                "a": i / kinds,
+                "b": i % kinds,
+                "attraction": band.attraction,
+                "start": band.start,
+                "end": band.end,
+            }))
+        })
+        .collect();
+
+    json!({
+        "particles": sim.particles.len(),
+        "friction": sim.friction,
+        "flat_force": sim.flat_force,
+        "wrap": sim.wrap,
+        "colors": colors,
+        "attractions": sim.pair_props.iter().map(|props| props.attraction).collect::<Vec<_>>(),
+        "repel_distances": sim.pair_props.iter().map(|props| props.repel_distance).collect::<Vec<_>>(),
+        "influence_radii": sim.pair_props.iter().map(|props| props.influence_radius).collect::<Vec<_>>(),
+        "outer_bands": outer_bands,
+    })
+}
+
+/// Recreates the universe written by `universe_to_json` with `Sim::from_def`.
+fn universe_from_json<R: Rng>(universe: &Value, rng: &mut R) -> Result<Sim, GalleryError> {
+    let particles = universe
+        .get("particles")
+        .and_then(Value::as_u64)
+        .ok_or(GalleryError::InvalidField("particles"))?;
+    let friction = float(universe.get("friction"), "friction")?;
+    let flat_force = bool_field(universe, "flat_force")?;
+    let wrap = bool_field(universe, "wrap")?;
+
+    let colors = array(universe, "colors")?
+        .iter()
+        .map(|color| match color.as_array().map(Vec::as_slice) {
+            Some([red, green, blue]) => Ok([
+                float(Some(red), "colors")?,
+                float(Some(green), "colors")?,
+                float(Some(blue), "colors")?,
+            ]),
+            _ => Err(GalleryError::InvalidField("colors")),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let attractions = floats(universe, "attractions")?;
+    let repel_distances = floats(universe, "repel_distances")?;
+    let influence_radii = floats(universe, "influence_radii")?;
+
+    let outer_bands = array(universe, "outer_bands")?
+        .iter()
+        .map(|band| {
+            let kind = |field| {
+                band.get(field)
+                    .and_then(Value::as_u64)
+                    .map(|kind| kind as usize)
+                    .ok_or(GalleryError::InvalidField("outer_bands"))
+            };
+            Ok((
+                kind("a")?,
+                kind("b")?,
+                ForceBand {
+                    attraction: float(band.get("attraction"), "outer_bands")?,
+                    start: float(band.get("start"), "outer_bands")?,
+                    end: float(band.get("end"), "outer_bands")?,
+                },
+            ))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let def = UniverseDef {
+        particles: particles as usize,
+        friction,
+        flat_force,
+        wrap,
+        colors: &colors,
+        attractions: &attractions,
+        repel_distances: &repel_distances,
+        influence_radii: &influence_radii,
+        outer_bands: &outer_bands,
+    };
+    Sim::from_def(&def, rng).map_err(GalleryError::Sim)
+}
+
+/// Returns `value` as an `f32`, or an error about `field` if it isn't a
+/// number.
+fn float(value: Option<&Value>, field: &'static str) -> Result<f32, GalleryError> {
+    value
+        .and_then(Value::as_f64)
+        .map(|value| value as f32)
+        .ok_or(GalleryError::InvalidField(field))
+}
+
+fn bool_field(universe: &Value, field: &'static str) -> Result<bool, GalleryError> {
+    universe
+        .get(field)
+        .and_then(Value::as_bool)
+        .ok_or(GalleryError::InvalidField(field))
+}
+
+fn array<'a>(universe: &'a Value, field: &'static str) -> Result<&'a [Value], GalleryError> {
+    universe
+        .get(field)
+        .and_then(Value::as_array)
+        .map(Vec::as_slice)
+        .ok_or(GalleryError::InvalidField(field))
+}
+
+fn floats(universe: &Value, field: &'static str) -> Result<Vec<f32>, GalleryError> {
+    array(universe, field)?
+        .iter()
+        .map(|value| float(Some(value), field))
+        .collect()
+}
+
+/// The reasons `gallery_from_json` can fail.
+#[derive(Debug)]
+pub enum GalleryError {
+    /// The gallery wasn't valid JSON.
+    Json(serde_json::Error),
+    /// A field was missing or had the wrong type.
+    InvalidField(&'static str),
+    /// A saved universe was rejected by `Sim::from_def`.
+    Sim(SimError),
+}
+
+impl fmt::Display for GalleryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GalleryError::Json(e) => write!(f, "invalid JSON: {e}"),
+            GalleryError::InvalidField(field) => write!(f, "missing or invalid {field:?}"),
+            GalleryError::Sim(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for GalleryError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            GalleryError::Json(e) => Some(e),
+            GalleryError::Sim(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::settings::Settings;
+
+    #[test]
+    fn round_trips() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut sim = Sim::new(Settings::balanced(), &mut rng);
+        sim.wrap = true;
+        sim.set_outer_band(
+            0,
+            1,
+            Some(ForceBand {
+                attraction: -0.25,
+                start: 60.0,
+                end: 90.5,
+            }),
+        );
+        let mut gallery: [Option<GallerySlot>; GALLERY_SLOTS] = Default::default();
+        gallery[3] = Some(GallerySlot {
+            name: "balanced \"favorite\"".to_owned(),
+            sim,
+        });
+
+        let json = gallery_to_json(&gallery);
+        let loaded = gallery_from_json(&json, &mut rng).unwrap();
+        for (slot, loaded) in gallery.iter().zip(&loaded) {
+            match (slot, loaded) {
+                (None, None) => {}
+                (Some(slot), Some(loaded)) => {
+                    assert_eq!(loaded.name, slot.name);
+                    // This includes every field of the universe's `UniverseDef`.
+                    assert_eq!(loaded.sim.to_rust_source(), slot.sim.to_rust_source());
+                }
+                _ => panic!("slot wasn't round-tripped"),
+            }
+        }
+        // Loading it again gives the same JSON back.
+        assert_eq!(gallery_to_json(&loaded), json);
+    }
+
+    #[test]
+    fn rejects_invalid_galleries() {
+        let mut rng = StdRng::seed_from_u64(0);
+        assert!(matches!(
+            gallery_from_json("[", &mut rng),
+            Err(GalleryError::Json(_))
+        ));
+        assert!(matches!(
+            gallery_from_json("{}", &mut rng),
+            Err(GalleryError::InvalidField("slots"))
+        ));
+        assert!(matches!(
+            gallery_from_json(r#"{"slots": [{"universe": {}}]}"#, &mut rng),
+            Err(GalleryError::InvalidField("name"))
+        ));
+        assert!(matches!(
+            gallery_from_json(
+                r#"{"slots": [null, {"name": "empty", "universe": {
+                    "particles": 0, "friction": 0.05, "flat_force": false, "wrap": false,
+                    "colors": [], "attractions": [], "repel_distances": [],
+                    "influence_radii": [], "outer_bands": []
+                }}]}"#,
+                &mut rng
+            ),
+            Err(GalleryError::Sim(SimError::NoKinds))
+        ));
+    }
+}
@@ -0,0 +1,317 @@
+//! Running the simulation without a window and printing statistics about it,
+//! for batch experiments and CI.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::io::Write;
+use std::time::Duration;
+
+use web_time::Instant;
+
+use crate::settings::Settings;
+use crate::sim::Sim;
+use crate::sim::SimError;
+
+/// The size (in pixels) of the simulation when it's run headless.
+pub const HEADLESS_WIDTH: f32 = 1280.0;
+pub const HEADLESS_HEIGHT: f32 = 720.0;
+
+/// The default number of frames between each line of statistics.
+pub const DEFAULT_STATS_INTERVAL: u64 = 100;
+
+/// The options for a headless run, as given on the command line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeadlessArgs {
+    /// The name of the preset to run (see `Settings::by_name`).
+    pub preset: String,
+    /// The number of frames to run for. Each frame is a single step.
+    pub frames: u64,
+    /// Whether to print statistics as CSV every `stats_interval` frames, rather
+    /// than just a summary at the end.
+    pub stats: bool,
+    pub stats_interval: u64,
+    /// The seed to create the universe from, which gives the same universe as
+    /// entering it in the window. A random one is picked if this is `None`.
+    pub seed: Option<u64>,
+}
+
+impl HeadlessArgs {
+    /// Parses command-line arguments (not including the program's name),
+    /// returning `Ok(None)` without looking at the rest if `--headless` isn't
+    /// one of them.
+    ///
+    /// The arguments are `--headless`, `--preset <name>`, `--frames <n>`,
+    /// `--stats`, `--stats-interval <n>` and `--seed <n>`.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Option<Self>, HeadlessError> {
+        let args: Vec<String> = args.into_iter().collect();
+        if !args.iter().any(|arg| arg == "--headless") {
+            return Ok(None);
+        }
+
+        let mut parsed = Self {
+            preset: String::from("balanced"),
+            frames: 1000,
+            stats: false,
+            stats_interval: DEFAULT_STATS_INTERVAL,
+            seed: None,
+        };
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| HeadlessError::InvalidArgs(format!("{arg} needs a value")))
+            };
+            match arg.as_str() {
+                "--headless" => {}
+                "--stats" => parsed.stats = true,
+                "--preset" => parsed.preset = value()?,
+                "--frames" => parsed.frames = parse_number(&arg, &value()?)?,
+                "--stats-interval" => parsed.stats_interval = parse_number(&arg, &value()?)?,
+                "--seed" => parsed.seed = Some(parse_number(&arg, &value()?)?),
+                _ => {
+                    return Err(HeadlessError::InvalidArgs(format!(
+                        "unknown argument {arg}"
+                    )))
+                }
+            }
+        }
+
+        if parsed.stats_interval == 0 {
+            return Err(HeadlessError::InvalidArgs(String::from(
+                "--stats-interval must be at least 1",
+            )));
+        }
+        Ok(Some(parsed))
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(arg: &str, value: &str) -> Result<T, HeadlessError> {
+    value
+        .parse()
+        .map_err(|_| HeadlessError::InvalidArgs(format!("invalid value for {arg}: {value}")))
+}
+
+/// The reasons a headless run can fail.
+#[derive(Debug)]
+pub enum HeadlessError {
+    /// The command-line arguments were invalid.
+    InvalidArgs(String),
+    /// There's no preset with this name.
+    UnknownPreset(String),
+    /// The preset's settings were rejected by `Sim::try_new`.
+    Sim(SimError),
+    /// Writing the statistics failed.
+    Io(io::Error),
+}
+
+impl fmt::Display for HeadlessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeadlessError::InvalidArgs(message) => f.write_str(message),
+            HeadlessError::UnknownPreset(name) => write!(f, "unknown preset {name:?}"),
+            HeadlessError::Sim(e) => e.fmt(f),
+            HeadlessError::Io(e) => write!(f, "failed to write statistics: {e}"),
+        }
+    }
+}
+
+impl Error for HeadlessError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            HeadlessError::Sim(e) => Some(e),
+            HeadlessError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for HeadlessError {
+    fn from(e: io::Error) -> Self {
+        HeadlessError::Io(e)
+    }
+}
+
+/// Statistics about the simulation after a frame of a headless run.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameStats {
+    /// The number of frames run so far.
+    pub frame: u64,
+    /// See `Sim::kinetic_energy`.
+    pub kinetic_energy: f32,
+    /// The average speed of the particles, in pixels per step.
+    pub mean_speed: f32,
+    /// See `Sim::count_clusters`.
+    pub clusters: usize,
+    /// The average time each step took since the last statistics.
+    pub step_time: Duration,
+}
+
+impl FrameStats {
+    /// The header of the CSV `write_csv` writes rows of.
+    pub const CSV_HEADER: &'static str = "frame,kinetic_energy,mean_speed,clusters,step_time_ms";
+
+    /// Measures `sim` after `frame` frames, which took `step_time` per step.
+    pub fn measure(sim: &Sim, frame: u64, step_time: Duration) -> Self {
+        let total_speed: f32 = sim.particles.iter().map(|p| p.vel.length()).sum();
+        Self {
+            frame,
+            kinetic_energy: sim.kinetic_energy(),
+            mean_speed: total_speed / sim.particles.len().max(1) as f32,
+            clusters: sim.count_clusters(HEADLESS_WIDTH, HEADLESS_HEIGHT),
+            step_time,
+        }
+    }
+
+    /// Writes these statistics as a row of CSV.
+    pub fn write_csv(&self, out: &mut impl Write) -> io::Result<()> {
+        writeln!(
+            out,
+            "{},{},{},{},{:.4}",
+            self.frame,
+            self.kinetic_energy,
+            self.mean_speed,
+            self.clusters,
+            self.step_time.as_secs_f64() * 1000.0,
+        )
+    }
+}
+
+/// Runs a universe created from `args` for `args.frames` frames without a
+/// window, writing statistics about it to `out`.
+///
+/// With `args.stats`, this writes a CSV header followed by a row of
+/// `FrameStats` every `args.stats_interval` frames, plus one for the last
+/// frame if it doesn't land on the interval. Otherwise, it just writes a
+/// one-line summary at the end.
+pub fn run(args: &HeadlessArgs, out: &mut impl Write) -> Result<(), HeadlessError> {
+    let settings = Settings::by_name(&args.preset)
+        .ok_or_else(|| HeadlessError::UnknownPreset(args.preset.clone()))?;
+    let seed = args.seed.unwrap_or_else(rand::random);
+    log::info!("running {} with seed {seed}", args.preset);
+
+    let mut sim = Sim::try_from_seed(settings, seed).map_err(HeadlessError::Sim)?;
+    // Life rules and spawn schedules need randomness as the simulation runs too.
+    let mut rng = Sim::step_rng(seed);
+
+    if args.stats {
+        writeln!(out, "{}", FrameStats::CSV_HEADER)?;
+    }
+
+    let start = Instant::now();
+    let mut interval_start = start;
+    let mut interval_frames = 0;
+    for frame in 1..=args.frames {
+        sim.step(HEADLESS_WIDTH, HEADLESS_HEIGHT);
+        sim.apply_life_rules(HEADLESS_WIDTH, HEADLESS_HEIGHT, &mut rng);
+        sim.apply_spawn_schedule(&mut rng);
+        interval_frames += 1;
+
+        if args.stats && (frame % args.stats_interval == 0 || frame == args.frames) {
+            let step_time = interval_start.elapsed() / interval_frames;
+            FrameStats::measure(&sim, frame, step_time).write_csv(out)?;
+            interval_start = Instant::now();
+            interval_frames = 0;
+        }
+    }
+
+    if !args.stats {
+        let elapsed = start.elapsed();
+        let step_time = elapsed.div_f64(args.frames.max(1) as f64);
+        writeln!(
+            out,
+            "ran {} frames of {} in {elapsed:?} ({step_time:?} per step)",
+            args.frames, args.preset,
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Result<Option<HeadlessArgs>, HeadlessError> {
+        HeadlessArgs::parse(args.iter().map(|&arg| arg.to_owned()))
+    }
+
+    fn run_to_string(args: &HeadlessArgs) -> String {
+        let mut out = Vec::new();
+        run(args, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn parses_args() {
+        assert_eq!(args(&["--preset", "cells"]).unwrap(), None);
+        assert_eq!(
+            args(&[
+                "--headless",
+                "--preset",
+                "cells",
+                "--frames",
+                "50",
+                "--stats",
+                "--stats-interval",
+                "5",
+                "--seed",
+                "7",
+            ])
+            .unwrap(),
+            Some(HeadlessArgs {
+                preset: String::from("cells"),
+                frames: 50,
+                stats: true,
+                stats_interval: 5,
+                seed: Some(7),
+            })
+        );
+        assert!(args(&["--headless", "--frames"]).is_err());
+        assert!(args(&["--headless", "--frames", "many"]).is_err());
+        assert!(args(&["--headless", "--stats-interval", "0"]).is_err());
+        assert!(args(&["--headless", "--bogus"]).is_err());
+    }
+
+    #[test]
+    fn writes_a_stat_line_per_interval() {
+        let mut args = args(&["--headless", "--frames", "25", "--stats", "--seed", "1"])
+            .unwrap()
+            .unwrap();
+        args.stats_interval = 10;
+        let out = run_to_string(&args);
+        let lines: Vec<&str> = out.lines().collect();
+        // The header, frames 10 and 20, and the last frame.
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], FrameStats::CSV_HEADER);
+        let frames: Vec<&str> = lines[1..]
+            .iter()
+            .map(|line| line.split(',').next().unwrap())
+            .collect();
+        assert_eq!(frames, ["10", "20", "25"]);
+
+        // The last frame isn't repeated if it lands on the interval.
+        args.frames = 20;
+        assert_eq!(run_to_string(&args).lines().count(), 3);
+    }
+
+    #[test]
+    fn writes_a_summary_without_stats() {
+        let args = args(&["--headless", "--frames", "3", "--seed", "1"])
+            .unwrap()
+            .unwrap();
+        let out = run_to_string(&args);
+        assert_eq!(out.lines().count(), 1);
+        assert!(out.starts_with("ran 3 frames of balanced"));
+    }
+
+    #[test]
+    fn rejects_unknown_presets() {
+        let mut args = args(&["--headless"]).unwrap().unwrap();
+        args.preset = String::from("nonexistent");
+        assert!(matches!(
+            run(&args, &mut Vec::new()),
+            Err(HeadlessError::UnknownPreset(_))
+        ));
+    }
+}
@@ -0,0 +1,151 @@
+//! A bounded record of past simulation steps, which can be scrubbed back
+//! through and resumed from.
+
+use std::collections::VecDeque;
+
+use crate::sim::Particle;
+
+/// The particles from the most recent steps of a simulation.
+pub struct History {
+    snapshots: VecDeque<Vec<Particle>>,
+    /// The maximum number of snapshots to keep.
+    capacity: usize,
+    /// The index of the snapshot being shown while scrubbing, or `None` if the
+    /// simulation is running live.
+    cursor: Option<usize>,
+}
+
+impl History {
+    /// Creates an empty history which keeps at most `capacity` snapshots.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            snapshots: VecDeque::with_capacity(capacity),
+            capacity,
+            cursor: None,
+        }
+    }
+
+    /// The number of snapshots currently stored.
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    /// The index of the snapshot being shown, if scrubbing.
+    pub fn cursor(&self) -> Option<usize> {
+        self.cursor
+    }
+
+    pub fn is_scrubbing(&self) -> bool {
+        self.cursor.is_some()
+    }
+
+    /// Records a snapshot of `particles`, dropping the oldest snapshot if the
+    /// history is full.
+    ///
+    /// Does nothing while scrubbing.
+    pub fn record(&mut self, particles: &[Particle]) {
+        if self.is_scrubbing() || self.capacity == 0 {
+            return;
+        }
+
+        if self.snapshots.len() == self.capacity {
+            // Reuse the oldest snapshot's allocation.
+            let mut snapshot = self.snapshots.pop_front().unwrap();
+            snapshot.clear();
+            snapshot.extend_from_slice(particles);
+            self.snapshots.push_back(snapshot);
+        } else {
+            self.snapshots.push_back(particles.to_vec());
+        }
+    }
+
+    /// Moves the cursor `steps` snapshots forward (or backward, if negative),
+    /// starting to scrub from the latest snapshot if not already scrubbing.
+    ///
+    /// The cursor stops at either end of the history. Returns the snapshot now
+    /// under the cursor, or `None` if there's nothing recorded.
+    pub fn scrub(&mut self, steps: isize) -> Option<&[Particle]> {
+        let last = self.snapshots.len().checked_sub(1)?;
+        let cursor = self.cursor.unwrap_or(last);
+        let cursor = cursor.saturating_add_signed(steps).min(last);
+        self.cursor = Some(cursor);
+        Some(&self.snapshots[cursor])
+    }
+
+    /// Stops scrubbing, discarding every snapshot after the cursor so that new
+    /// ones can be recorded from there.
+    pub fn resume(&mut self) {
+        if let Some(cursor) = self.cursor.take() {
+            self.snapshots.truncate(cursor + 1);
+        }
+    }
+
+    /// Discards every snapshot and stops scrubbing.
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+        self.cursor = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a snapshot of a single particle of kind `step`, so that
+    /// snapshots can be told apart.
+    fn snapshot(step: usize) -> Vec<Particle> {
+        vec![Particle {
+            kind: step,
+            ..Default::default()
+        }]
+    }
+
+    fn kind(snapshot: Option<&[Particle]>) -> Option<usize> {
+        snapshot.map(|particles| particles[0].kind)
+    }
+
+    #[test]
+    fn scrubs_back_and_resumes() {
+        let mut history = History::new(15);
+        for step in 0..20 {
+            history.record(&snapshot(step));
+        }
+        // Only the last 15 steps are kept.
+        assert_eq!(history.len(), 15);
+
+        assert_eq!(kind(history.scrub(-10)), Some(9));
+        assert_eq!(history.cursor(), Some(4));
+        // Recording does nothing while scrubbing.
+        history.record(&snapshot(100));
+        assert_eq!(history.len(), 15);
+
+        // Scrubbing stops at either end.
+        assert_eq!(kind(history.scrub(-100)), Some(5));
+        assert_eq!(kind(history.scrub(100)), Some(19));
+        assert_eq!(kind(history.scrub(-10)), Some(9));
+
+        history.resume();
+        assert!(!history.is_scrubbing());
+        assert_eq!(history.len(), 5);
+
+        for step in 10..13 {
+            history.record(&snapshot(step));
+        }
+        assert_eq!(history.len(), 8);
+        assert_eq!(kind(history.scrub(0)), Some(12));
+        assert_eq!(kind(history.scrub(-3)), Some(9));
+    }
+
+    #[test]
+    fn empty_history_has_nothing_to_scrub() {
+        let mut history = History::new(0);
+        history.record(&snapshot(0));
+        assert!(history.is_empty());
+        assert_eq!(kind(history.scrub(-1)), None);
+        assert!(!history.is_scrubbing());
+    }
+}
@@ -0,0 +1,251 @@
+//! Which keys trigger which actions, which users can change in a config file.
+
+use std::collections::HashMap;
+
+use crate::settings::Settings;
+
+/// Something the user can do with a key press.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    ToggleWrap,
+    ToggleWrapTiles,
+    Reverse,
+    ToggleRecording,
+    Resume,
+    ToggleAutoCycle,
+    EnterSeed,
+    ShowSeed,
+    /// Log the current universe as Rust code (see `Sim::to_rust_source`).
+    ShowRustSource,
+    ToggleBonds,
+    ToggleVelocityVectors,
+    ToggleTimeDilation,
+    /// Toggle `State::real_time`.
+    ToggleRealTime,
+    ToggleAdaptiveTrails,
+    ToggleTrailsOnly,
+    ToggleFocusVignette,
+    DecreaseFriction,
+    IncreaseFriction,
+    CycleMatrixKind,
+    CycleDrawOrder,
+    CycleSymmetry,
+    Regenerate,
+    ToggleFullscreen,
+    /// Start profiling how long steps take, or stop and log the results.
+    ToggleProfiling,
+    /// Switch to the preset with this name (see `Settings::by_name`).
+    Preset(&'static str),
+}
+
+impl Action {
+    /// Every action apart from the presets, paired with its name.
+    const NAMED: [(&'static str, Action); 24] = [
+        ("toggle_wrap", Action::ToggleWrap),
+        ("toggle_wrap_tiles", Action::ToggleWrapTiles),
+        ("reverse", Action::Reverse),
+        ("toggle_recording", Action::ToggleRecording),
+        ("resume", Action::Resume),
+        ("toggle_auto_cycle", Action::ToggleAutoCycle),
+        ("enter_seed", Action::EnterSeed),
+        ("show_seed", Action::ShowSeed),
+        ("show_rust_source", Action::ShowRustSource),
+        ("toggle_bonds", Action::ToggleBonds),
+        ("toggle_velocity_vectors", Action::ToggleVelocityVectors),
+        ("toggle_time_dilation", Action::ToggleTimeDilation),
+        ("toggle_real_time", Action::ToggleRealTime),
+        ("toggle_adaptive_trails", Action::ToggleAdaptiveTrails),
+        ("toggle_trails_only", Action::ToggleTrailsOnly),
+        ("toggle_focus_vignette", Action::ToggleFocusVignette),
+        ("decrease_friction", Action::DecreaseFriction),
+        ("increase_friction", Action::IncreaseFriction),
+        ("cycle_matrix_kind", Action::CycleMatrixKind),
+        ("cycle_draw_order", Action::CycleDrawOrder),
+        ("cycle_symmetry", Action::CycleSymmetry),
+        ("regenerate", Action::Regenerate),
+        ("toggle_fullscreen", Action::ToggleFullscreen),
+        ("toggle_profiling", Action::ToggleProfiling),
+    ];
+
+    /// Returns the action with the given name, as used in config files.
+    ///
+    /// Presets are named `preset_` followed by the name of the preset.
+    pub fn from_name(name: &str) -> Option<Action> {
+        if let Some(preset) = name.strip_prefix("preset_") {
+            return Settings::preset_names()
+                .iter()
+                .find(|&&name| name == preset)
+                .map(|&name| Action::Preset(name));
+        }
+
+        Self::NAMED
+            .iter()
+            .find(|&&(action_name, _)| action_name == name)
+            .map(|&(_, action)| action)
+    }
+}
+
+/// A mapping from keys to the actions they trigger.
+///
+/// Keys are named by the character they type (e.g. `w` or `,`), or the name of
+/// the key for keys that don't type anything (e.g. `Enter` or `F11`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Keybindings {
+    bindings: HashMap<String, Action>,
+}
+
+impl Default for Keybindings {
+    fn default() -> Self {
+        let bindings = [
+            ("w", Action::ToggleWrap),
+            ("n", Action::ToggleWrapTiles),
+            ("r", Action::Reverse),
+            ("z", Action::ToggleRecording),
+            ("p", Action::Resume),
+            ("a", Action::ToggleAutoCycle),
+            ("i", Action::EnterSeed),
+            ("y", Action::ShowSeed),
+            ("F6", Action::ShowRustSource),
+            ("j", Action::ToggleBonds),
+            ("F8", Action::ToggleVelocityVectors),
+            ("k", Action::ToggleTimeDilation),
+            ("F10", Action::ToggleRealTime),
+            ("t", Action::ToggleAdaptiveTrails),
+            ("u", Action::ToggleTrailsOnly),
+            ("F5", Action::ToggleFocusVignette),
+            (",", Action::DecreaseFriction),
+            (".", Action::IncreaseFriction),
+            ("x", Action::CycleMatrixKind),
+            ("v", Action::CycleDrawOrder),
+            ("F7", Action::CycleSymmetry),
+            ("Enter", Action::Regenerate),
+            ("F11", Action::ToggleFullscreen),
+            ("F9", Action::ToggleProfiling),
+            ("b", Action::Preset("balanced")),
+            ("c", Action::Preset("chaos")),
+            ("d", Action::Preset("diversity")),
+            ("e", Action::Preset("cells")),
+            ("f", Action::Preset("frictionless")),
+            ("g", Action::Preset("gliders")),
+            ("h", Action::Preset("homogeneity")),
+            ("l", Action::Preset("large_clusters")),
+            ("m", Action::Preset("medium_clusters")),
+            ("o", Action::Preset("orbits")),
+            ("q", Action::Preset("quiescence")),
+            ("s", Action::Preset("small_clusters")),
+        ];
+
+        Self {
+            bindings: bindings
+                .into_iter()
+                .map(|(key, action)| (key.to_owned(), action))
+                .collect(),
+        }
+    }
+}
+
+impl Keybindings {
+    /// Returns the action `key` triggers, if any.
+    pub fn action(&self, key: &str) -> Option<Action> {
+        self.bindings.get(key).copied()
+    }
+
+    /// Returns the default keybindings, modified by the `[keybindings]` section
+    /// of `config`.
+    ///
+    /// Each line of the section is of the form `key = "action"`, where both
+    /// sides can optionally be quoted (which is needed for keys like `=`). Any
+    /// action mentioned there loses its default keys, so that it's only
+    /// triggered by the keys given; binding a key to `none` unbinds it.
+    ///
+    /// Unknown actions and malformed lines are logged and skipped.
+    pub fn from_config(config: &str) -> Self {
+        let mut entries = Vec::new();
+        let mut in_section = false;
+        for line in config.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line.starts_with('[') {
+                in_section = line == "[keybindings]";
+                continue;
+            }
+            if !in_section {
+                continue;
+            }
+
+            // Split on the last `=`, since the key itself might be `=`.
+            let Some((key, action)) = line.rsplit_once('=') else {
+                log::warn!("malformed keybinding: {line:?}");
+                continue;
+            };
+            let key = unquote(key.trim());
+            let action = unquote(action.trim());
+            if action == "none" {
+                entries.push((key, None));
+            } else if let Some(action) = Action::from_name(action) {
+                entries.push((key, Some(action)));
+            } else {
+                log::warn!("unknown action {action:?} bound to {key:?}");
+            }
+        }
+
+        let mut keybindings = Self::default();
+        keybindings
+            .bindings
+            .retain(|_, action| !entries.iter().any(|(_, other)| *other == Some(*action)));
+        for (key, action) in entries {
+            match action {
+                Some(action) => keybindings.bindings.insert(key.to_owned(), action),
+                None => keybindings.bindings.remove(key),
+            };
+        }
+        keybindings
+    }
+}
+
+/// Removes the quotes from around `s`, if it has any.
+fn unquote(s: &str) -> &str {
+    s.strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .unwrap_or(s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn custom_mapping_overrides_default() {
+        let keybindings = Keybindings::from_config(
+            r#"
+            [window]
+            r = "toggle_wrap"
+
+            [keybindings]
+            # Regenerate with space instead of enter.
+            " " = "regenerate"
+            "=" = preset_chaos
+            b = none
+            q = "not_an_action"
+            "#,
+        );
+
+        assert_eq!(keybindings.action(" "), Some(Action::Regenerate));
+        // The action's default key doesn't trigger it anymore.
+        assert_eq!(keybindings.action("Enter"), None);
+        // The same goes for presets.
+        assert_eq!(keybindings.action("="), Some(Action::Preset("chaos")));
+        assert_eq!(keybindings.action("c"), None);
+        // Keys can be unbound.
+        assert_eq!(keybindings.action("b"), None);
+        // Everything else keeps its default.
+        assert_eq!(keybindings.action("q"), Some(Action::Preset("quiescence")));
+        assert_eq!(keybindings.action("w"), Some(Action::ToggleWrap));
+        // Other sections are ignored.
+        assert_eq!(keybindings.action("r"), Some(Action::Reverse));
+
+        assert_eq!(Keybindings::from_config(""), Keybindings::default());
+    }
+}
@@ -1,27 +1,41 @@
+use std::collections::BTreeSet;
 use std::f32::consts::TAU;
 use std::mem::size_of;
 use std::num::NonZeroU64;
+use std::thread;
 use std::time::Duration;
 
 use bytemuck::Pod;
 use bytemuck::Zeroable;
 use glam::vec2;
 use glam::vec4;
+use glam::Mat2;
 use glam::Vec2;
 use glam::Vec4;
 use palette::LinSrgb;
+use particle_life_core::MAX_PARTICLES;
 use rand::rngs::OsRng;
+use rand::rngs::StdRng;
 use rand::Rng;
+use rand::SeedableRng;
+use sim::adjust_color;
+use sim::pixels_to_clip;
+use sim::AdaptiveTrails;
+use sim::ColorSpace;
+use sim::DrawOrder;
+use sim::Particle;
 use sim::Sim;
 use sim::RADIUS;
 use web_time::Instant;
 use wgpu::include_wgsl;
 use wgpu::util::BufferInitDescriptor;
 use wgpu::util::DeviceExt;
+use wgpu::AddressMode;
 use wgpu::Backends;
 use wgpu::BindGroup;
 use wgpu::BindGroupDescriptor;
 use wgpu::BindGroupEntry;
+use wgpu::BindGroupLayout;
 use wgpu::BindGroupLayoutDescriptor;
 use wgpu::BindGroupLayoutEntry;
 use wgpu::BindingResource;
@@ -33,31 +47,47 @@ use wgpu::BlendState;
 use wgpu::Buffer;
 use wgpu::BufferBinding;
 use wgpu::BufferBindingType;
+use wgpu::BufferDescriptor;
 use wgpu::BufferUsages;
 use wgpu::ColorTargetState;
 use wgpu::ColorWrites;
+use wgpu::CommandEncoder;
 use wgpu::CommandEncoderDescriptor;
 use wgpu::Device;
+use wgpu::FilterMode;
 use wgpu::FragmentState;
+use wgpu::ImageCopyTexture;
+use wgpu::ImageDataLayout;
 use wgpu::InstanceDescriptor;
 use wgpu::Limits;
 use wgpu::MultisampleState;
+use wgpu::Origin3d;
+use wgpu::PipelineLayout;
 use wgpu::PipelineLayoutDescriptor;
 use wgpu::PresentMode;
 use wgpu::PrimitiveState;
+use wgpu::PrimitiveTopology;
 use wgpu::Queue;
 use wgpu::RenderPipeline;
 use wgpu::RenderPipelineDescriptor;
 use wgpu::RequestAdapterOptions;
+use wgpu::Sampler;
+use wgpu::SamplerBindingType;
+use wgpu::SamplerDescriptor;
+use wgpu::ShaderModule;
 use wgpu::ShaderStages;
 use wgpu::Surface;
 use wgpu::SurfaceConfiguration;
+use wgpu::Texture;
+use wgpu::TextureAspect;
 use wgpu::TextureDescriptor;
 use wgpu::TextureDimension;
 use wgpu::TextureFormat;
+use wgpu::TextureSampleType;
 use wgpu::TextureUsages;
 use wgpu::TextureView;
 use wgpu::TextureViewDescriptor;
+use wgpu::TextureViewDimension;
 use wgpu::VertexBufferLayout;
 use wgpu::VertexState;
 use wgpu::VertexStepMode;
@@ -65,24 +95,93 @@ use winit::dpi::LogicalSize;
 use winit::dpi::PhysicalSize;
 use winit::window::Window;
 
-pub mod settings;
-pub mod sim;
-
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod gallery;
+pub mod headless;
+pub mod history;
+pub mod keybindings;
+pub mod profiler;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod sim_thread;
+
+pub use particle_life_core::settings;
+pub use particle_life_core::sim;
+pub use particle_life_core::web_config;
+pub use particle_life_core::GpuParticle;
+
+use gallery::GallerySlot;
+use history::History;
+use profiler::settings_label;
+use profiler::StepProfiler;
+use settings::real_time_dt;
 use settings::Settings;
+use settings::REFERENCE_STEP_RATE;
 
 const CIRCLE_POINTS: usize = 32;
 const SAMPLE_COUNT: u32 = 4;
-const MAX_PARTICLES: usize = 600;
 const PARTICLE_SEGMENT_SIZE: u64 = (size_of::<GpuParticle>() * MAX_PARTICLES) as u64;
 
 /// The number of past frames to use to create trails behind each particle.
 const TRAIL_LENGTH: usize = 10;
 
-// The particle information sent to the GPU.
+/// The default width/height of the texture used for `BackgroundMode::DensityGlow`.
+const DEFAULT_DENSITY_RESOLUTION: u32 = 64;
+
+/// The range the camera's zoom is kept within.
+pub const MIN_ZOOM: f32 = 1.0;
+pub const MAX_ZOOM: f32 = 10.0;
+
+/// The maximum number of bonds drawn when `State::show_bonds` is set.
+const MAX_BONDS: usize = 2000;
+
+/// How many steps' worth of movement the line showing a particle's velocity
+/// covers when `State::show_velocity_vectors` is set.
+const VELOCITY_LINE_SCALE: f32 = 5.0;
+/// The longest a particle's velocity line is drawn, in pixels.
+const MAX_VELOCITY_LINE_LENGTH: f32 = 30.0;
+
+/// The maximum number of steps kept in `State::history` (4 seconds at the
+/// default step rate).
+const HISTORY_LENGTH: usize = 1200;
+
+/// The number of slots in `State::gallery`.
+pub const GALLERY_SLOTS: usize = 10;
+
+/// The number of slots in `State::camera_bookmarks`.
+pub const BOOKMARK_SLOTS: usize = 10;
+
+/// The opacity of the copies of the simulation drawn around it when
+/// `State::show_wrap_tiles` is set.
+const WRAP_TILE_OPACITY: f32 = 0.25;
+
+/// The most copies of the particles `Symmetry::Rotational` can draw.
+pub const MAX_SYMMETRY_COPIES: u32 = 12;
+
+/// The strength of `State::focus_vignette` when it's toggled on with a key.
+pub const DEFAULT_FOCUS_VIGNETTE: f32 = 0.75;
+
+/// How many steps `DrawOrder::BySpeed` waits between sorting the particles.
+const DRAW_ORDER_INTERVAL: u32 = 10;
+
+/// The default value of `State::color_transition`.
+const DEFAULT_COLOR_TRANSITION: Duration = Duration::from_millis(500);
+
+/// The radius (in physical pixels of whatever's being rendered to) below which
+/// `PointMode::Auto` switches to drawing particles as points.
+const POINT_RADIUS_THRESHOLD: f32 = 1.5;
+
+/// One end of a line drawn between two bonded particles.
 #[repr(C)]
 #[derive(Debug, Default, Clone, Copy, Zeroable, Pod)]
-pub struct GpuParticle {
-    pos: Vec2,
+struct BondVertex {
+    /// The position of the particle the line starts from.
+    anchor: Vec2,
+    /// The offset of this end of the line from `anchor`, in clip space.
+    ///
+    /// This is kept separate so that the shader can wrap `anchor` around
+    /// without splitting the line in two.
+    offset: Vec2,
     color: LinSrgb,
 }
 
@@ -94,7 +193,9 @@ pub struct RenderSettings {
     pub zoom: f32,
     pub camera: Vec2,
 
-    pub padding: [u32; 2],
+    /// The strength of `State::focus_vignette`, or 0 if it's off.
+    pub vignette: f32,
+    pub padding: u32,
 
     /// The horizontal/vertical radius of a particle in clip space.
     /// A perfect circle in pixel space isn't always a perfect circle in clip
@@ -116,7 +217,8 @@ impl RenderSettings {
             zoom: 1.0,
             camera: vec2(0.0, 0.0),
 
-            padding: [0; 2],
+            vignette: 0.0,
+            padding: 0,
 
             horiz_rad: 2.0 * RADIUS / size.width,
             vert_rad: 2.0 * RADIUS / size.height,
@@ -150,6 +252,347 @@ fn create_multisampled_framebuffer(
         .create_view(&TextureViewDescriptor::default())
 }
 
+/// Returns the size to render at when the window is `size` and the render scale
+/// is `render_scale`.
+fn scaled_size(size: PhysicalSize<u32>, render_scale: f32) -> PhysicalSize<u32> {
+    PhysicalSize::new(
+        ((size.width as f32 * render_scale).round() as u32).max(1),
+        ((size.height as f32 * render_scale).round() as u32).max(1),
+    )
+}
+
+/// Creates the texture the multisampled framebuffer is resolved into when
+/// rendering at a different resolution to the window, and the bind group used
+/// to copy it to the window.
+fn create_scaled_framebuffer(
+    device: &Device,
+    format: TextureFormat,
+    size: PhysicalSize<u32>,
+    layout: &BindGroupLayout,
+    sampler: &Sampler,
+) -> (TextureView, BindGroup) {
+    let view = device
+        .create_texture(&TextureDescriptor {
+            label: Some("Scaled framebuffer"),
+            size: wgpu::Extent3d {
+                width: size.width,
+                height: size.height,
+                ..Default::default()
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        })
+        .create_view(&TextureViewDescriptor::default());
+
+    let bind_group = device.create_bind_group(&BindGroupDescriptor {
+        label: Some("Blit bind group"),
+        layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::TextureView(&view),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: BindingResource::Sampler(sampler),
+            },
+        ],
+    });
+
+    (view, bind_group)
+}
+
+fn create_density_texture(device: &Device, resolution: u32) -> Texture {
+    device.create_texture(&TextureDescriptor {
+        label: Some("Density texture"),
+        size: wgpu::Extent3d {
+            width: resolution,
+            height: resolution,
+            ..Default::default()
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format: TextureFormat::R8Unorm,
+        usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+        view_formats: &[],
+    })
+}
+
+fn create_density_bind_group(
+    device: &Device,
+    layout: &BindGroupLayout,
+    texture: &Texture,
+    sampler: &Sampler,
+    background_buffer: &Buffer,
+) -> BindGroup {
+    device.create_bind_group(&BindGroupDescriptor {
+        label: Some("Density bind group"),
+        layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::TextureView(
+                    &texture.create_view(&TextureViewDescriptor::default()),
+                ),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: BindingResource::Sampler(sampler),
+            },
+            BindGroupEntry {
+                binding: 3,
+                resource: BindingResource::Buffer(BufferBinding {
+                    buffer: background_buffer,
+                    offset: 0,
+                    size: None,
+                }),
+            },
+        ],
+    })
+}
+
+/// Creates the pipeline used to draw particles.
+///
+/// If `points` is true, particles are drawn as single points instead of circles.
+fn create_particle_pipeline(
+    device: &Device,
+    pipeline_layout: &PipelineLayout,
+    shader: &ShaderModule,
+    format: TextureFormat,
+    blend_mode: BlendMode,
+    points: bool,
+) -> RenderPipeline {
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some(if points {
+            "Point pipeline"
+        } else {
+            "Render pipeline"
+        }),
+        layout: Some(pipeline_layout),
+        vertex: VertexState {
+            module: shader,
+            entry_point: if points { "vs_point" } else { "vs_main" },
+            buffers: &[
+                // Particle buffer
+                VertexBufferLayout {
+                    array_stride: size_of::<GpuParticle>() as u64,
+                    step_mode: VertexStepMode::Instance,
+                    attributes: &wgpu::vertex_attr_array![
+                        0 => Float32x2,
+                        1 => Float32x3,
+                        2 => Float32,
+                        3 => Float32,
+                    ],
+                },
+            ],
+        },
+        primitive: PrimitiveState {
+            topology: if points {
+                PrimitiveTopology::PointList
+            } else {
+                PrimitiveTopology::TriangleList
+            },
+            ..Default::default()
+        },
+        depth_stencil: None,
+        multisample: MultisampleState {
+            count: SAMPLE_COUNT,
+            ..Default::default()
+        },
+        fragment: Some(FragmentState {
+            module: shader,
+            entry_point: if points { "fs_point" } else { "fs_main" },
+            targets: &[Some(ColorTargetState {
+                format,
+                blend: Some(blend_mode.blend_state()),
+                write_mask: ColorWrites::ALL,
+            })],
+        }),
+        multiview: None,
+    })
+}
+
+/// Clamps the number of particles in `settings` to the number that fit in the
+/// particle buffer.
+fn clamp_particles(mut settings: Settings) -> Settings {
+    if settings.particles > MAX_PARTICLES {
+        log::warn!(
+            "{} particles requested, but only {} fit in the particle buffer; clamping",
+            settings.particles,
+            MAX_PARTICLES
+        );
+        settings.particles = MAX_PARTICLES;
+    }
+    settings
+}
+
+/// Returns the offsets (in clip space) of the 8 copies of a wrapping simulation
+/// surrounding it.
+fn wrap_tile_offsets() -> [Vec2; 8] {
+    let mut out = [Vec2::ZERO; 8];
+    let tiles = (-1..=1)
+        .flat_map(|y| (-1..=1).map(move |x| vec2(x as f32, y as f32)))
+        .filter(|&tile| tile != Vec2::ZERO);
+    for (offset, tile) in out.iter_mut().zip(tiles) {
+        // Clip space is 2 units across, so that's how far away each copy is.
+        *offset = 2.0 * tile;
+    }
+    out
+}
+
+/// Returns the segments of the particle buffer to draw, oldest first, when the
+/// current frame is in segment `particle_segment`.
+///
+/// With `trails_only`, the current frame is left out.
+fn drawn_segments(particle_segment: usize, trails_only: bool) -> impl Iterator<Item = usize> {
+    // The current frame is the last segment drawn, so leaving it off leaves just the trail.
+    let segments = if trails_only {
+        TRAIL_LENGTH - 1
+    } else {
+        TRAIL_LENGTH
+    };
+    (particle_segment + 1..)
+        .map(|i| i % TRAIL_LENGTH)
+        .take(segments)
+}
+
+/// Returns the lines to draw along the seams of a wrapping simulation, and the
+/// seams of the copies of it around it.
+fn seam_vertices() -> Vec<BondVertex> {
+    let color = LinSrgb::new(0.5, 0.5, 0.5);
+    let mut vertices = Vec::new();
+    for shift in [-2.0, 0.0, 2.0] {
+        // Make the lines long enough to cover all the copies.
+        for (anchor, start, end) in [
+            (vec2(-1.0, 0.0), vec2(shift, -3.0), vec2(shift, 3.0)),
+            (vec2(0.0, -1.0), vec2(-3.0, shift), vec2(3.0, shift)),
+        ] {
+            for offset in [start, end] {
+                vertices.push(BondVertex {
+                    anchor,
+                    offset,
+                    color,
+                });
+            }
+        }
+    }
+    vertices
+}
+
+/// Returns where the camera should actually be put when it's moved to
+/// `camera` at zoom `zoom`.
+///
+/// When not wrapping, this keeps the view inside the simulation; when wrapping,
+/// it wraps the camera around to the equivalent position within it.
+fn constrain_camera(mut camera: Vec2, zoom: f32, wrap: bool) -> Vec2 {
+    if !wrap {
+        let view_radius = 1.0 / zoom;
+
+        camera.clamp(
+            vec2(-1.0 + view_radius, -1.0 + view_radius),
+            vec2(1.0 - view_radius, 1.0 - view_radius),
+        )
+    } else {
+        while camera[0] > 1.0 {
+            camera[0] -= 2.0;
+        }
+
+        while camera[0] < -1.0 {
+            camera[0] += 2.0;
+        }
+
+        while camera[1] > 1.0 {
+            camera[1] -= 2.0;
+        }
+
+        while camera[1] < -1.0 {
+            camera[1] += 2.0;
+        }
+
+        camera
+    }
+}
+
+/// Returns the opacity `State::focus_vignette` draws a particle at `pos`
+/// with, when it's set to `strength` and the camera is at `camera` with zoom
+/// `zoom`.
+///
+/// Opacity falls off with the square of the distance from the middle of the
+/// screen, down to `1.0 - strength` at the middle of each edge.
+///
+/// Keep this in sync with `vignette` in `shader.wgsl`.
+pub fn vignette_opacity(strength: f32, pos: Vec2, camera: Vec2, zoom: f32) -> f32 {
+    let screen_pos = (pos + camera) * zoom;
+    (1.0 - strength * screen_pos.length_squared()).clamp(0.0, 1.0)
+}
+
+/// Returns where the line showing velocity `vel` (in pixels per step) ends,
+/// relative to the particle, in clip space for a window `width`x`height`
+/// logical pixels in size.
+fn velocity_line_offset(vel: Vec2, width: f32, height: f32) -> Vec2 {
+    let line = (vel * VELOCITY_LINE_SCALE).clamp_length_max(MAX_VELOCITY_LINE_LENGTH);
+    pixels_to_clip(line, width, height)
+}
+
+/// Returns whether a frame should be drawn at `now`, when frames are limited to
+/// `max_fps` a second and the next one is due at `next_frame`; if so, returns
+/// when the one after that is due.
+fn frame_due(max_fps: u32, next_frame: Instant, now: Instant) -> Option<Instant> {
+    if now < next_frame {
+        return None;
+    }
+
+    let period = Duration::from_secs(1) / max_fps.max(1);
+    let after = next_frame + period;
+    if after > now {
+        Some(after)
+    } else {
+        // We've fallen more than a frame behind; don't try to catch up.
+        Some(now + period)
+    }
+}
+
+/// Returns the step rate `zoom_time_dilation` slows `step_rate` down to at
+/// `zoom`.
+fn dilated_step_rate(step_rate: u32, zoom: f32) -> u32 {
+    // Don't let it hit 0, or the simulation would never step again.
+    ((step_rate as f32 / zoom).round() as u32).max(1)
+}
+
+/// Returns whether `auto_cycle` should switch universes at `now`, given that
+/// the universe was last switched at `last_cycle`; if so, `last_cycle` is moved
+/// up to `now`.
+fn cycle_due(auto_cycle: Option<Duration>, last_cycle: &mut Instant, now: Instant) -> bool {
+    match auto_cycle {
+        Some(interval) if now.duration_since(*last_cycle) >= interval => {
+            *last_cycle = now;
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Returns how far through a transition lasting `duration` is after `elapsed`
+/// has passed, from 0 to 1, or `None` if it's finished.
+fn transition_progress(elapsed: Duration, duration: Duration) -> Option<f32> {
+    if elapsed >= duration {
+        return None;
+    }
+    Some(elapsed.as_secs_f32() / duration.as_secs_f32())
+}
+
+/// Returns the number of frames in `duration` at `fps` frames per second,
+/// rounded to the nearest frame.
+#[cfg(not(target_arch = "wasm32"))]
+fn realtime_frame_count(fps: u32, duration: Duration) -> u64 {
+    (duration.as_secs_f64() * f64::from(fps)).round() as u64
+}
+
 fn opacities() -> impl Iterator<Item = f32> {
     (1..=TRAIL_LENGTH).map(|n| n as f32 / TRAIL_LENGTH as f32)
 }
@@ -170,10 +613,307 @@ fn circle_points(size: LogicalSize<f32>) -> [Vec4; CIRCLE_POINTS] {
     out
 }
 
+/// Whether to draw particles as single-pixel points rather than circles.
+///
+/// Tiny circles flicker as they move between pixels, even with MSAA; points
+/// instead fade out based on how much of a pixel the particle covers, so they
+/// stay stable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PointMode {
+    /// Always draw circles.
+    Never,
+    /// Always draw points.
+    Always,
+    /// Draw points when every particle would be smaller than
+    /// `POINT_RADIUS_THRESHOLD` physical pixels.
+    ///
+    /// At the default radius, this happens when rendering at a low
+    /// `render_scale`, on a low-DPI display, or into a small image with
+    /// `render_to_size`.
+    Auto,
+}
+
+impl PointMode {
+    /// Returns whether to draw points, given the largest radius of any
+    /// particle in physical pixels of the target being drawn to.
+    pub fn use_points(self, pixel_radius: f32) -> bool {
+        match self {
+            PointMode::Never => false,
+            PointMode::Always => true,
+            PointMode::Auto => pixel_radius < POINT_RADIUS_THRESHOLD,
+        }
+    }
+}
+
+/// How particles are blended with whatever's behind them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BlendMode {
+    /// Draw particles over the top of each other.
+    #[default]
+    AlphaOver,
+    /// Add particles' colors together, so that overlapping particles glow
+    /// brighter.
+    Additive,
+}
+
+impl BlendMode {
+    // some basic blending, to make the translucent trails work.
+    // I don't really know what I'm doing when it comes to this, but this works ok.
+    fn blend_state(self) -> BlendState {
+        let color = match self {
+            BlendMode::AlphaOver => BlendComponent {
+                src_factor: BlendFactor::SrcAlpha,
+                dst_factor: BlendFactor::OneMinusSrcAlpha,
+                operation: BlendOperation::Add,
+            },
+            // Still scale by alpha, so that trails fade out rather than all being added at full
+            // brightness.
+            BlendMode::Additive => BlendComponent {
+                src_factor: BlendFactor::SrcAlpha,
+                dst_factor: BlendFactor::One,
+                operation: BlendOperation::Add,
+            },
+        };
+
+        BlendState {
+            color,
+            alpha: BlendComponent {
+                src_factor: BlendFactor::One,
+                dst_factor: BlendFactor::One,
+                operation: BlendOperation::Add,
+            },
+        }
+    }
+}
+
+/// How the particles are copied around the middle of the screen to make a
+/// kaleidoscope.
+///
+/// This only changes how the particles are drawn; the copies don't exert any
+/// forces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Symmetry {
+    /// Draw the particles once.
+    #[default]
+    None,
+    /// Also draw the particles reflected left-to-right.
+    MirrorX,
+    /// Also draw the particles reflected left-to-right, top-to-bottom, and
+    /// both.
+    MirrorXY,
+    /// Draw this many copies of the particles, rotated evenly around the
+    /// middle of the screen. This is clamped to `1..=MAX_SYMMETRY_COPIES`.
+    Rotational(u32),
+}
+
+impl Symmetry {
+    /// Returns the symmetry after this one, wrapping back around to the first.
+    pub fn next(self) -> Self {
+        match self {
+            Symmetry::None => Symmetry::MirrorX,
+            Symmetry::MirrorX => Symmetry::MirrorXY,
+            Symmetry::MirrorXY => Symmetry::Rotational(6),
+            Symmetry::Rotational(_) => Symmetry::None,
+        }
+    }
+
+    /// Returns the number of copies of the particles drawn.
+    pub fn copies(self) -> usize {
+        match self {
+            Symmetry::None => 1,
+            Symmetry::MirrorX => 2,
+            Symmetry::MirrorXY => 4,
+            Symmetry::Rotational(copies) => copies.clamp(1, MAX_SYMMETRY_COPIES) as usize,
+        }
+    }
+
+    /// Returns the transform applied to each copy of the particles, in clip
+    /// space for a `width`x`height` view. The first is always the identity.
+    ///
+    /// Rotations are done in pixels, so that the copies aren't stretched when
+    /// the view isn't square.
+    pub fn transforms(self, width: f32, height: f32) -> Vec<Mat2> {
+        let scale = |x: f32, y: f32| Mat2::from_diagonal(vec2(x, y));
+        match self {
+            Symmetry::None => vec![Mat2::IDENTITY],
+            Symmetry::MirrorX => vec![Mat2::IDENTITY, scale(-1.0, 1.0)],
+            Symmetry::MirrorXY => vec![
+                Mat2::IDENTITY,
+                scale(-1.0, 1.0),
+                scale(1.0, -1.0),
+                scale(-1.0, -1.0),
+            ],
+            Symmetry::Rotational(_) => {
+                let copies = self.copies();
+                let to_pixels = scale(width, height);
+                let to_clip = scale(1.0 / width, 1.0 / height);
+                (0..copies)
+                    .map(|i| {
+                        let angle = TAU * i as f32 / copies as f32;
+                        to_clip * Mat2::from_angle(angle) * to_pixels
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    /// Returns every copy of `particles` drawn with this symmetry, all of the
+    /// first copy followed by all of the second and so on, for a
+    /// `width`x`height` view with the camera at `camera`.
+    ///
+    /// The copies are positioned so that drawing them without any symmetry
+    /// gives the same result, ignoring wrapping.
+    pub fn apply(
+        self,
+        particles: &[GpuParticle],
+        camera: Vec2,
+        width: f32,
+        height: f32,
+    ) -> Vec<GpuParticle> {
+        self.transforms(width, height)
+            .into_iter()
+            .flat_map(|transform| {
+                particles.iter().map(move |particle| {
+                    // The transform is around the middle of the screen, which is where the camera
+                    // is.
+                    particle.with_pos(transform * (particle.pos() + camera) - camera)
+                })
+            })
+            .collect()
+    }
+}
+
+/// What to draw behind the particles.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BackgroundMode {
+    /// A flat color.
+    Solid(LinSrgb),
+    /// A blurry glow which gets brighter where there are more particles.
+    DensityGlow {
+        /// The colors to use for empty and densely populated areas
+        /// respectively.
+        palette: [LinSrgb; 2],
+        /// How quickly the glow saturates as density increases.
+        intensity: f32,
+    },
+}
+
+impl Default for BackgroundMode {
+    fn default() -> Self {
+        BackgroundMode::Solid(LinSrgb::new(0.0, 0.0, 0.0))
+    }
+}
+
+/// The settings for the background sent to the GPU.
+#[repr(C)]
+#[derive(Pod, Zeroable, Clone, Copy, Debug)]
+struct BackgroundSettings {
+    // These are `Vec4`s rather than `LinSrgb`s because of alignment.
+    low: Vec4,
+    high: Vec4,
+}
+
+/// The number of steps `CatchUp::Drop` runs per frame at most.
+const DROP_STEPS: u32 = 20;
+
+/// How far behind `CatchUp::SlowDown` lets the simulation fall before it
+/// drops steps.
+const SLOW_DOWN_MAX_LAG: Duration = Duration::from_secs(1);
+
+/// What to do when rendering falls behind the simulation's step rate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CatchUp {
+    /// Run up to 20 steps per frame, and drop any steps still owed after that.
+    Drop,
+    /// Run every step that's owed, however long that makes the frame take.
+    ///
+    /// Only up to a second's worth of steps are ever owed, so that a long
+    /// stall (like the computer going to sleep) doesn't freeze the app while
+    /// it runs hours of steps.
+    SlowDown,
+    /// Run at most this many steps per frame, and keep the rest for future
+    /// frames.
+    Cap(u32),
+}
+
+/// Returns how many steps `step_period` apart are due by `now` under
+/// `catch_up`, moving `last_step` forward past them.
+fn due_steps(
+    last_step: &mut Instant,
+    now: Instant,
+    step_period: Duration,
+    catch_up: CatchUp,
+) -> u32 {
+    if catch_up == CatchUp::SlowDown {
+        if let Some(earliest) = now.checked_sub(SLOW_DOWN_MAX_LAG) {
+            *last_step = (*last_step).max(earliest);
+        }
+    }
+
+    let mut steps = 0;
+    // Don't keep checking the time while stepping, or we might never stop if the steps
+    // themselves are slower than `step_period`.
+    while *last_step + step_period < now {
+        if let CatchUp::Cap(max_steps) = catch_up {
+            if steps == max_steps {
+                break;
+            }
+        }
+
+        *last_step += step_period;
+        steps += 1;
+
+        if catch_up == CatchUp::Drop && steps == DROP_STEPS {
+            // `finish_steps` resets the clock once these have run.
+            break;
+        }
+    }
+    steps
+}
+
+/// Information about a frame passed to `State::on_frame`.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameInfo {
+    /// The number of simulation steps which were run this frame.
+    pub substeps: u32,
+    /// The index of this frame, starting from 0.
+    pub frame: u64,
+    /// How long it took to run this frame's simulation steps.
+    pub step_time: Duration,
+}
+
+/// A callback run after each frame; see `State::on_frame`.
+pub type FrameCallback = Box<dyn FnMut(&Sim, FrameInfo)>;
+
+/// Reports a frame which ran `steps` steps of `sim`, starting at `start`, to
+/// `on_frame` (if there is one) as frame number `frame`, and then moves
+/// `frame` on to the next one.
+fn report_frame(
+    on_frame: &mut Option<FrameCallback>,
+    sim: &Sim,
+    frame: &mut u64,
+    steps: u32,
+    start: Instant,
+) {
+    if let Some(on_frame) = on_frame {
+        on_frame(
+            sim,
+            FrameInfo {
+                substeps: steps,
+                frame: *frame,
+                step_time: start.elapsed(),
+            },
+        );
+    }
+    *frame += 1;
+}
+
 pub struct State {
     pub device: Device,
     pub queue: Queue,
-    pub surface: Surface,
+    /// The window's surface, or `None` if this `State` only renders offscreen
+    /// with `render_to_size`.
+    pub surface: Option<Surface>,
 
     pub settings_buffer: Buffer,
     pub particle_buffer: Buffer,
@@ -185,17 +925,187 @@ pub struct State {
     pub settings_bind_group: BindGroup,
     pub opacity_bind_groups: Vec<BindGroup>,
 
+    pub shader: ShaderModule,
+    pub pipeline_layout: PipelineLayout,
     pub render_pipeline: RenderPipeline,
+    pub point_pipeline: RenderPipeline,
+    pub point_mode: PointMode,
+    pub blend_mode: BlendMode,
+
+    /// Whether to draw lines between bonded particles (see `Sim::bonds`).
+    pub show_bonds: bool,
+    pub bond_buffer: Buffer,
+    pub bond_pipeline: RenderPipeline,
+    /// The number of vertices in `bond_buffer` to draw.
+    pub bond_vertices: u32,
+    /// Whether to draw a line from each particle in the direction it's moving,
+    /// with a length proportional to its speed.
+    pub show_velocity_vectors: bool,
+    /// The lines showing particles' velocities, drawn with `bond_pipeline`.
+    pub velocity_buffer: Buffer,
+    /// The color space to blend colors in, like the colors of bonds between
+    /// particles of different kinds.
+    pub color_space: ColorSpace,
+    /// The order to draw particles in.
+    ///
+    /// Set this with `set_draw_order`.
+    pub draw_order: DrawOrder,
+    /// The indices of the particles in the order they're drawn in, as
+    /// returned by `Sim::draw_order`.
+    pub draw_indices: Option<Vec<usize>>,
+    /// The number of times `draw_indices` has been used since it was computed.
+    pub draw_indices_age: u32,
+    /// How long particles take to fade from the old palette to the new one
+    /// when the settings are replaced. Zero switches instantly.
+    pub color_transition: Duration,
+    /// The palette before the settings were last replaced, and when that
+    /// happened.
+    pub previous_colors: Option<(Vec<LinSrgb>, Instant)>,
+    /// What to multiply the HSV value of every color in the palette by when
+    /// drawing it.
+    pub palette_brightness: f32,
+    /// What to multiply the saturation of every color in the palette by when
+    /// drawing it.
+    pub palette_saturation: f32,
+
+    /// Whether to only draw particles' trails, leaving out where they are
+    /// right now.
+    pub trails_only: bool,
+
+    /// Whether to mark the seams where the simulation wraps around, and draw
+    /// faint copies of it around itself (which show up when zoomed out past
+    /// its edges).
+    ///
+    /// This does nothing when the simulation isn't wrapping.
+    pub show_wrap_tiles: bool,
+    /// The lines along the seams, drawn with `bond_pipeline`.
+    pub seam_buffer: Buffer,
+    pub seam_vertices: u32,
+    /// The pass settings for each copy drawn by `show_wrap_tiles`.
+    pub tile_bind_groups: Vec<BindGroup>,
+
+    /// How the particles are copied around the middle of the screen. Use
+    /// `set_symmetry` to change this.
+    pub symmetry: Symmetry,
+    /// The transforms of each copy of the particles drawn by `symmetry`, with
+    /// room for `MAX_SYMMETRY_COPIES` of them.
+    pub symmetry_buffers: Vec<Buffer>,
+    pub symmetry_bind_groups: Vec<BindGroup>,
+
+    pub background: BackgroundMode,
+    pub background_buffer: Buffer,
+    pub background_pipeline: RenderPipeline,
+    /// The width/height of `density_texture`. This is always at least 1.
+    pub density_resolution: u32,
+    pub density_texture: Texture,
+    pub density_sampler: Sampler,
+    pub density_bind_group_layout: BindGroupLayout,
+    pub density_bind_group: BindGroup,
 
     pub swapchain_format: TextureFormat,
     pub multisampled_framebuffer: Option<TextureView>,
+    /// The size of the window's surface, in physical pixels.
+    pub surface_size: PhysicalSize<u32>,
+    /// The size of the window, in logical pixels.
+    pub logical_size: LogicalSize<f32>,
+    /// The resolution to render at, as a fraction of the window's resolution.
+    pub render_scale: f32,
+    /// The texture rendered into when `render_scale` isn't 1, and the bind
+    /// group used to copy it onto the window.
+    pub scaled_framebuffer: Option<(TextureView, BindGroup)>,
+    pub blit_bind_group_layout: BindGroupLayout,
+    pub blit_sampler: Sampler,
+    pub blit_pipeline: RenderPipeline,
 
     pub last_step: Instant,
+    /// The most frames `render` draws per second, or `None` to draw a frame
+    /// every time it's called (which is usually limited by vsync).
+    ///
+    /// This doesn't affect the step rate; skipped frames' steps are run in the
+    /// next frame that's drawn.
+    pub max_fps: Option<u32>,
+    /// When the next frame is due when `max_fps` is set.
+    pub next_frame: Instant,
     /// The index of the next segment of the particle buffer to be written to.
     pub particle_segment: usize,
     pub step_rate: u32,
+    /// Whether to scale each step's length by the step rate, so that the
+    /// simulation runs at the same speed in real time whatever the step rate.
+    ///
+    /// Otherwise, every step advances the simulation by the same amount, so
+    /// lowering the step rate slows it down.
+    pub real_time: bool,
+    /// Whether to slow the simulation down as the camera zooms in, dividing the
+    /// step rate by the zoom.
+    pub zoom_time_dilation: bool,
+    /// The number of smaller steps each step of the simulation is split into.
+    pub physics_substeps: u32,
+    /// Whether to run the simulation on another thread while the previous
+    /// frame is drawn, rather than before drawing each frame.
+    ///
+    /// This makes better use of multiple cores, but shows everything a frame
+    /// later. It's ignored on the web.
+    pub threaded_step: bool,
+    pub catch_up: CatchUp,
+    /// The number of frames which have been rendered so far.
+    pub frame: u64,
+    /// If set, records how long each step takes under each set of settings.
+    ///
+    /// With `threaded_step`, the times include drawing the frame the steps
+    /// overlapped with.
+    pub profiler: Option<StepProfiler>,
+    /// The texture `render_to_egui_texture` last rendered into, and the ID
+    /// it's registered with egui under.
+    #[cfg(feature = "egui")]
+    egui_texture: Option<(egui::TextureId, Texture)>,
+    /// A callback to run after the simulation is stepped each frame.
+    pub on_frame: Option<FrameCallback>,
 
     pub sim: Sim,
+    /// The seed the current universe was created from with `Sim::from_seed`,
+    /// if it was. Universes recalled from the gallery don't have one.
+    ///
+    /// Regenerating the particles doesn't change this; it only describes the
+    /// universe as it was created.
+    pub seed: Option<u64>,
+    /// The RNG life rules and spawn schedules draw from while stepping.
+    ///
+    /// This is reseeded with `Sim::step_rng` whenever a universe is created
+    /// from a seed, so that the same seed always plays out the same way,
+    /// whether or not `threaded_step` is on.
+    step_rng: StdRng,
+    /// How often to automatically switch to a new universe, if at all.
+    pub auto_cycle: Option<Duration>,
+    /// Whether `auto_cycle` switches to a random preset, rather than just
+    /// regenerating the particles.
+    pub auto_cycle_presets: bool,
+    /// When the universe was last switched by `auto_cycle`.
+    pub last_cycle: Instant,
+    /// Whether to record each step into `history`.
+    pub recording: bool,
+    /// The recent steps of the simulation, for scrubbing back through.
+    pub history: History,
+    /// Saved universes which can be switched back to. See the `gallery`
+    /// module for keeping these between runs.
+    pub gallery: [Option<GallerySlot>; GALLERY_SLOTS],
+    /// Saved camera positions, as a zoom and the point being looked at, which
+    /// can be jumped back to.
+    pub camera_bookmarks: [Option<(f32, Vec2)>; BOOKMARK_SLOTS],
+    /// The radius each kind of particle is drawn with, in pixels.
+    ///
+    /// This is purely cosmetic; the physics always uses `RADIUS`.
+    pub kind_radii: Vec<f32>,
+    /// If set, only particles of these kinds are drawn.
+    ///
+    /// This is purely cosmetic; hidden particles are still simulated.
+    pub visible_kinds: Option<BTreeSet<usize>>,
+    /// If set, particles' trails fade out the slower they're moving.
+    pub adaptive_trails: Option<AdaptiveTrails>,
+    /// If set, fade particles out the further they are from the middle of the
+    /// screen, to draw attention to what the camera's focused on when zoomed
+    /// in; see `vignette_opacity` for how strong this is. Use
+    /// `set_focus_vignette` to change this.
+    pub focus_vignette: Option<f32>,
 
     // It's easier to keep track of these externally than read them from GPU memory every time.
     pub zoom: f32,
@@ -203,8 +1113,10 @@ pub struct State {
 }
 
 impl State {
-    pub async fn new(window: &Window) -> Self {
-        let settings = Settings::balanced();
+    /// Creates a `State` rendering to `window`, with a random universe which
+    /// has already been run for `warmup_steps` steps.
+    pub async fn new(window: &Window, warmup_steps: u32) -> Self {
+        let settings = clamp_particles(Settings::balanced());
 
         let instance = wgpu::Instance::new(InstanceDescriptor {
             backends: Backends::all(),
@@ -240,8 +1152,52 @@ impl State {
             .await
             .expect("Failed to obtain device");
 
-        let mut rng = OsRng;
+        let swapchain_format = surface.get_capabilities(&adapter).formats[0];
+        let seed = OsRng.gen();
+        let mut sim = Sim::from_seed(settings, seed);
+
+        // `with_device` exports the particles into every segment of the trail, so this has to
+        // happen first.
+        let size: LogicalSize<f32> = window.inner_size().to_logical(window.scale_factor());
+        for _ in 0..warmup_steps {
+            sim.step(size.width, size.height);
+        }
+
+        let mut state = Self::with_device(device, queue, Some(surface), swapchain_format, sim);
+        state.seed = Some(seed);
+        state.step_rng = Sim::step_rng(seed);
+        state
+    }
+
+    /// Creates a `State` which renders `sim` with an existing wgpu device, for
+    /// embedding the renderer in another wgpu app.
+    ///
+    /// `surface` will be configured to render `swapchain_format` at `size`;
+    /// call `resize` when that changes. Without a surface, frames can still be
+    /// rendered with `render_to_size`.
+    pub fn from_wgpu(
+        device: Device,
+        queue: Queue,
+        surface: Option<Surface>,
+        swapchain_format: TextureFormat,
+        size: PhysicalSize<u32>,
+        scale_factor: f64,
+        sim: Sim,
+    ) -> Self {
+        let mut state = Self::with_device(device, queue, surface, swapchain_format, sim);
+        state.resize(size, scale_factor);
+        state
+    }
 
+    /// Creates everything apart from the wgpu device and surface, leaving the
+    /// surface unconfigured until `resize` is called.
+    fn with_device(
+        device: Device,
+        queue: Queue,
+        surface: Option<Surface>,
+        swapchain_format: TextureFormat,
+        sim: Sim,
+    ) -> Self {
         // Fill this in with a dummy size for now.
         let render_settings = RenderSettings::new(LogicalSize::new(1.0, 1.0));
 
@@ -251,10 +1207,10 @@ impl State {
             usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
         });
 
-        let sim = Sim::new(settings, &mut rng);
+        let kind_radii = vec![RADIUS; sim.colors.len()];
 
         let mut particles = [GpuParticle::default(); MAX_PARTICLES];
-        sim.export_particles(&mut particles);
+        sim.export_particles(&mut particles, &kind_radii, None, None, None, None);
 
         let particle_buffer_data = Box::new([particles; TRAIL_LENGTH]);
         let particle_buffer = device.create_buffer_init(&BufferInitDescriptor {
@@ -265,9 +1221,11 @@ impl State {
 
         let opacity_buffers: Vec<_> = opacities()
             .map(|opacity| {
+                // Everything but the current frame is part of the trail.
+                let trail = if opacity < 1.0 { 1.0 } else { 0.0 };
                 device.create_buffer_init(&BufferInitDescriptor {
                     label: Some(&format!("{} opacity buffer", opacity)),
-                    contents: bytemuck::cast_slice(&[opacity, 0.0, 0.0, 0.0]),
+                    contents: bytemuck::cast_slice(&[opacity, trail, 0.0, 0.0]),
                     usage: BufferUsages::UNIFORM,
                 })
             })
@@ -285,7 +1243,7 @@ impl State {
                             min_binding_size: NonZeroU64::new(size_of::<RenderSettings>() as u64),
                         },
                         binding: 0,
-                        visibility: ShaderStages::VERTEX,
+                        visibility: ShaderStages::VERTEX | ShaderStages::FRAGMENT,
                         count: None,
                     },
                 ],
@@ -309,7 +1267,7 @@ impl State {
                 label: Some("Opacity bind group layout"),
                 entries: &[BindGroupLayoutEntry {
                     binding: 0,
-                    visibility: ShaderStages::FRAGMENT,
+                    visibility: ShaderStages::VERTEX | ShaderStages::FRAGMENT,
                     ty: BindingType::Buffer {
                         ty: BufferBindingType::Uniform,
                         has_dynamic_offset: false,
@@ -337,84 +1295,413 @@ impl State {
             })
             .collect();
 
-        let swapchain_format = surface.get_capabilities(&adapter).formats[0];
+        let tile_bind_groups: Vec<_> = wrap_tile_offsets()
+            .into_iter()
+            .map(|offset| {
+                let buffer = device.create_buffer_init(&BufferInitDescriptor {
+                    label: Some(&format!("{} tile buffer", offset)),
+                    contents: bytemuck::cast_slice(&[WRAP_TILE_OPACITY, 0.0, offset.x, offset.y]),
+                    usage: BufferUsages::UNIFORM,
+                });
+                device.create_bind_group(&BindGroupDescriptor {
+                    label: Some(&format!("{} tile bind group", offset)),
+                    layout: &opacity_bind_group_layout,
+                    entries: &[BindGroupEntry {
+                        binding: 0,
+                        resource: BindingResource::Buffer(BufferBinding {
+                            buffer: &buffer,
+                            offset: 0,
+                            size: None,
+                        }),
+                    }],
+                })
+            })
+            .collect();
+
+        let symmetry_bind_group_layout =
+            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("Symmetry bind group layout"),
+                entries: &[BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::VERTEX,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: NonZeroU64::new(size_of::<Mat2>() as u64),
+                    },
+                    count: None,
+                }],
+            });
+
+        let symmetry_buffers: Vec<_> = (0..MAX_SYMMETRY_COPIES)
+            .map(|i| {
+                device.create_buffer_init(&BufferInitDescriptor {
+                    label: Some(&format!("Symmetry buffer {i}")),
+                    contents: bytemuck::bytes_of(&Mat2::IDENTITY),
+                    usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+                })
+            })
+            .collect();
+        let symmetry_bind_groups: Vec<_> = symmetry_buffers
+            .iter()
+            .enumerate()
+            .map(|(i, buffer)| {
+                device.create_bind_group(&BindGroupDescriptor {
+                    label: Some(&format!("Symmetry bind group {i}")),
+                    layout: &symmetry_bind_group_layout,
+                    entries: &[BindGroupEntry {
+                        binding: 0,
+                        resource: BindingResource::Buffer(BufferBinding {
+                            buffer,
+                            offset: 0,
+                            size: None,
+                        }),
+                    }],
+                })
+            })
+            .collect();
 
         let shader = device.create_shader_module(include_wgsl!("shader.wgsl"));
 
         let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
-            bind_group_layouts: &[&settings_bind_group_layout, &opacity_bind_group_layout],
+            bind_group_layouts: &[
+                &settings_bind_group_layout,
+                &opacity_bind_group_layout,
+                &symmetry_bind_group_layout,
+            ],
             ..Default::default()
         });
 
-        let render_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
-            label: Some("Render pipeline"),
-            layout: Some(&pipeline_layout),
-            vertex: VertexState {
-                module: &shader,
-                entry_point: "vs_main",
-                buffers: &[
-                    // Particle buffer
-                    VertexBufferLayout {
-                        array_stride: size_of::<GpuParticle>() as u64,
-                        step_mode: VertexStepMode::Instance,
-                        attributes: &wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x3],
-                    },
-                ],
-            },
-            primitive: PrimitiveState::default(),
-            depth_stencil: None,
-            multisample: MultisampleState {
-                count: SAMPLE_COUNT,
-                ..Default::default()
-            },
-            fragment: Some(FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
-                targets: &[Some(ColorTargetState {
-                    format: swapchain_format,
-                    // some basic blending, to make the translucent trails work.
-                    // I don't really know what I'm doing when it comes to this, but this works ok.
-                    blend: Some(BlendState {
-                        color: BlendComponent {
-                            src_factor: BlendFactor::SrcAlpha,
-                            dst_factor: BlendFactor::OneMinusSrcAlpha,
-                            operation: BlendOperation::Add,
-                        },
-                        alpha: BlendComponent {
-                            src_factor: BlendFactor::One,
-                            dst_factor: BlendFactor::One,
-                            operation: BlendOperation::Add,
-                        },
-                    }),
-                    write_mask: ColorWrites::ALL,
-                })],
-            }),
-            multiview: None,
-        });
-
-        Self {
-            device,
-            queue,
-            surface,
+        let render_pipeline = create_particle_pipeline(
+            &device,
+            &pipeline_layout,
+            &shader,
+            swapchain_format,
+            BlendMode::default(),
+            false,
+        );
+        let point_pipeline = create_particle_pipeline(
+            &device,
+            &pipeline_layout,
+            &shader,
+            swapchain_format,
+            BlendMode::default(),
+            true,
+        );
 
-            settings_buffer,
-            particle_buffer,
+        let background_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Background buffer"),
+            size: size_of::<BackgroundSettings>() as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
 
-            particle_buffer_data,
+        let density_texture = create_density_texture(&device, DEFAULT_DENSITY_RESOLUTION);
+        let density_sampler = device.create_sampler(&SamplerDescriptor {
+            label: Some("Density sampler"),
+            // The shader clamps the coordinates itself when not wrapping.
+            address_mode_u: AddressMode::Repeat,
+            address_mode_v: AddressMode::Repeat,
+            // Blur the density out between texels.
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            ..Default::default()
+        });
 
-            settings_bind_group,
-            opacity_bind_groups,
+        let density_bind_group_layout =
+            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("Density bind group layout"),
+                entries: &[
+                    // density
+                    BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::Texture {
+                            sample_type: TextureSampleType::Float { filterable: true },
+                            view_dimension: TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    // density_sampler
+                    BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                    // background
+                    BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: NonZeroU64::new(
+                                size_of::<BackgroundSettings>() as u64
+                            ),
+                        },
+                        count: None,
+                    },
+                ],
+            });
+
+        let density_bind_group = create_density_bind_group(
+            &device,
+            &density_bind_group_layout,
+            &density_texture,
+            &density_sampler,
+            &background_buffer,
+        );
+
+        let bond_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Bond buffer"),
+            // Bonds which wrap around the edges are drawn as two lines.
+            size: (2 * MAX_BONDS * 2 * size_of::<BondVertex>()) as u64,
+            usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let velocity_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Velocity buffer"),
+            size: (MAX_PARTICLES * 2 * size_of::<BondVertex>()) as u64,
+            usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let seam_vertices = seam_vertices();
+        let seam_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Seam buffer"),
+            contents: bytemuck::cast_slice(&seam_vertices),
+            usage: BufferUsages::VERTEX,
+        });
+
+        let bond_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            bind_group_layouts: &[&settings_bind_group_layout],
+            ..Default::default()
+        });
+
+        let bond_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("Bond pipeline"),
+            layout: Some(&bond_pipeline_layout),
+            vertex: VertexState {
+                module: &shader,
+                entry_point: "vs_bond",
+                buffers: &[VertexBufferLayout {
+                    array_stride: size_of::<BondVertex>() as u64,
+                    step_mode: VertexStepMode::Vertex,
+                    attributes: &wgpu::vertex_attr_array![
+                        0 => Float32x2,
+                        1 => Float32x2,
+                        2 => Float32x3,
+                    ],
+                }],
+            },
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::LineList,
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: MultisampleState {
+                count: SAMPLE_COUNT,
+                ..Default::default()
+            },
+            fragment: Some(FragmentState {
+                module: &shader,
+                entry_point: "fs_bond",
+                targets: &[Some(ColorTargetState {
+                    format: swapchain_format,
+                    blend: Some(BlendState::ALPHA_BLENDING),
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+        });
+
+        let background_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            bind_group_layouts: &[&settings_bind_group_layout, &density_bind_group_layout],
+            ..Default::default()
+        });
+
+        let background_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("Background pipeline"),
+            layout: Some(&background_pipeline_layout),
+            vertex: VertexState {
+                module: &shader,
+                entry_point: "vs_background",
+                buffers: &[],
+            },
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: MultisampleState {
+                count: SAMPLE_COUNT,
+                ..Default::default()
+            },
+            fragment: Some(FragmentState {
+                module: &shader,
+                entry_point: "fs_background",
+                targets: &[Some(ColorTargetState {
+                    format: swapchain_format,
+                    blend: None,
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+        });
+
+        let blit_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Blit bind group layout"),
+            entries: &[
+                // blit_source
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: true },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                // blit_sampler
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+
+        let blit_sampler = device.create_sampler(&SamplerDescriptor {
+            label: Some("Blit sampler"),
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let blit_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            bind_group_layouts: &[&blit_bind_group_layout],
+            ..Default::default()
+        });
+
+        let blit_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("Blit pipeline"),
+            layout: Some(&blit_pipeline_layout),
+            vertex: VertexState {
+                module: &shader,
+                entry_point: "vs_blit",
+                buffers: &[],
+            },
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            fragment: Some(FragmentState {
+                module: &shader,
+                entry_point: "fs_blit",
+                targets: &[Some(ColorTargetState {
+                    format: swapchain_format,
+                    blend: None,
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+        });
+
+        Self {
+            device,
+            queue,
+            surface,
+
+            settings_buffer,
+            particle_buffer,
 
+            particle_buffer_data,
+
+            settings_bind_group,
+            opacity_bind_groups,
+
+            shader,
+            pipeline_layout,
             render_pipeline,
+            point_pipeline,
+            point_mode: PointMode::Auto,
+            blend_mode: BlendMode::default(),
+
+            show_bonds: false,
+            bond_buffer,
+            bond_pipeline,
+            bond_vertices: 0,
+            show_velocity_vectors: false,
+            velocity_buffer,
+            color_space: ColorSpace::default(),
+            draw_order: DrawOrder::default(),
+            draw_indices: None,
+            draw_indices_age: 0,
+            color_transition: DEFAULT_COLOR_TRANSITION,
+            previous_colors: None,
+            palette_brightness: 1.0,
+            palette_saturation: 1.0,
+
+            trails_only: false,
+            show_wrap_tiles: false,
+            seam_buffer,
+            seam_vertices: seam_vertices.len() as u32,
+            tile_bind_groups,
+
+            symmetry: Symmetry::None,
+            symmetry_buffers,
+            symmetry_bind_groups,
+
+            background: BackgroundMode::default(),
+            background_buffer,
+            background_pipeline,
+            density_resolution: DEFAULT_DENSITY_RESOLUTION,
+            density_texture,
+            density_sampler,
+            density_bind_group_layout,
+            density_bind_group,
 
             swapchain_format,
             multisampled_framebuffer: None,
+            surface_size: PhysicalSize::new(0, 0),
+            logical_size: LogicalSize::new(1.0, 1.0),
+            render_scale: 1.0,
+            scaled_framebuffer: None,
+            blit_bind_group_layout,
+            blit_sampler,
+            blit_pipeline,
 
             last_step: Instant::now(),
+            max_fps: None,
+            next_frame: Instant::now(),
             particle_segment: 0,
-            step_rate: 300,
+            step_rate: REFERENCE_STEP_RATE,
+            real_time: false,
+            zoom_time_dilation: false,
+            physics_substeps: 1,
+            threaded_step: false,
+            catch_up: CatchUp::Drop,
+            frame: 0,
+            profiler: None,
+            #[cfg(feature = "egui")]
+            egui_texture: None,
+            on_frame: None,
 
             sim,
+            seed: None,
+            step_rng: StdRng::from_entropy(),
+            auto_cycle: None,
+            auto_cycle_presets: false,
+            last_cycle: Instant::now(),
+            recording: false,
+            history: History::new(HISTORY_LENGTH),
+            gallery: Default::default(),
+            camera_bookmarks: [None; BOOKMARK_SLOTS],
+            kind_radii,
+            visible_kinds: None,
+            adaptive_trails: None,
+            focus_vignette: None,
 
             zoom: 1.0,
             camera: vec2(0.0, 0.0),
@@ -422,28 +1709,32 @@ impl State {
     }
 
     pub fn resize(&mut self, size: PhysicalSize<u32>, scale_factor: f64) {
-        self.surface.configure(
-            &self.device,
-            &SurfaceConfiguration {
-                usage: TextureUsages::RENDER_ATTACHMENT,
-                format: self.swapchain_format,
-                width: size.width,
-                height: size.height,
-                present_mode: PresentMode::Fifo,
-                alpha_mode: Default::default(),
-                view_formats: Default::default(),
-            },
-        );
+        // wgpu doesn't allow 0-sized surfaces, which is what we get when the window's minimized.
+        // Just keep using the old size until it's restored.
+        if size.width == 0 || size.height == 0 {
+            return;
+        }
 
-        // Replace the framebuffer with a new one the correct size
-        self.multisampled_framebuffer = Some(create_multisampled_framebuffer(
-            &self.device,
-            self.swapchain_format,
-            size.width,
-            size.height,
-        ));
+        if let Some(surface) = &self.surface {
+            surface.configure(
+                &self.device,
+                &SurfaceConfiguration {
+                    usage: TextureUsages::RENDER_ATTACHMENT,
+                    format: self.swapchain_format,
+                    width: size.width,
+                    height: size.height,
+                    present_mode: PresentMode::Fifo,
+                    alpha_mode: Default::default(),
+                    view_formats: Default::default(),
+                },
+            );
+        }
+
+        self.surface_size = size;
+        self.create_framebuffers();
 
         let logical_size: LogicalSize<f32> = size.to_logical(scale_factor);
+        self.logical_size = logical_size;
 
         let new_settings = RenderSettings::new(logical_size);
 
@@ -453,118 +1744,1137 @@ impl State {
             24,
             &bytemuck::bytes_of(&new_settings)[24..],
         );
+        // Rotations depend on the aspect ratio.
+        self.write_symmetry(logical_size.width, logical_size.height);
     }
 
-    pub fn render(&mut self, width: f32, height: f32) {
-        // Don't render until we know what the size of the window is.
-        let Some(multisampled_framebuffer) = &self.multisampled_framebuffer else {
-            return;
-        };
+    /// Switches to drawing the particles with `symmetry`.
+    pub fn set_symmetry(&mut self, symmetry: Symmetry) {
+        self.symmetry = symmetry;
+        self.write_symmetry(self.logical_size.width, self.logical_size.height);
+    }
 
-        let frame = self
-            .surface
-            .get_current_texture()
-            .expect("Failed to acquire next swap chain texture");
+    /// Writes the transforms for `symmetry` in a `width`x`height` view to
+    /// `symmetry_buffers`.
+    fn write_symmetry(&self, width: f32, height: f32) {
+        let transforms = self.symmetry.transforms(width, height);
+        for (buffer, transform) in self.symmetry_buffers.iter().zip(&transforms) {
+            self.queue
+                .write_buffer(buffer, 0, bytemuck::bytes_of(transform));
+        }
+    }
 
-        let view = frame.texture.create_view(&TextureViewDescriptor::default());
+    /// Returns the size the scene is rendered at before being scaled up to
+    /// the window, in physical pixels.
+    pub fn render_size(&self) -> PhysicalSize<u32> {
+        scaled_size(self.surface_size, self.render_scale)
+    }
 
-        let mut encoder = self
-            .device
-            .create_command_encoder(&CommandEncoderDescriptor { label: None });
+    /// Replaces the framebuffers with new ones the correct size for the window
+    /// and `render_scale`.
+    fn create_framebuffers(&mut self) {
+        let size = self.render_size();
 
-        let step_period = Duration::from_secs(1) / self.step_rate;
-        let mut steps = 0;
-        while self.last_step + step_period < Instant::now() {
-            self.last_step += step_period;
+        self.multisampled_framebuffer = Some(create_multisampled_framebuffer(
+            &self.device,
+            self.swapchain_format,
+            size.width,
+            size.height,
+        ));
 
-            self.sim.step(width, height);
+        self.scaled_framebuffer = if self.render_scale == 1.0 {
+            // Render straight to the window.
+            None
+        } else {
+            Some(create_scaled_framebuffer(
+                &self.device,
+                self.swapchain_format,
+                size,
+                &self.blit_bind_group_layout,
+                &self.blit_sampler,
+            ))
+        };
+    }
 
-            self.particle_segment += 1;
-            self.particle_segment %= TRAIL_LENGTH;
+    /// Sets the resolution to render at, as a fraction of the window's
+    /// resolution; for example, 0.5 renders at half resolution and scales the
+    /// result up.
+    ///
+    /// Lower scales are faster but blurrier.
+    pub fn set_render_scale(&mut self, render_scale: f32) {
+        self.render_scale = render_scale;
+        // If we don't know the window's size yet, `resize` will create them later.
+        if self.multisampled_framebuffer.is_some() {
+            self.create_framebuffers();
+        }
+    }
 
-            self.sim
-                .export_particles(&mut self.particle_buffer_data[self.particle_segment]);
+    /// Returns when `render` will next draw a frame, or `None` if it draws one
+    /// every time it's called.
+    pub fn next_frame_due(&self) -> Option<Instant> {
+        self.max_fps.map(|_| self.next_frame)
+    }
 
-            steps += 1;
+    pub fn render(&mut self, width: f32, height: f32) {
+        // Don't render until we know what the size of the window is.
+        if self.multisampled_framebuffer.is_none() {
+            return;
+        }
 
-            if steps == 20 {
-                // It's not worth trying to catch up that far, just reset from here.
-                self.last_step = Instant::now();
+        let now = Instant::now();
+        if let Some(max_fps) = self.max_fps {
+            match frame_due(max_fps, self.next_frame, now) {
+                Some(next_frame) => self.next_frame = next_frame,
+                None => return,
             }
         }
 
+        if cycle_due(self.auto_cycle, &mut self.last_cycle, now) {
+            self.cycle();
+        }
+        if self.history.is_scrubbing() {
+            // Hold the simulation still while looking back through its history.
+            self.last_step = now;
+        }
+        let steps = self.due_steps(now);
+
+        // Threads aren't available on the web.
+        if self.threaded_step && steps > 0 && cfg!(not(target_arch = "wasm32")) {
+            self.render_threaded(width, height, steps, now);
+            return;
+        }
+
+        for _ in 0..steps {
+            self.advance(width, height);
+        }
+        self.finish_steps(steps, now);
+
+        self.queue.write_buffer(
+            &self.particle_buffer,
+            0,
+            bytemuck::cast_slice(self.particle_buffer_data.as_slice()),
+        );
+
+        self.update_lines(width, height);
+
+        self.draw_frame();
+    }
+
+    /// Like `render`, but draws the particles from the last frame while the
+    /// next `steps` steps run on another thread.
+    ///
+    /// This runs exactly the same steps as `render` would, just overlapped with
+    /// drawing, so the particles are shown a frame later.
+    fn render_threaded(&mut self, width: f32, height: f32, steps: u32, now: Instant) {
         self.queue.write_buffer(
             &self.particle_buffer,
             0,
             bytemuck::cast_slice(self.particle_buffer_data.as_slice()),
         );
 
-        {
+        self.update_lines(width, height);
+
+        let mut sim = self.sim.clone();
+        let mut rng = self.step_rng.clone();
+        let substeps = self.physics_substeps;
+        let dt = self.substep_dt();
+        let snapshots = thread::scope(|scope| {
+            let worker = scope.spawn(|| {
+                Self::step_sim_snapshots(&mut sim, &mut rng, steps, substeps, dt, width, height)
+            });
+            self.draw_frame();
+            worker.join().expect("simulation thread panicked")
+        });
+
+        self.sim = sim;
+        self.step_rng = rng;
+        // Go back through each step's particles so that the trail and history still get
+        // every step.
+        for particles in snapshots {
+            self.sim.particles = particles;
+            self.particle_segment += 1;
+            self.particle_segment %= TRAIL_LENGTH;
+            self.export_segment();
+            if self.recording {
+                self.history.record(&self.sim.particles);
+            }
+        }
+        self.finish_steps(steps, now);
+    }
+
+    /// Returns how many steps of the simulation are due by `now`, moving
+    /// `last_step` forward past them.
+    fn due_steps(&mut self, now: Instant) -> u32 {
+        let step_period = Duration::from_secs(1) / self.effective_step_rate();
+        due_steps(&mut self.last_step, now, step_period, self.catch_up)
+    }
+
+    /// Finishes off a frame which ran `steps` steps, starting at `start`, and
+    /// reports it to `on_frame`.
+    fn finish_steps(&mut self, steps: u32, start: Instant) {
+        if self.catch_up == CatchUp::Drop && steps == DROP_STEPS {
+            // It's not worth trying to catch up that far, just reset from here.
+            self.last_step = Instant::now();
+        }
+
+        if let Some(profiler) = &mut self.profiler {
+            profiler.record(steps, start.elapsed());
+        }
+        report_frame(&mut self.on_frame, &self.sim, &mut self.frame, steps, start);
+    }
+
+    /// Draws the contents of `particle_buffer` to the window.
+    ///
+    /// This only borrows `self`, so everything that changes what's drawn -
+    /// cycling to a new universe, updating the bonds, exporting the latest
+    /// segment with the palette fade blended in - has to happen before it's
+    /// called.
+    fn draw_frame(&self) {
+        let (Some(surface), Some(multisampled_framebuffer)) =
+            (&self.surface, &self.multisampled_framebuffer)
+        else {
+            return;
+        };
+
+        let frame = surface
+            .get_current_texture()
+            .expect("Failed to acquire next swap chain texture");
+
+        let view = frame.texture.create_view(&TextureViewDescriptor::default());
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor { label: None });
+
+        self.draw_scene(
+            &mut encoder,
+            multisampled_framebuffer,
+            match &self.scaled_framebuffer {
+                Some((scaled_view, _)) => scaled_view,
+                None => &view,
+            },
+            self.window_pixel_scale(),
+        );
+
+        if let Some((_, blit_bind_group)) = &self.scaled_framebuffer {
+            // Scale what we rendered up (or down) onto the window.
             let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: None,
+                label: Some("Blit pass"),
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: multisampled_framebuffer,
-                    resolve_target: Some(&view),
+                    view: &view,
+                    resolve_target: None,
                     ops: wgpu::Operations {
                         load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
-                        store: false,
+                        store: true,
                     },
                 })],
                 depth_stencil_attachment: None,
             });
-            rpass.set_pipeline(&self.render_pipeline);
 
-            rpass.set_bind_group(0, &self.settings_bind_group, &[]);
-
-            for (j, i) in (self.particle_segment + 1..)
-                .map(|i| i % TRAIL_LENGTH)
-                .take(TRAIL_LENGTH)
-                .enumerate()
-            {
-                let offset = i as u64 * PARTICLE_SEGMENT_SIZE;
-                rpass.set_vertex_buffer(
-                    0,
-                    self.particle_buffer.slice(
-                        offset
-                            ..offset + (self.sim.particles.len() * size_of::<GpuParticle>()) as u64,
-                    ),
-                );
-                rpass.set_bind_group(1, &self.opacity_bind_groups[j], &[]);
-                rpass.draw(
-                    0..CIRCLE_POINTS as u32 * 3,
-                    0..self.sim.particles.len() as u32,
-                );
-            }
+            rpass.set_pipeline(&self.blit_pipeline);
+            rpass.set_bind_group(0, blit_bind_group, &[]);
+            rpass.draw(0..3, 0..1);
         }
 
         self.queue.submit(Some(encoder.finish()));
         frame.present();
     }
 
-    pub fn toggle_wrap(&mut self) {
-        self.sim.wrap = !self.sim.wrap;
+    /// Draws the background and particles into the multisampled `framebuffer`,
+    /// and resolves it into `target`, which has `pixel_scale` physical pixels
+    /// per logical pixel of the window.
+    fn draw_scene(
+        &self,
+        encoder: &mut CommandEncoder,
+        framebuffer: &TextureView,
+        target: &TextureView,
+        pixel_scale: f32,
+    ) {
+        let clear_color = match self.background {
+            BackgroundMode::Solid(color) => wgpu::Color {
+                r: color.red.into(),
+                g: color.green.into(),
+                b: color.blue.into(),
+                a: 1.0,
+            },
+            BackgroundMode::DensityGlow { intensity, .. } => {
+                self.update_density(intensity);
+                wgpu::Color::BLACK
+            }
+        };
 
-        self.queue.write_buffer(
-            &self.settings_buffer,
-            0,
-            bytemuck::bytes_of(&(self.sim.wrap as u32)),
-        );
+        let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: None,
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: framebuffer,
+                resolve_target: Some(target),
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(clear_color),
+                    store: false,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
 
-        // Make sure the camera is within bounds
-        self.set_camera();
-    }
+        if let BackgroundMode::DensityGlow { .. } = self.background {
+            rpass.set_pipeline(&self.background_pipeline);
+            rpass.set_bind_group(0, &self.settings_bind_group, &[]);
+            rpass.set_bind_group(1, &self.density_bind_group, &[]);
+            rpass.draw(0..3, 0..1);
+        }
 
-    pub fn replace_settings<R: Rng>(&mut self, settings: Settings, rng: &mut R) {
-        self.sim = Sim {
-            wrap: self.sim.wrap,
-            ..Sim::new(settings, rng)
-        };
+        if self.show_bonds {
+            rpass.set_pipeline(&self.bond_pipeline);
+            rpass.set_bind_group(0, &self.settings_bind_group, &[]);
+            rpass.set_vertex_buffer(0, self.bond_buffer.slice(..));
+            rpass.draw(0..self.bond_vertices, 0..1);
+        }
 
-        self.regenerate_particles(rng);
-    }
+        if self.show_velocity_vectors {
+            rpass.set_pipeline(&self.bond_pipeline);
+            rpass.set_bind_group(0, &self.settings_bind_group, &[]);
+            rpass.set_vertex_buffer(0, self.velocity_buffer.slice(..));
+            rpass.draw(0..2 * self.sim.particles.len() as u32, 0..1);
+        }
 
-    pub fn regenerate_particles<R: Rng>(&mut self, rng: &mut R) {
-        self.sim.regenerate_particles(rng);
+        let show_wrap_tiles = self.show_wrap_tiles && self.sim.wrap;
+        if show_wrap_tiles {
+            rpass.set_pipeline(&self.bond_pipeline);
+            rpass.set_bind_group(0, &self.settings_bind_group, &[]);
+            rpass.set_vertex_buffer(0, self.seam_buffer.slice(..));
+            rpass.draw(0..self.seam_vertices, 0..1);
+        }
+
+        let points = self.points_active_at(pixel_scale);
+        if points {
+            rpass.set_pipeline(&self.point_pipeline);
+        } else {
+            rpass.set_pipeline(&self.render_pipeline);
+        }
+
+        rpass.set_bind_group(0, &self.settings_bind_group, &[]);
+
+        let vertices = if points {
+            0..1
+        } else {
+            0..CIRCLE_POINTS as u32 * 3
+        };
+        let particles_size = (self.sim.particles.len() * size_of::<GpuParticle>()) as u64;
+
+        for symmetry_bind_group in &self.symmetry_bind_groups[..self.symmetry.copies()] {
+            rpass.set_bind_group(2, symmetry_bind_group, &[]);
+
+            for (j, i) in drawn_segments(self.particle_segment, self.trails_only).enumerate() {
+                let offset = i as u64 * PARTICLE_SEGMENT_SIZE;
+                rpass.set_vertex_buffer(
+                    0,
+                    self.particle_buffer.slice(offset..offset + particles_size),
+                );
+                rpass.set_bind_group(1, &self.opacity_bind_groups[j], &[]);
+                rpass.draw(vertices.clone(), 0..self.sim.particles.len() as u32);
+            }
+
+            if show_wrap_tiles && !self.trails_only {
+                // Only draw the current frame for the copies, without trails.
+                let offset = self.particle_segment as u64 * PARTICLE_SEGMENT_SIZE;
+                rpass.set_vertex_buffer(
+                    0,
+                    self.particle_buffer.slice(offset..offset + particles_size),
+                );
+                for bind_group in &self.tile_bind_groups {
+                    rpass.set_bind_group(1, bind_group, &[]);
+                    rpass.draw(vertices.clone(), 0..self.sim.particles.len() as u32);
+                }
+            }
+        }
+    }
+
+    /// Renders the current frame at `width`x`height` physical pixels,
+    /// independently of the window's size, and returns the result as RGBA
+    /// bytes, one row after another from the top.
+    ///
+    /// The view is scaled so that it shows the same part of the simulation as
+    /// the window does vertically, with particles drawn correspondingly larger.
+    /// The swapchain format must have 4 bytes per pixel.
+    ///
+    /// This waits for the GPU to finish, so it isn't available on the web.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn render_to_size(&self, width: u32, height: u32) -> Vec<u8> {
+        use wgpu::ImageCopyBuffer;
+        use wgpu::Maintain;
+        use wgpu::MapMode;
+        use wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            ..Default::default()
+        };
+
+        let target = self.device.create_texture(&TextureDescriptor {
+            label: Some("Export texture"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: self.swapchain_format,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+
+        // Each row of the copy has to be padded out to `COPY_BYTES_PER_ROW_ALIGNMENT` bytes.
+        let row_bytes = 4 * width;
+        let padded_row_bytes = row_bytes.next_multiple_of(COPY_BYTES_PER_ROW_ALIGNMENT);
+        let output_buffer = self.device.create_buffer(&BufferDescriptor {
+            label: Some("Export buffer"),
+            size: padded_row_bytes as u64 * height as u64,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        self.render_offscreen(
+            &target.create_view(&TextureViewDescriptor::default()),
+            width,
+            height,
+        );
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("Export encoder"),
+            });
+
+        encoder.copy_texture_to_buffer(
+            target.as_image_copy(),
+            ImageCopyBuffer {
+                buffer: &output_buffer,
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row_bytes),
+                    rows_per_image: None,
+                },
+            },
+            size,
+        );
+
+        self.queue.submit(Some(encoder.finish()));
+
+        let slice = output_buffer.slice(..);
+        slice.map_async(MapMode::Read, |result| {
+            result.expect("failed to map export buffer")
+        });
+        self.device.poll(Maintain::Wait);
+
+        let mut pixels = Vec::with_capacity((row_bytes * height) as usize);
+        for row in slice.get_mapped_range().chunks(padded_row_bytes as usize) {
+            pixels.extend_from_slice(&row[..row_bytes as usize]);
+        }
+        output_buffer.unmap();
+
+        if matches!(
+            self.swapchain_format,
+            TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb
+        ) {
+            for pixel in pixels.chunks_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+
+        pixels
+    }
+
+    /// Renders the current frame into `target`, which is `width`x`height`
+    /// physical pixels, independently of the window's size.
+    ///
+    /// The view is scaled so that it shows the same part of the simulation as
+    /// the window does vertically, with particles drawn correspondingly larger.
+    #[cfg(any(not(target_arch = "wasm32"), feature = "egui"))]
+    fn render_offscreen(&self, target: &TextureView, width: u32, height: u32) {
+        let framebuffer =
+            create_multisampled_framebuffer(&self.device, self.swapchain_format, width, height);
+
+        // Pretend the window is the target's size scaled down to the window's height, so that the
+        // particles' radii and circle points are correct for the target's aspect ratio.
+        let scale = height as f32 / self.logical_size.height;
+        let offscreen_settings = RenderSettings::new(LogicalSize::new(
+            width as f32 / scale,
+            height as f32 / scale,
+        ));
+        self.queue.write_buffer(
+            &self.settings_buffer,
+            24,
+            &bytemuck::bytes_of(&offscreen_settings)[24..],
+        );
+        self.write_symmetry(width as f32, height as f32);
+        self.queue.write_buffer(
+            &self.particle_buffer,
+            0,
+            bytemuck::cast_slice(self.particle_buffer_data.as_slice()),
+        );
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("Offscreen encoder"),
+            });
+        self.draw_scene(&mut encoder, &framebuffer, target, scale);
+        self.queue.submit(Some(encoder.finish()));
+
+        // Put the window's settings back for the next frame.
+        let window_settings = RenderSettings::new(self.logical_size);
+        self.queue.write_buffer(
+            &self.settings_buffer,
+            24,
+            &bytemuck::bytes_of(&window_settings)[24..],
+        );
+        self.write_symmetry(self.logical_size.width, self.logical_size.height);
+    }
+
+    /// Renders the current frame at `width`x`height` physical pixels into a
+    /// texture registered with `renderer`, so that it can be shown in an egui
+    /// `Image` alongside other widgets.
+    ///
+    /// The same texture ID is returned every time, and the texture is only
+    /// recreated when the size changes, so this can be called every frame.
+    /// `renderer` must be using the same device as `self`, and should be the
+    /// same renderer each time.
+    #[cfg(feature = "egui")]
+    pub fn render_to_egui_texture(
+        &mut self,
+        renderer: &mut egui_wgpu::Renderer,
+        width: u32,
+        height: u32,
+    ) -> egui::TextureId {
+        let up_to_date = self
+            .egui_texture
+            .as_ref()
+            .is_some_and(|(_, texture)| texture.width() == width && texture.height() == height);
+        if !up_to_date {
+            let texture = self.device.create_texture(&TextureDescriptor {
+                label: Some("egui texture"),
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    ..Default::default()
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: self.swapchain_format,
+                usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            });
+            let view = texture.create_view(&TextureViewDescriptor::default());
+            let id = match self.egui_texture.take() {
+                Some((id, _)) => {
+                    renderer.update_egui_texture_from_wgpu_texture(
+                        &self.device,
+                        &view,
+                        FilterMode::Linear,
+                        id,
+                    );
+                    id
+                }
+                None => renderer.register_native_texture(&self.device, &view, FilterMode::Linear),
+            };
+            self.egui_texture = Some((id, texture));
+        }
+
+        let (id, texture) = self.egui_texture.as_ref().unwrap();
+        self.render_offscreen(
+            &texture.create_view(&TextureViewDescriptor::default()),
+            width,
+            height,
+        );
+        *id
+    }
+
+    /// Renders a frame of each of `runs` (as returned by `Settings::sweep`) at
+    /// `width`x`height` physical pixels, returning each value alongside its
+    /// frame as RGBA bytes.
+    ///
+    /// Each universe is created from `seed` and run for `steps` steps first, so
+    /// the frames only differ by the swept parameter. The last universe is left
+    /// loaded afterwards.
+    ///
+    /// This waits for the GPU to finish, so it isn't available on the web.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn render_sweep(
+        &mut self,
+        runs: &[(f32, Settings)],
+        steps: u32,
+        seed: u64,
+        width: u32,
+        height: u32,
+    ) -> Vec<(f32, Vec<u8>)> {
+        let LogicalSize {
+            width: sim_width,
+            height: sim_height,
+        } = self.logical_size;
+
+        runs.iter()
+            .map(|(value, settings)| {
+                self.replace_settings_seeded(settings.clone(), 0, seed);
+                // Don't fade in from the previous run's colors.
+                self.previous_colors = None;
+                for _ in 0..steps {
+                    self.sim.step(sim_width, sim_height);
+
+                    // Export every step so that the trails are from this universe too.
+                    self.particle_segment += 1;
+                    self.particle_segment %= TRAIL_LENGTH;
+                    self.export_segment();
+                }
+                (*value, self.render_to_size(width, height))
+            })
+            .collect()
+    }
+
+    /// Returns the amount of time each of the `physics_substeps` substeps of
+    /// a step should advance the simulation by.
+    fn substep_dt(&self) -> f32 {
+        let mut dt = 1.0 / self.physics_substeps as f32;
+        if self.real_time {
+            // This deliberately ignores `zoom_time_dilation`, so that it still slows
+            // things down.
+            dt *= real_time_dt(self.step_rate);
+        }
+        dt
+    }
+
+    /// Runs a single step of `sim`, split into `substeps` substeps of `dt`
+    /// each, in a window `width`x`height` logical pixels in size, drawing any
+    /// randomness from `rng`.
+    ///
+    /// This doesn't take `self`, so that it can run on another thread while
+    /// `self` is used for drawing.
+    fn step_sim(sim: &mut Sim, rng: &mut StdRng, substeps: u32, dt: f32, width: f32, height: f32) {
+        for _ in 0..substeps {
+            sim.step_dt(width, height, dt);
+        }
+        sim.apply_life_rules(width, height, rng);
+        sim.apply_spawn_schedule(rng);
+    }
+
+    /// Runs `steps` steps of `sim` with `step_sim`, returning the particles
+    /// after each one.
+    fn step_sim_snapshots(
+        sim: &mut Sim,
+        rng: &mut StdRng,
+        steps: u32,
+        substeps: u32,
+        dt: f32,
+        width: f32,
+        height: f32,
+    ) -> Vec<Vec<Particle>> {
+        (0..steps)
+            .map(|_| {
+                Self::step_sim(sim, rng, substeps, dt, width, height);
+                sim.particles.clone()
+            })
+            .collect()
+    }
+
+    /// Runs a single step of the simulation, in a window `width`x`height`
+    /// logical pixels in size, and exports the result into the next segment of
+    /// the trail.
+    fn advance(&mut self, width: f32, height: f32) {
+        let dt = self.substep_dt();
+        Self::step_sim(
+            &mut self.sim,
+            &mut self.step_rng,
+            self.physics_substeps,
+            dt,
+            width,
+            height,
+        );
+
+        self.particle_segment += 1;
+        self.particle_segment %= TRAIL_LENGTH;
+
+        self.export_segment();
+
+        if self.recording {
+            self.history.record(&self.sim.particles);
+        }
+    }
+
+    /// Exports the particles into the current segment of
+    /// `particle_buffer_data`.
+    fn export_segment(&mut self) {
+        self.update_draw_indices();
+
+        let kind_colors = self.kind_colors();
+        self.sim.export_particles(
+            &mut self.particle_buffer_data[self.particle_segment],
+            &self.kind_radii,
+            kind_colors.as_deref(),
+            self.visible_kinds.as_ref(),
+            self.adaptive_trails,
+            self.draw_indices.as_deref(),
+        );
+    }
+
+    /// Sets the order particles are drawn in.
+    pub fn set_draw_order(&mut self, draw_order: DrawOrder) {
+        self.draw_order = draw_order;
+        self.draw_indices = None;
+    }
+
+    /// Recomputes `draw_indices` if they're out of date.
+    fn update_draw_indices(&mut self) {
+        let stale = match &self.draw_indices {
+            None => true,
+            Some(indices) => indices.len() != self.sim.particles.len(),
+        };
+        // Speeds change all the time, but sorting every step is slow, so only do it every so
+        // often.
+        self.draw_indices_age += 1;
+        let outdated =
+            self.draw_order == DrawOrder::BySpeed && self.draw_indices_age >= DRAW_ORDER_INTERVAL;
+
+        if stale || outdated {
+            self.draw_indices = self.sim.draw_order(self.draw_order, &mut OsRng);
+            self.draw_indices_age = 0;
+        }
+    }
+
+    /// Sets what to multiply the HSV value of every color in the palette by
+    /// when drawing it.
+    pub fn set_palette_brightness(&mut self, brightness: f32) {
+        self.palette_brightness = brightness;
+        // Update the current frame straight away, in case the simulation isn't running.
+        self.export_segment();
+    }
+
+    /// Sets what to multiply the saturation of every color in the palette by
+    /// when drawing it.
+    pub fn set_palette_saturation(&mut self, saturation: f32) {
+        self.palette_saturation = saturation;
+        self.export_segment();
+    }
+
+    /// Returns the colors to draw each kind with, or `None` if they're just
+    /// `sim.colors`.
+    ///
+    /// They're different while fading from the previous palette after the
+    /// settings were replaced, and when the palette's brightness or saturation
+    /// has been adjusted.
+    fn kind_colors(&self) -> Option<Vec<LinSrgb>> {
+        let transition = self.previous_colors.as_ref().and_then(|(previous, start)| {
+            let progress = transition_progress(start.elapsed(), self.color_transition)?;
+            (!previous.is_empty()).then_some((previous, progress))
+        });
+        let adjusted = self.palette_brightness != 1.0 || self.palette_saturation != 1.0;
+        if transition.is_none() && !adjusted {
+            return None;
+        }
+
+        let colors = self.sim.colors.iter().enumerate().map(|(kind, &color)| {
+            let color = match transition {
+                Some((previous, progress)) => {
+                    // There might be more kinds than there used to be, so reuse the old colors.
+                    let old = previous[kind % previous.len()];
+                    self.color_space.mix(old, color, progress)
+                }
+                None => color,
+            };
+            adjust_color(color, self.palette_brightness, self.palette_saturation)
+        });
+        Some(colors.collect())
+    }
+
+    /// Renders `duration` of the simulation as it would run in real time at
+    /// `fps` frames per second, passing each frame to `sink` as RGBA bytes at
+    /// `width`x`height` physical pixels.
+    ///
+    /// Unlike `render`, this doesn't depend on how long anything takes: between
+    /// frames, it runs exactly as many steps as `step_rate` says should happen
+    /// in that time, never skipping any. Returns the number of frames rendered.
+    ///
+    /// This waits for the GPU to finish, so it isn't available on the web.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn render_realtime(
+        &mut self,
+        fps: u32,
+        duration: Duration,
+        width: u32,
+        height: u32,
+        mut sink: impl FnMut(Vec<u8>),
+    ) -> u64 {
+        let LogicalSize {
+            width: sim_width,
+            height: sim_height,
+        } = self.logical_size;
+
+        let frames = realtime_frame_count(fps, duration);
+        let mut steps = 0;
+        for frame in 1..=frames {
+            // Work out the total number of steps from the start each time, so that rounding
+            // errors don't build up.
+            let target_steps = frame * u64::from(self.step_rate) / u64::from(fps);
+            while steps < target_steps {
+                self.advance(sim_width, sim_height);
+                steps += 1;
+            }
+
+            self.update_lines(sim_width, sim_height);
+            sink(self.render_to_size(width, height));
+        }
+
+        frames
+    }
+
+    /// Updates the buffers of whichever lines are being shown, for a window
+    /// `width`x`height` logical pixels in size.
+    fn update_lines(&mut self, width: f32, height: f32) {
+        if self.show_bonds {
+            self.update_bonds(width, height);
+        }
+        if self.show_velocity_vectors {
+            self.update_velocity_vectors(width, height);
+        }
+    }
+
+    /// Fills `velocity_buffer` with a line from each particle showing its
+    /// velocity, for a window `width`x`height` logical pixels in size.
+    fn update_velocity_vectors(&mut self, width: f32, height: f32) {
+        let kind_colors = self.kind_colors();
+        let colors = kind_colors.as_deref().unwrap_or(&self.sim.colors);
+
+        let mut vertices = Vec::with_capacity(2 * self.sim.particles.len());
+        for p in self.sim.particles.iter() {
+            let color = colors[p.kind];
+            vertices.push(BondVertex {
+                anchor: p.pos,
+                offset: Vec2::ZERO,
+                color,
+            });
+            vertices.push(BondVertex {
+                anchor: p.pos,
+                offset: velocity_line_offset(p.vel, width, height),
+                color,
+            });
+        }
+
+        self.queue
+            .write_buffer(&self.velocity_buffer, 0, bytemuck::cast_slice(&vertices));
+    }
+
+    /// Fills `bond_buffer` with lines between the bonded particles, for a
+    /// window `width`x`height` logical pixels in size.
+    fn update_bonds(&mut self, width: f32, height: f32) {
+        let kind_colors = self.kind_colors();
+        let colors = kind_colors.as_deref().unwrap_or(&self.sim.colors);
+
+        let mut vertices = Vec::new();
+        for (i, j) in self.sim.bonds(width, height, MAX_BONDS) {
+            let p = self.sim.particles[i];
+            let q = self.sim.particles[j];
+            let color = self.color_space.mix(colors[p.kind], colors[q.kind], 0.5);
+
+            let raw_delta = q.pos - p.pos;
+            let mut delta = raw_delta;
+            if self.sim.wrap {
+                delta.x += -2.0 * f32::floor(0.5 * delta.x + 0.5);
+                delta.y += -2.0 * f32::floor(0.5 * delta.y + 0.5);
+            }
+
+            let mut line = |anchor, offset| {
+                vertices.push(BondVertex {
+                    anchor,
+                    offset: Vec2::ZERO,
+                    color,
+                });
+                vertices.push(BondVertex {
+                    anchor,
+                    offset,
+                    color,
+                });
+            };
+
+            line(p.pos, delta);
+            if delta != raw_delta {
+                // The bond wraps around the edge, so also draw the half coming from the other
+                // particle.
+                line(q.pos, -delta);
+            }
+        }
+
+        self.queue
+            .write_buffer(&self.bond_buffer, 0, bytemuck::cast_slice(&vertices));
+        self.bond_vertices = vertices.len() as u32;
+    }
+
+    /// Bins the particles into `density_texture`.
+    fn update_density(&self, intensity: f32) {
+        let resolution = self.density_resolution as usize;
+        let mut counts = vec![0u32; resolution * resolution];
+        for particle in self.sim.particles.iter() {
+            // Map from clip space to texel coordinates, remembering that textures start from
+            // the top.
+            let x = (0.5 * (particle.pos.x + 1.0) * resolution as f32) as usize;
+            let y = (0.5 * (1.0 - particle.pos.y) * resolution as f32) as usize;
+            counts[y.min(resolution - 1) * resolution + x.min(resolution - 1)] += 1;
+        }
+
+        let texels: Vec<u8> = counts
+            .into_iter()
+            .map(|count| (255.0 * (1.0 - f32::exp(-intensity * count as f32))) as u8)
+            .collect();
+
+        self.queue.write_texture(
+            ImageCopyTexture {
+                texture: &self.density_texture,
+                mip_level: 0,
+                origin: Origin3d::ZERO,
+                aspect: TextureAspect::All,
+            },
+            &texels,
+            ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(self.density_resolution),
+                rows_per_image: None,
+            },
+            wgpu::Extent3d {
+                width: self.density_resolution,
+                height: self.density_resolution,
+                ..Default::default()
+            },
+        );
+    }
+
+    /// Sets what's drawn behind the particles.
+    pub fn set_background(&mut self, background: BackgroundMode) {
+        self.background = background;
+
+        if let BackgroundMode::DensityGlow { palette, .. } = background {
+            let [low, high] = palette;
+            let settings = BackgroundSettings {
+                low: vec4(low.red, low.green, low.blue, 1.0),
+                high: vec4(high.red, high.green, high.blue, 1.0),
+            };
+            self.queue
+                .write_buffer(&self.background_buffer, 0, bytemuck::bytes_of(&settings));
+        }
+    }
+
+    /// Sets the width/height of the texture used for
+    /// `BackgroundMode::DensityGlow`.
+    ///
+    /// Lower resolutions are cheaper and blurrier. The resolution is clamped
+    /// to at least 1 and at most the largest texture the device supports.
+    pub fn set_density_resolution(&mut self, resolution: u32) {
+        let max_resolution = self.device.limits().max_texture_dimension_2d;
+        let resolution = resolution.clamp(1, max_resolution);
+        self.density_resolution = resolution;
+        self.density_texture = create_density_texture(&self.device, resolution);
+        self.density_bind_group = create_density_bind_group(
+            &self.device,
+            &self.density_bind_group_layout,
+            &self.density_texture,
+            &self.density_sampler,
+            &self.background_buffer,
+        );
+    }
+
+    /// Sets how particles are blended together, rebuilding the pipelines used
+    /// to draw them.
+    pub fn set_blend_mode(&mut self, blend_mode: BlendMode) {
+        self.blend_mode = blend_mode;
+        self.render_pipeline = create_particle_pipeline(
+            &self.device,
+            &self.pipeline_layout,
+            &self.shader,
+            self.swapchain_format,
+            blend_mode,
+            false,
+        );
+        self.point_pipeline = create_particle_pipeline(
+            &self.device,
+            &self.pipeline_layout,
+            &self.shader,
+            self.swapchain_format,
+            blend_mode,
+            true,
+        );
+    }
+
+    /// Returns whether particles are currently being drawn as points in the
+    /// window.
+    pub fn points_active(&self) -> bool {
+        self.points_active_at(self.window_pixel_scale())
+    }
+
+    /// Returns whether particles are drawn as points into a target with
+    /// `pixel_scale` physical pixels per logical pixel of the window.
+    fn points_active_at(&self, pixel_scale: f32) -> bool {
+        let max_radius = self.kind_radii.iter().copied().fold(0.0, f32::max);
+        self.point_mode
+            .use_points(max_radius * self.zoom * pixel_scale)
+    }
+
+    /// Returns the number of physical pixels the window's scene is rendered
+    /// with per logical pixel, taking `render_scale` into account.
+    fn window_pixel_scale(&self) -> f32 {
+        self.render_size().height as f32 / self.logical_size.height
+    }
+
+    /// Returns the number of steps actually being run per second, which is
+    /// `step_rate` unless `zoom_time_dilation` is slowing it down.
+    pub fn effective_step_rate(&self) -> u32 {
+        if self.zoom_time_dilation {
+            dilated_step_rate(self.step_rate, self.zoom)
+        } else {
+            self.step_rate
+        }
+    }
+
+    /// Sets what to do when rendering falls behind the simulation.
+    pub fn set_catchup_policy(&mut self, catch_up: CatchUp) {
+        self.catch_up = catch_up;
+    }
+
+    pub fn toggle_wrap(&mut self) {
+        self.sim.wrap = !self.sim.wrap;
+
+        self.queue.write_buffer(
+            &self.settings_buffer,
+            0,
+            bytemuck::bytes_of(&(self.sim.wrap as u32)),
+        );
+
+        // Make sure the camera is within bounds
+        self.set_camera();
+    }
+
+    /// Switches to a new universe created from `settings` and a seed picked
+    /// with `rng`, which is run for `warmup_steps` steps before it's shown.
+    ///
+    /// Warming up skips past the initial explosion of presets which start out
+    /// chaotic and settle down into something more interesting.
+    pub fn replace_settings<R: Rng>(&mut self, settings: Settings, warmup_steps: u32, rng: &mut R) {
+        self.replace_settings_seeded(settings, warmup_steps, rng.gen());
+    }
+
+    /// Runs the simulation for `steps` steps without showing them, and then
+    /// replaces the whole trail with where the particles end up.
+    fn warm_up(&mut self, steps: u32) {
+        if steps == 0 {
+            return;
+        }
+
+        let LogicalSize { width, height } = self.logical_size;
+        for _ in 0..steps {
+            self.sim.step(width, height);
+        }
+
+        for segment in 0..TRAIL_LENGTH {
+            self.particle_segment = segment;
+            self.export_segment();
+        }
+    }
+
+    /// Like `replace_settings`, but creates the new universe from `seed` with
+    /// `Sim::from_seed`, so that the same settings and seed always give the
+    /// same universe.
+    ///
+    /// Options which aren't part of the universe itself are carried over from
+    /// the current one (see `Sim::carry_over_options_from`); with the default
+    /// options, the result is exactly what `Sim::from_seed` gives.
+    pub fn replace_settings_seeded(&mut self, settings: Settings, warmup_steps: u32, seed: u64) {
+        let settings = clamp_particles(settings);
+        if let Some(profiler) = &mut self.profiler {
+            profiler.set_settings(settings_label(&settings));
+        }
+        self.seed = Some(seed);
+        self.step_rng = Sim::step_rng(seed);
+        self.previous_colors = Some((self.sim.colors.clone(), Instant::now()));
+        let mut sim = Sim::from_seed(settings, seed);
+        sim.carry_over_options_from(&self.sim);
+        self.sim = sim;
+        self.kind_radii = vec![RADIUS; self.sim.colors.len()];
+        // The old kinds might not exist anymore.
+        self.visible_kinds = None;
+
+        // Place the particles again in case the carried-over options place them differently.
+        self.regenerate_particles(&mut Sim::particles_rng(seed));
+        self.warm_up(warmup_steps);
+    }
+
+    /// Automatically switches to a new universe every `interval`, or stops
+    /// doing so if it's `None`.
+    pub fn set_auto_cycle(&mut self, interval: Option<Duration>) {
+        self.auto_cycle = interval;
+        self.last_cycle = Instant::now();
+    }
+
+    /// Switches to a new universe for `auto_cycle`.
+    fn cycle(&mut self) {
+        if self.auto_cycle_presets {
+            let names = Settings::preset_names();
+            let name = names[OsRng.gen_range(0..names.len())];
+            log::info!("Switching to the {name} preset");
+            let settings = Settings::by_name(name).expect("preset names should be valid");
+            self.replace_settings_seeded(settings, 0, OsRng.gen());
+        } else {
+            self.regenerate_particles(&mut OsRng);
+        }
+    }
+
+    /// Saves a copy of the current universe into a slot of the gallery, under
+    /// `name`.
+    pub fn save_to_gallery(&mut self, slot: usize, name: String) {
+        self.gallery[slot] = Some(GallerySlot {
+            name,
+            sim: self.sim.clone(),
+        });
+    }
+
+    /// Saves the current zoom and camera position into a bookmark slot.
+    pub fn save_bookmark(&mut self, slot: usize) {
+        self.camera_bookmarks[slot] = Some((self.zoom, self.camera));
+    }
+
+    /// Moves the camera to the position saved in a bookmark slot, returning
+    /// whether there was one.
+    pub fn recall_bookmark(&mut self, slot: usize) -> bool {
+        let Some((zoom, camera)) = self.camera_bookmarks[slot] else {
+            return false;
+        };
+
+        self.zoom = zoom;
+        self.camera = camera;
+        self.set_camera();
+
+        true
+    }
+
+    /// Switches to the universe saved in a slot of the gallery, returning
+    /// the slot's name if there was one.
+    pub fn recall_from_gallery(&mut self, slot: usize) -> Option<String> {
+        let GallerySlot { name, sim } = self.gallery[slot].clone()?;
+
+        self.sim = sim;
+        self.kind_radii = vec![RADIUS; self.sim.colors.len()];
+        self.visible_kinds = None;
+        self.history.clear();
+        self.seed = None;
+
+        // The saved universe might not have the same wrapping setting.
+        self.queue.write_buffer(
+            &self.settings_buffer,
+            0,
+            bytemuck::bytes_of(&(self.sim.wrap as u32)),
+        );
+        self.set_camera();
+
+        Some(name)
+    }
+
+    pub fn regenerate_particles<R: Rng>(&mut self, rng: &mut R) {
+        self.sim.regenerate_particles(rng);
+        self.history.clear();
 
         // Reset camera and zoom
         self.camera = vec2(0.0, 0.0);
@@ -572,37 +2882,730 @@ impl State {
         self.set_camera();
     }
 
+    /// Starts or stops recording the simulation's history for `scrub`.
+    ///
+    /// Stopping throws away the history, and resumes the simulation if it was
+    /// being scrubbed through.
+    pub fn set_recording(&mut self, recording: bool) {
+        self.recording = recording;
+        if !recording {
+            self.history.clear();
+        }
+    }
+
+    /// Shows only particles of `kind`, or shows every kind again if only `kind`
+    /// was already being shown.
+    pub fn toggle_solo_kind(&mut self, kind: usize) {
+        if self.visible_kinds == Some(BTreeSet::from([kind])) {
+            self.visible_kinds = None;
+        } else {
+            self.visible_kinds = Some(BTreeSet::from([kind]));
+        }
+
+        // Update the current frame straight away, in case the simulation isn't running.
+        self.export_segment();
+    }
+
+    /// Moves `steps` steps forward (or backward, if negative) through the
+    /// recorded history, pausing the simulation on that step until `resume`
+    /// is called.
+    pub fn scrub(&mut self, steps: isize) {
+        if let Some(particles) = self.history.scrub(steps) {
+            self.sim.particles.clear();
+            self.sim.particles.extend_from_slice(particles);
+
+            self.particle_segment += 1;
+            self.particle_segment %= TRAIL_LENGTH;
+            self.export_segment();
+        }
+    }
+
+    /// Continues the simulation from the step currently being scrubbed to,
+    /// discarding the history after it.
+    pub fn resume(&mut self) {
+        self.history.resume();
+        self.last_step = Instant::now();
+    }
+
+    /// Moves the camera to `camera` with the given zoom.
+    ///
+    /// The zoom is clamped between `MIN_ZOOM` and `MAX_ZOOM`. When not
+    /// wrapping, the camera is then clamped to keep the view inside the
+    /// simulation, just like when it's dragged there; when wrapping, it's only
+    /// wrapped around to the equivalent position, so off-center views are kept.
+    pub fn set_view(&mut self, zoom: f32, camera: Vec2) {
+        self.zoom = zoom.clamp(MIN_ZOOM, MAX_ZOOM);
+        self.camera = camera;
+        self.set_camera();
+    }
+
+    /// Sets the strength of `focus_vignette`, or turns it off if `strength` is
+    /// `None`.
+    pub fn set_focus_vignette(&mut self, strength: Option<f32>) {
+        self.focus_vignette = strength;
+        self.queue.write_buffer(
+            &self.settings_buffer,
+            16,
+            bytemuck::bytes_of(&strength.unwrap_or(0.0)),
+        );
+    }
+
+    /// Returns the point in the simulation shown at `screen_pos`, which is an
+    /// offset from the center of the window in clip space.
+    pub fn screen_to_sim(&self, screen_pos: Vec2) -> Vec2 {
+        screen_pos / self.zoom - self.camera
+    }
+
     /// Sets the camera zoom and position.
     pub fn set_camera(&mut self) {
-        if !self.sim.wrap {
-            let view_radius = 1.0 / self.zoom;
+        self.camera = constrain_camera(self.camera, self.zoom, self.sim.wrap);
+
+        self.queue.write_buffer(
+            &self.settings_buffer,
+            4,
+            bytemuck::bytes_of(&[self.zoom, self.camera[0], self.camera[1]]),
+        )
+    }
+}
 
-            self.camera = self.camera.clamp(
-                vec2(-1.0 + view_radius, -1.0 + view_radius),
-                vec2(1.0 - view_radius, 1.0 - view_radius),
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::settings::SweepParam;
+    use crate::sim::clip_to_pixels;
+    use crate::sim::LifeRules;
+    use crate::sim::SpawnSchedule;
+    use crate::sim::UniverseBuilder;
+
+    #[test]
+    fn due_steps_follows_catch_up_policy() {
+        let step_period = Duration::from_millis(10);
+        let start = Instant::now();
+        let run = |catch_up, elapsed| {
+            let mut last_step = start;
+            let steps = due_steps(&mut last_step, start + elapsed, step_period, catch_up);
+            (steps, last_step - start)
+        };
+
+        // When keeping up, every policy runs the steps that are due.
+        for catch_up in [CatchUp::Drop, CatchUp::SlowDown, CatchUp::Cap(5)] {
+            assert_eq!(
+                run(catch_up, Duration::from_millis(35)),
+                (3, Duration::from_millis(30))
             );
-        } else {
-            while self.camera[0] > 1.0 {
-                self.camera[0] -= 2.0;
+            assert_eq!(run(catch_up, Duration::from_millis(5)), (0, Duration::ZERO));
+        }
+
+        // 50 steps behind.
+        let behind = Duration::from_millis(505);
+        assert_eq!(
+            run(CatchUp::Drop, behind),
+            (DROP_STEPS, DROP_STEPS * step_period)
+        );
+        assert_eq!(run(CatchUp::SlowDown, behind), (50, 50 * step_period));
+        // The other 45 steps are still owed.
+        assert_eq!(run(CatchUp::Cap(5), behind), (5, 5 * step_period));
+
+        // After a long stall, `SlowDown` only runs a second's worth.
+        let (steps, last_step) = run(CatchUp::SlowDown, Duration::from_secs(3600));
+        assert!(steps <= 100, "{steps} steps run");
+        assert!(Duration::from_secs(3600) - last_step <= step_period);
+    }
+
+    #[test]
+    fn threaded_steps_match_inline_steps() {
+        let mut sim = Sim::from_seed(Settings::balanced(), 7);
+        let kinds = sim.colors.len();
+        sim.life_rules = Some(LifeRules {
+            radius: 15.0,
+            birth_neighbours: vec![Some(3); kinds],
+            birth_chance: 0.05,
+            death_steps: vec![Some(20); kinds],
+        });
+        sim.spawn_schedule = Some(SpawnSchedule {
+            target: MAX_PARTICLES,
+            rate: 0.5,
+            kind_weights: None,
+        });
+        let (steps, substeps, dt) = (50, 2, 0.5);
+
+        // What `advance` does each step.
+        let mut inline_sim = sim.clone();
+        let mut inline_rng = Sim::step_rng(7);
+        let inline: Vec<_> = (0..steps)
+            .map(|_| {
+                State::step_sim(&mut inline_sim, &mut inline_rng, substeps, dt, 800.0, 600.0);
+                inline_sim.particles.clone()
+            })
+            .collect();
+
+        // What `render_threaded` does.
+        let mut threaded_rng = Sim::step_rng(7);
+        let threaded = thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    State::step_sim_snapshots(
+                        &mut sim,
+                        &mut threaded_rng,
+                        steps,
+                        substeps,
+                        dt,
+                        800.0,
+                        600.0,
+                    )
+                })
+                .join()
+                .unwrap()
+        });
+
+        assert_eq!(threaded.len(), inline.len());
+        for (threaded, inline) in threaded.iter().zip(&inline) {
+            assert_eq!(threaded.len(), inline.len());
+            for (threaded, inline) in threaded.iter().zip(inline) {
+                assert_eq!(threaded.pos, inline.pos);
+                assert_eq!(threaded.vel, inline.vel);
+                assert_eq!(threaded.kind, inline.kind);
             }
+        }
+        // Make sure the life rules and spawn schedule actually did something.
+        let counts: Vec<_> = inline.iter().map(Vec::len).collect();
+        assert!(counts.windows(2).any(|pair| pair[0] != pair[1]));
+        // Both leave the RNG in the same state for the next frame.
+        assert_eq!(threaded_rng.gen::<u64>(), inline_rng.gen::<u64>());
+    }
 
-            while self.camera[0] < -1.0 {
-                self.camera[0] += 2.0;
+    #[test]
+    fn reports_every_frame_to_on_frame() {
+        let sim = Sim::from_seed(Settings::balanced(), 0);
+        let reported = Rc::new(RefCell::new(Vec::new()));
+        let mut on_frame: Option<FrameCallback> = Some(Box::new({
+            let reported = Rc::clone(&reported);
+            move |sim: &Sim, info: FrameInfo| {
+                reported
+                    .borrow_mut()
+                    .push((sim.particles.len(), info.frame, info.substeps));
             }
+        }));
 
-            while self.camera[1] > 1.0 {
-                self.camera[1] -= 2.0;
+        let mut frame = 0;
+        for steps in [3, 0, 20] {
+            report_frame(&mut on_frame, &sim, &mut frame, steps, Instant::now());
+        }
+        // Frames with no steps are still counted.
+        let particles = sim.particles.len();
+        assert_eq!(
+            *reported.borrow(),
+            [(particles, 0, 3), (particles, 1, 0), (particles, 2, 20)]
+        );
+
+        // Without a callback, frames are still counted.
+        report_frame(&mut None, &sim, &mut frame, 1, Instant::now());
+        assert_eq!(frame, 4);
+    }
+
+    #[test]
+    fn clamps_particles_to_buffer() {
+        let settings = Settings {
+            particles: MAX_PARTICLES + 100,
+            ..Settings::balanced()
+        };
+        assert_eq!(clamp_particles(settings).particles, MAX_PARTICLES);
+
+        let settings = Settings {
+            particles: 10,
+            ..Settings::balanced()
+        };
+        assert!(clamp_particles(settings.clone()) == settings);
+    }
+
+    #[test]
+    fn additive_blending_adds_colors() {
+        let alpha_over = BlendMode::AlphaOver.blend_state();
+        let additive = BlendMode::Additive.blend_state();
+        // Both fade the source by its alpha, so trails still fade out...
+        assert_eq!(alpha_over.color.src_factor, BlendFactor::SrcAlpha);
+        assert_eq!(additive.color.src_factor, BlendFactor::SrcAlpha);
+        // ...but only alpha-over covers up what's behind.
+        assert_eq!(alpha_over.color.dst_factor, BlendFactor::OneMinusSrcAlpha);
+        assert_eq!(additive.color.dst_factor, BlendFactor::One);
+        assert_eq!(additive.color.operation, BlendOperation::Add);
+        assert_eq!(additive.alpha, alpha_over.alpha);
+    }
+
+    #[test]
+    fn constrains_camera_to_simulation() {
+        // At zoom 1, the whole simulation is in view, so the camera can't move.
+        assert_eq!(constrain_camera(vec2(0.5, -0.3), 1.0, false), Vec2::ZERO);
+        // At zoom 4, the view is half a unit across, so the camera can move
+        // up to 0.75 from the middle.
+        assert_eq!(
+            constrain_camera(vec2(0.5, -0.9), 4.0, false),
+            vec2(0.5, -0.75)
+        );
+
+        // When wrapping, views off the edge are allowed, but wrap around.
+        assert_eq!(
+            constrain_camera(vec2(0.5, -0.9), 4.0, true),
+            vec2(0.5, -0.9)
+        );
+        assert_eq!(
+            constrain_camera(vec2(1.5, -3.5), 1.0, true),
+            vec2(-0.5, 0.5)
+        );
+    }
+
+    #[test]
+    fn scales_render_size() {
+        let size = PhysicalSize::new(1920, 1080);
+        assert_eq!(scaled_size(size, 1.0), size);
+        assert_eq!(scaled_size(size, 0.5), PhysicalSize::new(960, 540));
+        assert_eq!(scaled_size(size, 2.0), PhysicalSize::new(3840, 2160));
+        // Rounded to the nearest pixel, and never empty.
+        assert_eq!(
+            scaled_size(PhysicalSize::new(101, 3), 0.5),
+            PhysicalSize::new(51, 2)
+        );
+        assert_eq!(scaled_size(size, 0.0), PhysicalSize::new(1, 1));
+    }
+
+    /// Creates a `State` with `State::from_wgpu` using a device with no
+    /// surface, or returns `None` if there's no adapter to get one from.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn headless_state(sim: Sim) -> Option<State> {
+        let instance = wgpu::Instance::new(InstanceDescriptor {
+            backends: Backends::all(),
+            dx12_shader_compiler: Default::default(),
+        });
+        let adapter = pollster::block_on(instance.request_adapter(&Default::default()))?;
+        let (device, queue) = pollster::block_on(adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                label: None,
+                features: wgpu::Features::empty(),
+                limits: Limits::downlevel_webgl2_defaults().using_resolution(adapter.limits()),
+            },
+            None,
+        ))
+        .ok()?;
+        Some(State::from_wgpu(
+            device,
+            queue,
+            None,
+            TextureFormat::Rgba8UnormSrgb,
+            PhysicalSize::new(160, 120),
+            1.0,
+            sim,
+        ))
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn creates_state_from_existing_device() {
+        let sim = Sim::from_seed(Settings::balanced(), 0);
+        let particles = sim.particles.len();
+        let Some(state) = headless_state(sim) else {
+            eprintln!("no wgpu adapter available, skipping");
+            return;
+        };
+        assert_eq!(state.surface_size, PhysicalSize::new(160, 120));
+        assert_eq!(state.logical_size, LogicalSize::new(160.0, 120.0));
+        assert_eq!(state.sim.particles.len(), particles);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn renders_at_twice_the_window_size() {
+        let Some(state) = headless_state(Sim::from_seed(Settings::balanced(), 0)) else {
+            eprintln!("no wgpu adapter available, skipping");
+            return;
+        };
+        let pixels = state.render_to_size(320, 240);
+        assert_eq!(pixels.len(), 320 * 240 * 4);
+        // Something other than the background got drawn.
+        assert!(pixels.chunks(4).any(|pixel| pixel != &pixels[..4]));
+    }
+
+    #[test]
+    fn cycles_after_each_interval() {
+        let interval = Duration::from_secs(60);
+        let start = Instant::now();
+        let mut last_cycle = start;
+
+        assert!(!cycle_due(Some(interval), &mut last_cycle, start));
+        assert!(!cycle_due(
+            Some(interval),
+            &mut last_cycle,
+            start + Duration::from_secs(59)
+        ));
+        assert!(cycle_due(
+            Some(interval),
+            &mut last_cycle,
+            start + Duration::from_secs(61)
+        ));
+        assert_eq!(last_cycle, start + Duration::from_secs(61));
+        // The next interval starts from when it last cycled.
+        assert!(!cycle_due(
+            Some(interval),
+            &mut last_cycle,
+            start + Duration::from_secs(120)
+        ));
+        assert!(cycle_due(
+            Some(interval),
+            &mut last_cycle,
+            start + Duration::from_secs(121)
+        ));
+
+        // Nothing happens when it's turned off.
+        let mut last_cycle = start;
+        assert!(!cycle_due(
+            None,
+            &mut last_cycle,
+            start + Duration::from_secs(600)
+        ));
+        assert_eq!(last_cycle, start);
+    }
+
+    #[test]
+    fn zooming_in_dilates_time() {
+        assert_eq!(dilated_step_rate(60, 1.0), 60);
+        assert_eq!(dilated_step_rate(60, 2.0), 30);
+        assert_eq!(dilated_step_rate(60, 4.0), 15);
+        // Zooming out speeds it up.
+        assert_eq!(dilated_step_rate(60, 0.5), 120);
+        // It never stops altogether.
+        assert_eq!(dilated_step_rate(60, 1000.0), 1);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn renders_a_frame_for_each_swept_value() {
+        let Some(mut state) = headless_state(Sim::from_seed(Settings::balanced(), 0)) else {
+            eprintln!("no wgpu adapter available, skipping");
+            return;
+        };
+        let runs = Settings::balanced().sweep(SweepParam::Friction, 0.0..=0.1, 3);
+        let frames = state.render_sweep(&runs, 5, 0, 64, 48);
+        let values: Vec<f32> = frames.iter().map(|&(value, _)| value).collect();
+        assert_eq!(values, [0.0, 0.05, 0.1]);
+        for (_, frame) in &frames {
+            assert_eq!(frame.len(), 64 * 48 * 4);
+        }
+    }
+
+    #[test]
+    fn offsets_a_tile_to_each_side() {
+        let offsets = wrap_tile_offsets();
+        for x in [-2.0, 0.0, 2.0] {
+            for y in [-2.0, 0.0, 2.0] {
+                let count = offsets.iter().filter(|&&o| o == vec2(x, y)).count();
+                // Every neighbouring tile is drawn exactly once, and the simulation itself isn't.
+                let expected = if (x, y) == (0.0, 0.0) { 0 } else { 1 };
+                assert_eq!(count, expected, "tile at ({x}, {y})");
             }
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn ignores_zero_sized_resizes() {
+        let Some(mut state) = headless_state(Sim::from_seed(Settings::balanced(), 0)) else {
+            eprintln!("no wgpu adapter available, skipping");
+            return;
+        };
+        for size in [
+            PhysicalSize::new(0, 120),
+            PhysicalSize::new(160, 0),
+            PhysicalSize::new(0, 0),
+        ] {
+            state.resize(size, 1.0);
+            assert_eq!(state.surface_size, PhysicalSize::new(160, 120));
+            assert_eq!(state.logical_size, LogicalSize::new(160.0, 120.0));
+        }
+        // It can still render at the old size afterwards.
+        assert_eq!(state.render_to_size(160, 120).len(), 160 * 120 * 4);
+
+        state.resize(PhysicalSize::new(200, 100), 2.0);
+        assert_eq!(state.logical_size, LogicalSize::new(100.0, 50.0));
+    }
+
+    #[test]
+    fn color_transitions_progress_linearly() {
+        let duration = Duration::from_millis(500);
+        assert_eq!(transition_progress(Duration::ZERO, duration), Some(0.0));
+        assert_eq!(
+            transition_progress(Duration::from_millis(125), duration),
+            Some(0.25)
+        );
+        assert_eq!(
+            transition_progress(Duration::from_millis(250), duration),
+            Some(0.5)
+        );
+        assert_eq!(transition_progress(duration, duration), None);
+        assert_eq!(transition_progress(Duration::from_secs(3), duration), None);
+        // A zero-length transition is over straight away.
+        assert_eq!(transition_progress(Duration::ZERO, Duration::ZERO), None);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn renders_exact_number_of_realtime_frames() {
+        assert_eq!(realtime_frame_count(30, Duration::from_secs(2)), 60);
+        assert_eq!(realtime_frame_count(24, Duration::from_millis(1500)), 36);
+        assert_eq!(realtime_frame_count(60, Duration::ZERO), 0);
+        // Partial frames are rounded to the nearest frame.
+        assert_eq!(realtime_frame_count(10, Duration::from_millis(1049)), 10);
+        assert_eq!(realtime_frame_count(10, Duration::from_millis(1051)), 11);
+
+        let Some(mut state) = headless_state(Sim::from_seed(Settings::balanced(), 0)) else {
+            eprintln!("no wgpu adapter available, skipping");
+            return;
+        };
+        let start_age = state.sim.particles[0].age;
+        let mut frames = 0;
+        let rendered = state.render_realtime(10, Duration::from_millis(500), 32, 24, |frame| {
+            assert_eq!(frame.len(), 32 * 24 * 4);
+            frames += 1;
+        });
+        assert_eq!(rendered, 5);
+        assert_eq!(frames, 5);
+        // Every step in that half a second was run.
+        let steps = state.sim.particles[0].age - start_age;
+        assert_eq!(steps, state.step_rate / 2);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn trails_only_leaves_out_current_frame() {
+        let segments: Vec<usize> = drawn_segments(3, false).collect();
+        assert_eq!(segments, [4, 5, 6, 7, 8, 9, 0, 1, 2, 3]);
+        let segments: Vec<usize> = drawn_segments(3, true).collect();
+        assert_eq!(segments, [4, 5, 6, 7, 8, 9, 0, 1, 2]);
+        let segments: Vec<usize> = drawn_segments(TRAIL_LENGTH - 1, true).collect();
+        assert_eq!(segments, [0, 1, 2, 3, 4, 5, 6, 7, 8]);
+
+        // Move a particle along a few steps, far enough that its trail doesn't overlap it.
+        let sim = UniverseBuilder::new(1)
+            .friction(0.0)
+            .particle(0, vec2(-0.5, 0.0))
+            .build()
+            .unwrap();
+        let Some(mut state) = headless_state(sim) else {
+            eprintln!("no wgpu adapter available, skipping");
+            return;
+        };
+        state.sim.particles[0].vel = vec2(20.0, 0.0);
+        state.render_realtime(state.step_rate, Duration::from_millis(50), 64, 48, |_| {});
+        let pos = state.sim.particles[0].pos;
+        let previous_pos = pos
+            - state
+                .sim
+                .clip_velocity(&state.sim.particles[0], 160.0, 120.0);
+
+        let brightness = |pixels: &[u8], pos: Vec2| {
+            let x = ((pos.x + 1.0) / 2.0 * 64.0) as usize;
+            let y = ((1.0 - pos.y) / 2.0 * 48.0) as usize;
+            let i = (y * 64 + x) * 4;
+            u32::from(pixels[i]) + u32::from(pixels[i + 1]) + u32::from(pixels[i + 2])
+        };
+        let full = state.render_to_size(64, 48);
+        state.trails_only = true;
+        let trails = state.render_to_size(64, 48);
+        assert!(brightness(&trails, pos) < brightness(&full, pos));
+        // The trail is still there.
+        assert_eq!(
+            brightness(&trails, previous_pos),
+            brightness(&full, previous_pos)
+        );
+        assert!(brightness(&trails, previous_pos) > brightness(&trails, vec2(0.9, 0.9)));
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn warms_up_new_universes() {
+        let Some(mut state) = headless_state(Sim::from_seed(Settings::balanced(), 0)) else {
+            eprintln!("no wgpu adapter available, skipping");
+            return;
+        };
+        state.replace_settings_seeded(Settings::cells(), 100, 7);
+
+        // Every particle has been through the warm-up steps, at the size it's rendered at.
+        assert!(state.sim.particles.iter().all(|p| p.age == 100));
+        let mut expected = Sim::from_seed(clamp_particles(Settings::cells()), 7);
+        for _ in 0..100 {
+            expected.step(160.0, 120.0);
+        }
+        for (p, expected) in state.sim.particles.iter().zip(&expected.particles) {
+            assert_eq!(p.pos, expected.pos);
+            assert_eq!(p.vel, expected.vel);
+        }
 
-            while self.camera[1] < -1.0 {
-                self.camera[1] += 2.0;
+        // The whole trail is the warmed-up universe. Only positions are compared, since the
+        // colors are still fading in from the previous palette while the segments are exported.
+        let current = state.particle_buffer_data[state.particle_segment];
+        for segment in state.particle_buffer_data.iter() {
+            assert!(segment
+                .iter()
+                .zip(&current)
+                .all(|(a, b)| a.pos() == b.pos()));
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn recalls_camera_bookmarks() {
+        let Some(mut state) = headless_state(Sim::from_seed(Settings::balanced(), 0)) else {
+            eprintln!("no wgpu adapter available, skipping");
+            return;
+        };
+        state.zoom = 3.5;
+        state.camera = vec2(0.25, -0.375);
+        state.set_camera();
+        state.save_bookmark(4);
+
+        state.zoom = 1.0;
+        state.camera = Vec2::ZERO;
+        state.set_camera();
+        assert!(!state.recall_bookmark(5));
+        assert_eq!((state.zoom, state.camera), (1.0, Vec2::ZERO));
+
+        assert!(state.recall_bookmark(4));
+        assert_eq!((state.zoom, state.camera), (3.5, vec2(0.25, -0.375)));
+    }
+
+    #[cfg(feature = "egui")]
+    #[test]
+    fn renders_into_an_egui_texture() {
+        let Some(mut state) = headless_state(Sim::from_seed(Settings::balanced(), 0)) else {
+            eprintln!("no wgpu adapter available, skipping");
+            return;
+        };
+        let mut renderer = egui_wgpu::Renderer::new(&state.device, state.swapchain_format, None, 1);
+
+        let id = state.render_to_egui_texture(&mut renderer, 64, 48);
+        assert!(matches!(id, egui::TextureId::User(_)));
+        assert!(renderer.texture(&id).is_some());
+        let (_, texture) = state.egui_texture.as_ref().unwrap();
+        assert_eq!((texture.width(), texture.height()), (64, 48));
+
+        // The same texture is reused, and replaced under the same ID when the size changes.
+        assert_eq!(state.render_to_egui_texture(&mut renderer, 64, 48), id);
+        assert_eq!(state.render_to_egui_texture(&mut renderer, 128, 96), id);
+        let (_, texture) = state.egui_texture.as_ref().unwrap();
+        assert_eq!((texture.width(), texture.height()), (128, 96));
+    }
+
+    #[test]
+    fn velocity_lines_end_at_scaled_velocity() {
+        // 2 pixels per step to the right is a 10-pixel line, which is 0.025 of clip space's 2
+        // units across 800 pixels.
+        let offset = velocity_line_offset(vec2(2.0, 0.0), 800.0, 600.0);
+        assert!((offset - vec2(0.025, 0.0)).length() < 1e-6, "{offset}");
+        let offset = velocity_line_offset(vec2(0.0, -3.0), 800.0, 600.0);
+        assert!((offset - vec2(0.0, -0.05)).length() < 1e-6, "{offset}");
+
+        // Fast particles' lines are capped at 30 pixels, in the same direction.
+        let offset = velocity_line_offset(vec2(30.0, 40.0), 800.0, 600.0);
+        let pixels = clip_to_pixels(offset, 800.0, 600.0);
+        assert!((pixels - vec2(18.0, 24.0)).length() < 1e-4, "{pixels}");
+
+        assert_eq!(velocity_line_offset(Vec2::ZERO, 800.0, 600.0), Vec2::ZERO);
+    }
+
+    #[test]
+    fn frame_cap_throttles_rendering() {
+        let start = Instant::now();
+        let period = Duration::from_secs(1) / 60;
+
+        // A 144 Hz display capped at 60 FPS only draws on some of its refreshes.
+        let refresh = Duration::from_secs(1) / 144;
+        let mut next_frame = start;
+        let mut frames = 0;
+        for i in 0..144 {
+            if let Some(after) = frame_due(60, next_frame, start + i * refresh) {
+                next_frame = after;
+                frames += 1;
             }
         }
+        assert!((59..=61).contains(&frames), "drew {frames} frames");
+
+        // Frames which aren't due yet are skipped.
+        assert_eq!(frame_due(60, start + period, start), None);
+        // A frame on time schedules the next one a period later.
+        assert_eq!(
+            frame_due(60, start, start + Duration::from_millis(1)),
+            Some(start + period)
+        );
+        // After falling behind, the next frame is a period from now rather than trying to catch
+        // up.
+        let late = start + Duration::from_secs(1);
+        assert_eq!(frame_due(60, start, late), Some(late + period));
+        // A cap of 0 is treated as 1.
+        assert_eq!(
+            frame_due(0, start, start),
+            Some(start + Duration::from_secs(1))
+        );
+    }
 
-        self.queue.write_buffer(
-            &self.settings_buffer,
-            4,
-            bytemuck::bytes_of(&[self.zoom, self.camera[0], self.camera[1]]),
-        )
+    #[test]
+    fn mirror_x_reflects_each_particle() {
+        let particles: Vec<GpuParticle> = [vec2(0.5, 0.25), vec2(-0.1, -0.7)]
+            .into_iter()
+            .map(|pos| GpuParticle::default().with_pos(pos))
+            .collect();
+        let positions = |particles: Vec<GpuParticle>| -> Vec<Vec2> {
+            particles.iter().map(GpuParticle::pos).collect()
+        };
+
+        assert_eq!(
+            positions(Symmetry::None.apply(&particles, Vec2::ZERO, 800.0, 600.0)),
+            [vec2(0.5, 0.25), vec2(-0.1, -0.7)]
+        );
+        assert_eq!(
+            positions(Symmetry::MirrorX.apply(&particles, Vec2::ZERO, 800.0, 600.0)),
+            [
+                vec2(0.5, 0.25),
+                vec2(-0.1, -0.7),
+                vec2(-0.5, 0.25),
+                vec2(0.1, -0.7)
+            ]
+        );
+        // They're reflected about the middle of the screen, where the camera is.
+        let mirrored =
+            positions(Symmetry::MirrorX.apply(&particles, vec2(0.25, 0.0), 800.0, 600.0));
+        assert!((mirrored[2] - vec2(-1.0, 0.25)).length() < 1e-6);
+        assert!((mirrored[3] - vec2(-0.4, -0.7)).length() < 1e-6);
+
+        // Rotated copies stay the same number of pixels from the middle.
+        let rotated =
+            positions(Symmetry::Rotational(5).apply(&particles, Vec2::ZERO, 800.0, 600.0));
+        assert_eq!(rotated.len(), 10);
+        for (i, pos) in rotated.iter().enumerate() {
+            let original = particles[i % 2].pos();
+            let dist = clip_to_pixels(*pos, 800.0, 600.0).length();
+            let expected = clip_to_pixels(original, 800.0, 600.0).length();
+            assert!(
+                (dist - expected).abs() < 1e-3,
+                "copy {i} is {dist} pixels out, not {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn vignette_fades_towards_edges() {
+        let opacity = |pos, camera, zoom| vignette_opacity(0.4, pos, camera, zoom);
+        assert_eq!(opacity(Vec2::ZERO, Vec2::ZERO, 1.0), 1.0);
+        assert!((opacity(vec2(1.0, 0.0), Vec2::ZERO, 1.0) - 0.6).abs() < 1e-6);
+        assert!((opacity(vec2(0.0, -1.0), Vec2::ZERO, 1.0) - 0.6).abs() < 1e-6);
+        // It follows the camera, so whatever's in the middle of the screen is fully visible.
+        assert_eq!(opacity(vec2(0.25, 0.5), vec2(-0.25, -0.5), 3.0), 1.0);
+        // Zooming in brings particles closer to the edges.
+        assert!(
+            opacity(vec2(0.2, 0.0), Vec2::ZERO, 2.0) < opacity(vec2(0.2, 0.0), Vec2::ZERO, 1.0)
+        );
+        assert_eq!(opacity(vec2(5.0, 5.0), Vec2::ZERO, 1.0), 0.0);
+        // Strength 0 turns it off.
+        assert_eq!(vignette_opacity(0.0, vec2(5.0, 5.0), Vec2::ZERO, 1.0), 1.0);
     }
 }
@@ -1,26 +1,93 @@
+use std::time::Duration;
+
 use glam::vec2;
+#[cfg(not(target_arch = "wasm32"))]
+use particle_life::gallery::gallery_from_json;
+#[cfg(not(target_arch = "wasm32"))]
+use particle_life::gallery::gallery_to_json;
+use particle_life::keybindings::Action;
+use particle_life::keybindings::Keybindings;
+use particle_life::profiler::settings_label;
+use particle_life::profiler::StepProfiler;
 use particle_life::settings::Settings;
+use particle_life::settings::REFERENCE_STEP_RATE;
+use particle_life::sim::AdaptiveTrails;
 use particle_life::State;
+use particle_life::DEFAULT_FOCUS_VIGNETTE;
+use particle_life::MAX_ZOOM;
+use particle_life::MIN_ZOOM;
 use rand::rngs::OsRng;
+use rand::Rng;
+use web_time::Instant;
+#[cfg(not(target_arch = "wasm32"))]
+use winit::dpi::LogicalSize;
 use winit::event::ElementState;
 use winit::event::Event;
+use winit::event::MouseButton;
 use winit::event::MouseScrollDelta;
+use winit::event::StartCause;
 use winit::event::WindowEvent;
+use winit::event_loop::ControlFlow;
 use winit::event_loop::EventLoop;
 use winit::event_loop::EventLoopWindowTarget;
 use winit::keyboard::Key;
+use winit::keyboard::KeyCode;
+use winit::keyboard::ModifiersState;
 use winit::keyboard::NamedKey;
+use winit::keyboard::PhysicalKey;
 #[cfg(target_arch = "wasm32")]
 use winit::platform::web::EventLoopExtWebSys;
 use winit::window::Fullscreen;
 use winit::window::Window;
 use winit::window::WindowBuilder;
 
+/// How much `,` and `.` change the friction by.
+const FRICTION_STEP: f32 = 0.005;
+/// The range `,` and `.` keep the friction within.
+const MAX_FRICTION: f32 = 0.5;
+
+/// The radius (in pixels) of the shockwave made by Shift-clicking.
+const SHOCKWAVE_RADIUS: f32 = 150.0;
+/// How hard (in pixels per step) the shockwave made by Shift-clicking kicks
+/// particles at its center.
+const SHOCKWAVE_STRENGTH: f32 = 5.0;
+
+/// The number of frames' worth of step times to keep for each set of settings
+/// while profiling.
+const PROFILE_WINDOW: usize = 1000;
+
+/// The smallest the window can be resized to.
+#[cfg(not(target_arch = "wasm32"))]
+const MIN_WINDOW_SIZE: LogicalSize<u32> = LogicalSize::new(200, 150);
+
+/// The file to load settings like keybindings from, relative to the working
+/// directory.
+#[cfg(not(target_arch = "wasm32"))]
+const CONFIG_PATH: &str = "particle-life.toml";
+
+/// The file the gallery is kept in between runs, relative to the working
+/// directory. This is separate from `CONFIG_PATH` since it's rewritten every
+/// time a universe is saved, whereas the config is written by hand.
+#[cfg(not(target_arch = "wasm32"))]
+const GALLERY_PATH: &str = "particle-life-gallery.json";
+
+/// How long messages such as the name of a recalled gallery slot stay in the
+/// window's title.
+const NOTICE_DURATION: Duration = Duration::from_secs(3);
+
+/// How often `a` switches to a new preset.
+const AUTO_CYCLE_INTERVAL: Duration = Duration::from_secs(60);
+
 fn main() {
     #[cfg(target_arch = "wasm32")]
     // Do this as early as physically possible.
     console_error_panic_hook::set_once();
 
+    #[cfg(not(target_arch = "wasm32"))]
+    if run_headless() {
+        return;
+    }
+
     let event_loop = EventLoop::new().unwrap();
     #[allow(unused_mut)]
     let mut builder = WindowBuilder::new().with_title("Particle Life");
@@ -36,6 +103,13 @@ fn main() {
         builder = builder.with_canvas(Some(canvas.dyn_into().unwrap()));
     }
 
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        builder = builder
+            .with_window_icon(Some(window_icon()))
+            .with_min_inner_size(MIN_WINDOW_SIZE);
+    }
+
     let window = builder.build(&event_loop).unwrap();
 
     #[cfg(not(target_arch = "wasm32"))]
@@ -52,77 +126,454 @@ fn main() {
     }
 }
 
+/// Runs the simulation without a window if `--headless` was passed on the
+/// command line, returning whether it was.
+///
+/// Exits the process if the arguments are invalid or the run fails.
+#[cfg(not(target_arch = "wasm32"))]
+fn run_headless() -> bool {
+    use std::io;
+    use std::process;
+
+    use particle_life::headless;
+    use particle_life::headless::HeadlessArgs;
+
+    let args = match HeadlessArgs::parse(std::env::args().skip(1)) {
+        Ok(Some(args)) => args,
+        Ok(None) => return false,
+        Err(e) => {
+            eprintln!("error: {e}");
+            process::exit(2);
+        }
+    };
+
+    env_logger::init();
+    if let Err(e) = headless::run(&args, &mut io::stdout().lock()) {
+        eprintln!("error: {e}");
+        process::exit(1);
+    }
+    true
+}
+
+/// The width and height of the window's icon, in pixels.
+#[cfg(not(target_arch = "wasm32"))]
+const ICON_SIZE: u32 = 32;
+
+/// The number of particles drawn in the window's icon.
+#[cfg(not(target_arch = "wasm32"))]
+const ICON_KINDS: usize = 6;
+
+/// Draws the window's icon: a ring of particles in the default palette.
+#[cfg(not(target_arch = "wasm32"))]
+fn window_icon() -> winit::window::Icon {
+    winit::window::Icon::from_rgba(window_icon_rgba(), ICON_SIZE, ICON_SIZE)
+        .expect("icon should be the right size")
+}
+
+/// Returns the pixels of the window's icon, in RGBA order.
+#[cfg(not(target_arch = "wasm32"))]
+fn window_icon_rgba() -> Vec<u8> {
+    use palette::Srgb;
+    use particle_life::sim::PaletteSpec;
+
+    let colors = PaletteSpec::Full.colors(ICON_KINDS);
+
+    let mut rgba = vec![0; (ICON_SIZE * ICON_SIZE * 4) as usize];
+    for y in 0..ICON_SIZE {
+        for x in 0..ICON_SIZE {
+            let pos = vec2(x as f32 + 0.5, y as f32 + 0.5) - 0.5 * ICON_SIZE as f32;
+            for (kind, &color) in colors.iter().enumerate() {
+                if pos.distance(icon_particle_center(kind)) < 5.0 {
+                    let color: Srgb<u8> = Srgb::from_linear(color);
+                    let i = ((y * ICON_SIZE + x) * 4) as usize;
+                    rgba[i..i + 4].copy_from_slice(&[color.red, color.green, color.blue, 255]);
+                }
+            }
+        }
+    }
+    rgba
+}
+
+/// Returns where the particle of kind `kind` is drawn in the window's icon,
+/// relative to its middle.
+#[cfg(not(target_arch = "wasm32"))]
+fn icon_particle_center(kind: usize) -> glam::Vec2 {
+    use std::f32::consts::TAU;
+
+    10.0 * glam::Vec2::from_angle(TAU * kind as f32 / ICON_KINDS as f32)
+}
+
+/// Loads the keybindings from `CONFIG_PATH`, falling back to the defaults if it
+/// doesn't exist.
+#[cfg(not(target_arch = "wasm32"))]
+fn load_keybindings() -> Keybindings {
+    match std::fs::read_to_string(CONFIG_PATH) {
+        Ok(config) => Keybindings::from_config(&config),
+        Err(e) => {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                log::warn!("failed to read {CONFIG_PATH}: {e}");
+            }
+            Keybindings::default()
+        }
+    }
+}
+
+/// There's no config file on the web, so just use the defaults.
+#[cfg(target_arch = "wasm32")]
+fn load_keybindings() -> Keybindings {
+    Keybindings::default()
+}
+
+/// Loads the gallery from `GALLERY_PATH` into `state`, leaving it empty if
+/// there isn't one.
+#[cfg(not(target_arch = "wasm32"))]
+fn load_gallery(state: &mut State) {
+    match std::fs::read_to_string(GALLERY_PATH) {
+        Ok(json) => match gallery_from_json(&json, &mut OsRng) {
+            Ok(gallery) => state.gallery = gallery,
+            Err(e) => log::warn!("failed to load {GALLERY_PATH}: {e}"),
+        },
+        Err(e) => {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                log::warn!("failed to read {GALLERY_PATH}: {e}");
+            }
+        }
+    }
+}
+
+/// There's nowhere to keep the gallery on the web, so it's only kept for as
+/// long as the page is open.
+#[cfg(target_arch = "wasm32")]
+fn load_gallery(_state: &mut State) {}
+
+/// Writes the gallery in `state` to `GALLERY_PATH`.
+#[cfg(not(target_arch = "wasm32"))]
+fn save_gallery(state: &State) {
+    if let Err(e) = std::fs::write(GALLERY_PATH, gallery_to_json(&state.gallery)) {
+        log::warn!("failed to write {GALLERY_PATH}: {e}");
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn save_gallery(_state: &State) {}
+
+/// Returns the gallery slot corresponding to a number key.
+fn gallery_slot_for_key(key: &str) -> Option<usize> {
+    match key.parse() {
+        Ok(slot) if key.len() == 1 => Some(slot),
+        _ => None,
+    }
+}
+
+/// Returns the camera bookmark slot corresponding to a number key.
+///
+/// This uses the physical key, since bookmarks are used with Shift held and so
+/// the key would otherwise type a symbol instead.
+fn bookmark_slot_for_key(key: PhysicalKey) -> Option<usize> {
+    let PhysicalKey::Code(code) = key else {
+        return None;
+    };
+    [
+        KeyCode::Digit0,
+        KeyCode::Digit1,
+        KeyCode::Digit2,
+        KeyCode::Digit3,
+        KeyCode::Digit4,
+        KeyCode::Digit5,
+        KeyCode::Digit6,
+        KeyCode::Digit7,
+        KeyCode::Digit8,
+        KeyCode::Digit9,
+    ]
+    .iter()
+    .position(|&digit| digit == code)
+}
+
+/// Parses a seed typed in by the user.
+fn parse_seed(input: &str) -> Option<u64> {
+    input.trim().parse().ok()
+}
+
+/// Shows the current seed (and the one being typed in, if any) in the window's
+/// title, so that it can be shared, followed by `notice` if it hasn't expired.
+fn update_title(
+    window: &Window,
+    state: &State,
+    seed_entry: &Option<String>,
+    notice: &Option<(String, Instant)>,
+) {
+    let mut title = String::from("Particle Life");
+    if let Some(seed) = state.seed {
+        title += &format!(" - seed {seed}");
+    }
+    if let Some(entry) = seed_entry {
+        title += &format!(" - enter seed: {entry}_");
+    }
+    if let Some((notice, shown)) = notice {
+        if shown.elapsed() < NOTICE_DURATION {
+            title += &format!(" - {notice}");
+        }
+    }
+    window.set_title(&title);
+}
+
+/// Returns how many steps the arrow keys should scrub through the history by.
+fn scrub_steps(modifiers: ModifiersState) -> isize {
+    if modifiers.shift_key() {
+        10
+    } else {
+        1
+    }
+}
+
 async fn run(event_loop: EventLoop<()>, window: Window) {
-    let mut state = State::new(&window).await;
+    let mut state = State::new(&window, 0).await;
+    load_gallery(&mut state);
+    update_title(&window, &state, &None, &None);
 
     // The offset from the center of the window in clip space.
     let mut mouse_pos = vec2(0.0, 0.0);
     let mut drag_cause = None;
+    let mut modifiers = ModifiersState::empty();
+    // The settings of the current preset, so that its matrix kind can be changed.
+    let mut settings = Settings::balanced();
+    // The seed being typed in, if the user is entering one.
+    let mut seed_entry: Option<String> = None;
+    // A message shown in the title for `NOTICE_DURATION`, and when it was shown.
+    let mut notice: Option<(String, Instant)> = None;
+    let keybindings = load_keybindings();
 
     let mut rng = OsRng;
 
     let event_handler = move |event, elwt: &EventLoopWindowTarget<()>| {
+        if let Event::NewEvents(StartCause::ResumeTimeReached { .. }) = event {
+            // `max_fps` is set and the next frame is due.
+            window.request_redraw();
+        }
         if let Event::WindowEvent { event, .. } = event {
             match event {
                 WindowEvent::Resized(size) => state.resize(size, window.scale_factor()),
                 WindowEvent::CloseRequested => elwt.exit(),
                 WindowEvent::KeyboardInput { event, .. } => {
-                    if event.state == ElementState::Pressed {
+                    if let (Some(entry), ElementState::Pressed) = (&mut seed_entry, event.state) {
                         match event.logical_key {
-                            Key::Character(char) => match char.as_str() {
-                                "w" => state.toggle_wrap(),
-
-                                "b" | "c" | "d" | "f" | "g" | "h" | "l" | "m" | "q" | "s" => {
-                                    let settings = match char.as_str() {
-                                        "b" => Settings::balanced(),
-                                        "c" => Settings::chaos(),
-                                        "d" => Settings::diversity(),
-                                        "f" => Settings::frictionless(),
-                                        "g" => Settings::gliders(),
-                                        "h" => Settings::homogeneity(),
-                                        "l" => Settings::large_clusters(),
-                                        "m" => Settings::medium_clusters(),
-                                        "q" => Settings::quiescence(),
-                                        "s" => Settings::small_clusters(),
-                                        _ => unreachable!(),
+                            Key::Character(char) if char.chars().all(|c| c.is_ascii_digit()) => {
+                                entry.push_str(&char)
+                            }
+                            Key::Named(NamedKey::Backspace) => {
+                                entry.pop();
+                            }
+                            Key::Named(NamedKey::Enter) => {
+                                match parse_seed(entry) {
+                                    Some(seed) => {
+                                        state.replace_settings_seeded(settings.clone(), 0, seed)
+                                    }
+                                    None => log::warn!("invalid seed: {entry:?}"),
+                                }
+                                seed_entry = None;
+                            }
+                            Key::Named(NamedKey::Escape) => seed_entry = None,
+                            _ => {}
+                        }
+                    } else if let (ElementState::Pressed, true, Some(slot)) = (
+                        event.state,
+                        modifiers.shift_key(),
+                        bookmark_slot_for_key(event.physical_key),
+                    ) {
+                        if modifiers.control_key() {
+                            state.save_bookmark(slot);
+                            log::info!("Saved camera to bookmark {slot}");
+                        } else if state.recall_bookmark(slot) {
+                            log::info!("Recalled camera from bookmark {slot}");
+                        }
+                    } else if event.state == ElementState::Pressed {
+                        let key_name = match &event.logical_key {
+                            Key::Character(char) => Some(char.to_string()),
+                            Key::Named(key) => Some(format!("{key:?}")),
+                            _ => None,
+                        };
+                        let action = key_name.and_then(|key| keybindings.action(&key));
+
+                        match (action, event.logical_key) {
+                            (Some(action), _) => match action {
+                                Action::ToggleWrap => state.toggle_wrap(),
+                                Action::ToggleWrapTiles => {
+                                    state.show_wrap_tiles = !state.show_wrap_tiles
+                                }
+                                Action::Reverse => state.sim.reverse(),
+                                Action::ToggleRecording => {
+                                    state.set_recording(!state.recording);
+                                    if state.recording {
+                                        log::info!("Started recording");
+                                    } else {
+                                        log::info!("Stopped recording");
+                                    }
+                                }
+                                Action::Resume => state.resume(),
+                                Action::ToggleAutoCycle => {
+                                    if state.auto_cycle.is_some() {
+                                        state.set_auto_cycle(None);
+                                        log::info!("Stopped cycling presets");
+                                    } else {
+                                        state.auto_cycle_presets = true;
+                                        state.set_auto_cycle(Some(AUTO_CYCLE_INTERVAL));
+                                        log::info!("Cycling presets every {AUTO_CYCLE_INTERVAL:?}");
+                                    }
+                                }
+                                Action::EnterSeed => seed_entry = Some(String::new()),
+                                Action::ToggleBonds => state.show_bonds = !state.show_bonds,
+                                Action::ToggleVelocityVectors => {
+                                    state.show_velocity_vectors = !state.show_velocity_vectors
+                                }
+                                Action::ToggleTimeDilation => {
+                                    state.zoom_time_dilation = !state.zoom_time_dilation;
+                                    log::info!(
+                                        "Time dilation {}",
+                                        if state.zoom_time_dilation {
+                                            "on"
+                                        } else {
+                                            "off"
+                                        }
+                                    );
+                                }
+                                Action::ToggleRealTime => {
+                                    state.real_time = !state.real_time;
+                                    let message = if state.real_time {
+                                        "real time on"
+                                    } else {
+                                        "real time off"
                                     };
-
-                                    state.replace_settings(settings, &mut rng);
+                                    log::info!("{message}");
+                                    notice = Some((message.to_owned(), Instant::now()));
+                                }
+                                Action::ToggleAdaptiveTrails => {
+                                    state.adaptive_trails = match state.adaptive_trails {
+                                        Some(_) => None,
+                                        None => Some(AdaptiveTrails::default()),
+                                    }
+                                }
+                                Action::ToggleTrailsOnly => state.trails_only = !state.trails_only,
+                                Action::ToggleFocusVignette => {
+                                    state.set_focus_vignette(match state.focus_vignette {
+                                        Some(_) => None,
+                                        None => Some(DEFAULT_FOCUS_VIGNETTE),
+                                    })
+                                }
+                                Action::ShowSeed => match state.seed {
+                                    Some(seed) => log::info!("Seed: {seed}"),
+                                    None => log::info!("This universe wasn't created from a seed"),
+                                },
+                                Action::ShowRustSource => {
+                                    log::info!("{}", state.sim.to_rust_source())
+                                }
+                                Action::DecreaseFriction | Action::IncreaseFriction => {
+                                    let delta = if action == Action::DecreaseFriction {
+                                        -FRICTION_STEP
+                                    } else {
+                                        FRICTION_STEP
+                                    };
+                                    let friction =
+                                        (state.sim.friction + delta).clamp(0.0, MAX_FRICTION);
+                                    state.sim.set_friction(friction);
+                                    log::info!("Friction: {friction:.3}");
+                                    // Show it on screen too, since there's no log in the window.
+                                    notice =
+                                        Some((format!("friction {friction:.3}"), Instant::now()));
+                                }
+                                Action::CycleMatrixKind => {
+                                    settings.matrix_kind = settings.matrix_kind.next();
+                                    log::info!("Switched to {:?} matrix", settings.matrix_kind);
+                                    state.replace_settings_seeded(settings.clone(), 0, rng.gen());
+                                }
+                                Action::CycleDrawOrder => {
+                                    state.set_draw_order(state.draw_order.next());
+                                    log::info!("Drawing particles {:?}", state.draw_order);
+                                }
+                                Action::CycleSymmetry => {
+                                    state.set_symmetry(state.symmetry.next());
+                                    log::info!("Switched to {:?} symmetry", state.symmetry);
+                                }
+                                Action::Regenerate => state.regenerate_particles(&mut rng),
+                                Action::ToggleFullscreen => {
+                                    if window.fullscreen().is_some() {
+                                        window.set_fullscreen(None);
+                                    } else {
+                                        window.set_fullscreen(Some(Fullscreen::Borderless(None)))
+                                    }
+                                }
+                                Action::ToggleProfiling => match state.profiler.take() {
+                                    Some(profiler) => profiler.log_summary(),
+                                    None => {
+                                        log::info!("Started profiling");
+                                        state.profiler = Some(StepProfiler::new(
+                                            PROFILE_WINDOW,
+                                            settings_label(&settings),
+                                        ));
+                                    }
+                                },
+                                Action::Preset(name) => {
+                                    settings = Settings::by_name(name)
+                                        .expect("preset names should be valid");
+                                    state.replace_settings_seeded(settings.clone(), 0, rng.gen());
                                 }
-
-                                _ => {}
                             },
 
-                            Key::Named(NamedKey::Enter) => state.regenerate_particles(&mut rng),
-                            Key::Named(NamedKey::Space) => state.step_rate = 30,
-
-                            Key::Named(NamedKey::F11) => {
-                                if window.fullscreen().is_some() {
-                                    window.set_fullscreen(None);
-                                } else {
-                                    window.set_fullscreen(Some(Fullscreen::Borderless(None)))
+                            (None, Key::Character(key)) => {
+                                if let Some(slot) = gallery_slot_for_key(&key) {
+                                    if modifiers.alt_key() {
+                                        // Reuse the number keys to pick kinds to show.
+                                        if slot < state.sim.colors.len() {
+                                            state.toggle_solo_kind(slot);
+                                        }
+                                    } else if modifiers.control_key() {
+                                        let name = match state.seed {
+                                            Some(seed) => {
+                                                format!("{} {seed}", settings_label(&settings))
+                                            }
+                                            None => format!("slot {slot}"),
+                                        };
+                                        log::info!("Saved universe to slot {slot} as {name:?}");
+                                        notice = Some((format!("saved {name}"), Instant::now()));
+                                        state.save_to_gallery(slot, name);
+                                        save_gallery(&state);
+                                    } else if let Some(name) = state.recall_from_gallery(slot) {
+                                        log::info!("Recalled {name:?} from slot {slot}");
+                                        notice =
+                                            Some((format!("slot {slot}: {name}"), Instant::now()));
+                                    }
                                 }
                             }
 
+                            (None, Key::Named(NamedKey::Space)) => state.step_rate = 30,
+                            (None, Key::Named(NamedKey::ArrowLeft)) => {
+                                state.scrub(-scrub_steps(modifiers))
+                            }
+                            (None, Key::Named(NamedKey::ArrowRight)) => {
+                                state.scrub(scrub_steps(modifiers))
+                            }
+
                             _ => {}
                         }
                     } else if event.logical_key == Key::Named(NamedKey::Space) {
                         // Space was lifted, set the step rate back to normal.
-                        state.step_rate = 300;
+                        state.step_rate = REFERENCE_STEP_RATE;
                     }
+
+                    update_title(&window, &state, &seed_entry, &notice);
                 }
+                WindowEvent::ModifiersChanged(new_modifiers) => modifiers = new_modifiers.state(),
                 WindowEvent::MouseWheel { delta, .. } => {
                     let scrolled = match delta {
                         MouseScrollDelta::LineDelta(_, y) => y,
                         MouseScrollDelta::PixelDelta(pos) => pos.y as f32 / 60.0,
                     };
 
-                    let old_pos = mouse_pos / state.zoom - state.camera;
+                    let old_pos = state.screen_to_sim(mouse_pos);
 
                     state.zoom *= 1.1f32.powf(scrolled);
-                    state.zoom = state.zoom.clamp(1.0, 10.0);
+                    state.zoom = state.zoom.clamp(MIN_ZOOM, MAX_ZOOM);
 
-                    let new_pos = mouse_pos / state.zoom - state.camera;
+                    let new_pos = state.screen_to_sim(mouse_pos);
 
                     let delta = new_pos - old_pos;
 
@@ -141,7 +592,7 @@ async fn run(event_loop: EventLoop<()>, window: Window) {
                     mouse_pos = vec2(offset.x, -offset.y) / center;
 
                     if drag_cause.is_some() {
-                        let delta = (mouse_pos - old_pos) / state.zoom;
+                        let delta = state.screen_to_sim(mouse_pos) - state.screen_to_sim(old_pos);
 
                         // Drag the camera by however much the mouse position has changed.
                         state.camera += delta;
@@ -149,6 +600,38 @@ async fn run(event_loop: EventLoop<()>, window: Window) {
                         state.set_camera();
                     }
                 }
+                WindowEvent::MouseInput {
+                    button: MouseButton::Middle,
+                    state: ElementState::Pressed,
+                    ..
+                } => {
+                    // Toggle whether the particle under the cursor is pinned.
+                    let pos = state.screen_to_sim(mouse_pos);
+                    if let Some(index) = state.sim.nearest_particle(pos) {
+                        if state.sim.particles[index].pinned {
+                            state.sim.unpin(index);
+                        } else {
+                            state.sim.pin(index);
+                        }
+                    }
+                }
+                WindowEvent::MouseInput {
+                    button: MouseButton::Left,
+                    state: ElementState::Pressed,
+                    ..
+                } if modifiers.shift_key() => {
+                    // Push the particles away from the cursor, or pull them in with Ctrl held too.
+                    let pos = state.screen_to_sim(mouse_pos);
+                    let size = window.inner_size().to_logical(window.scale_factor());
+                    let strength = if modifiers.control_key() {
+                        -SHOCKWAVE_STRENGTH
+                    } else {
+                        SHOCKWAVE_STRENGTH
+                    };
+                    state
+                        .sim
+                        .shockwave(pos, SHOCKWAVE_RADIUS, strength, size.width, size.height);
+                }
                 WindowEvent::MouseInput { button, state, .. } => {
                     if state == ElementState::Pressed && drag_cause.is_none() {
                         drag_cause = Some(button);
@@ -157,9 +640,25 @@ async fn run(event_loop: EventLoop<()>, window: Window) {
                     }
                 }
                 WindowEvent::RedrawRequested => {
+                    if notice
+                        .as_ref()
+                        .is_some_and(|(_, shown)| shown.elapsed() >= NOTICE_DURATION)
+                    {
+                        notice = None;
+                        update_title(&window, &state, &seed_entry, &notice);
+                    }
+
                     let size = window.inner_size().to_logical(window.scale_factor());
                     state.render(size.width, size.height);
-                    window.request_redraw();
+                    match state.next_frame_due() {
+                        // Sleep until the next frame's due, rather than spinning through redraws
+                        // that `render` would skip.
+                        Some(time) => elwt.set_control_flow(ControlFlow::WaitUntil(time)),
+                        None => {
+                            elwt.set_control_flow(ControlFlow::Wait);
+                            window.request_redraw();
+                        }
+                    }
                 }
                 _ => {}
             }
@@ -172,3 +671,42 @@ async fn run(event_loop: EventLoop<()>, window: Window) {
     #[cfg(target_arch = "wasm32")]
     event_loop.spawn(event_handler);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_seeds() {
+        assert_eq!(parse_seed("12345"), Some(12345));
+        assert_eq!(parse_seed(" 42\n"), Some(42));
+        assert_eq!(parse_seed(&u64::MAX.to_string()), Some(u64::MAX));
+        assert_eq!(parse_seed(""), None);
+        assert_eq!(parse_seed("-1"), None);
+        assert_eq!(parse_seed("12a"), None);
+        assert_eq!(parse_seed("18446744073709551616"), None);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn draws_a_ring_of_particles_for_the_icon() {
+        let rgba = window_icon_rgba();
+        assert_eq!(rgba.len(), (ICON_SIZE * ICON_SIZE * 4) as usize);
+        let pixel = |pos: glam::Vec2| {
+            let pos = (pos + 0.5 * ICON_SIZE as f32).as_uvec2();
+            let i = ((pos.y * ICON_SIZE + pos.x) * 4) as usize;
+            [rgba[i], rgba[i + 1], rgba[i + 2], rgba[i + 3]]
+        };
+
+        // The middle of the ring is transparent.
+        assert_eq!(pixel(glam::Vec2::ZERO)[3], 0);
+        // Each particle is opaque, and a different color.
+        let colors: Vec<_> = (0..ICON_KINDS)
+            .map(|kind| pixel(icon_particle_center(kind)))
+            .collect();
+        for (kind, color) in colors.iter().enumerate() {
+            assert_eq!(color[3], 255, "particle {kind} isn't drawn");
+            assert!(!colors[..kind].contains(color));
+        }
+    }
+}
@@ -0,0 +1,172 @@
+//! Timings of simulation steps grouped by the settings they were run with, for
+//! tracking down which settings make the simulation slow.
+
+use std::cmp::Reverse;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::settings::Settings;
+
+/// The label `StepProfiler` groups timings from `settings` under: the name of
+/// the preset they match, or `custom` if they don't match any.
+pub fn settings_label(settings: &Settings) -> &'static str {
+    settings.matching_preset().unwrap_or("custom")
+}
+
+/// A summary of the step times recorded for one set of settings.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProfileSummary {
+    pub label: String,
+    /// The number of samples the summary is based on.
+    pub samples: usize,
+    pub mean: Duration,
+    pub median: Duration,
+    pub max: Duration,
+}
+
+/// Records how long each step of the simulation takes, grouped by the
+/// settings which were active at the time.
+pub struct StepProfiler {
+    /// The maximum number of samples to keep for each set of settings.
+    window: usize,
+    /// The label of the settings currently being run.
+    current: String,
+    /// The most recent step times for each label, in the order the labels were
+    /// first recorded.
+    samples: Vec<(String, VecDeque<Duration>)>,
+}
+
+impl StepProfiler {
+    /// Creates a profiler which keeps the most recent `window` samples for each
+    /// set of settings, starting off with the settings labelled `label`.
+    pub fn new(window: usize, label: impl Into<String>) -> Self {
+        Self {
+            window,
+            current: label.into(),
+            samples: Vec::new(),
+        }
+    }
+
+    /// Attributes any timings recorded from now on to the settings labelled
+    /// `label`.
+    pub fn set_settings(&mut self, label: impl Into<String>) {
+        self.current = label.into();
+    }
+
+    /// Records that `steps` steps took `time` in total, as a sample of the
+    /// average time per step.
+    ///
+    /// Does nothing if `steps` is 0.
+    pub fn record(&mut self, steps: u32, time: Duration) {
+        if steps == 0 || self.window == 0 {
+            return;
+        }
+
+        let index = match self
+            .samples
+            .iter()
+            .position(|(label, _)| *label == self.current)
+        {
+            Some(index) => index,
+            None => {
+                self.samples
+                    .push((self.current.clone(), VecDeque::with_capacity(self.window)));
+                self.samples.len() - 1
+            }
+        };
+
+        let samples = &mut self.samples[index].1;
+        if samples.len() == self.window {
+            samples.pop_front();
+        }
+        samples.push_back(time / steps);
+    }
+
+    /// Summarises the step times recorded for each set of settings, slowest
+    /// first.
+    pub fn summary(&self) -> Vec<ProfileSummary> {
+        let mut summaries: Vec<_> = self
+            .samples
+            .iter()
+            .map(|(label, samples)| {
+                let mut sorted: Vec<Duration> = samples.iter().copied().collect();
+                sorted.sort();
+                ProfileSummary {
+                    label: label.clone(),
+                    samples: sorted.len(),
+                    mean: sorted.iter().sum::<Duration>() / sorted.len() as u32,
+                    median: sorted[sorted.len() / 2],
+                    max: sorted[sorted.len() - 1],
+                }
+            })
+            .collect();
+        summaries.sort_by_key(|summary| Reverse(summary.mean));
+        summaries
+    }
+
+    /// Logs `summary`, so it can be copied into a bug report.
+    pub fn log_summary(&self) {
+        for summary in self.summary() {
+            log::info!(
+                "{}: mean {:?}, median {:?}, max {:?} per step ({} samples)",
+                summary.label,
+                summary.mean,
+                summary.median,
+                summary.max,
+                summary.samples,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn groups_timings_by_settings() {
+        let mut profiler = StepProfiler::new(3, settings_label(&Settings::balanced()));
+        profiler.record(2, ms(4));
+        profiler.record(1, ms(3));
+        // Nothing to average over.
+        profiler.record(0, ms(100));
+
+        profiler.set_settings(settings_label(&Settings {
+            friction: 0.5,
+            ..Settings::chaos()
+        }));
+        profiler.record(1, ms(10));
+        profiler.record(1, ms(20));
+        profiler.record(1, ms(30));
+        // Only the last 3 samples are kept.
+        profiler.record(1, ms(40));
+
+        // Switching back carries on where it left off.
+        profiler.set_settings(settings_label(&Settings::balanced()));
+        profiler.record(4, ms(4));
+
+        assert_eq!(
+            profiler.summary(),
+            [
+                ProfileSummary {
+                    label: "custom".to_owned(),
+                    samples: 3,
+                    mean: ms(30),
+                    median: ms(30),
+                    max: ms(40),
+                },
+                ProfileSummary {
+                    label: "balanced".to_owned(),
+                    samples: 3,
+                    mean: ms(2),
+                    median: ms(2),
+                    max: ms(3),
+                },
+            ]
+        );
+    }
+}
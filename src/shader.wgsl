@@ -1,4 +1,6 @@
 const num_circle_points: u32 = 32u;
+// Keep this in sync with `RADIUS` in `sim.rs`.
+const radius: f32 = 5.0;
 
 const pi: f32 = 3.14159265358979323846264338327950288;
 
@@ -8,10 +10,12 @@ struct Settings {
     zoom: f32,
     camera: vec2<f32>,
 
+    // The strength of `State::focus_vignette`, or 0 if it's off.
+    vignette: f32,
+
     // Explicitly declare the padding here so that the dimensions and circle points are next to each other.
     // Also, I'm not sure this padding would even be there outside webgl, so we need to add it manually to make the layout consistent.
-    padding1: u32,
-    padding2: u32,
+    padding: u32,
 
     /// The horizontal/vertical radius of a particle in clip space.
     /// (A perfect circle in pixel space isn't always a perfect circle in clip space, hence why can't just pass `radius`.)
@@ -26,19 +30,47 @@ struct Settings {
 /// Settings which differ between render passes.
 struct PassSettings {
     opacity: f32,
+    // 1.0 if this pass draws part of the trail rather than the current frame, 0.0 otherwise.
+    trail: f32,
+    // An offset to draw the particles at, for drawing copies of the simulation next to itself when wrapping.
+    tile_offset: vec2<f32>,
+}
 
-    // Pad this out to 16 bytes for WebGL.
-    padding1: f32,
-    padding2: f32,
-    padding3: f32,
+/// The transform for one copy of the particles drawn by `Symmetry`, as the columns of a matrix.
+/// (These aren't a `mat2x2` because WebGL pads out the columns of matrices in uniforms.)
+struct SymmetrySettings {
+    transform_x: vec2<f32>,
+    transform_y: vec2<f32>,
+}
+
+/// Settings for `BackgroundMode::DensityGlow`.
+struct BackgroundSettings {
+    // The colors of empty and dense areas respectively.
+    low: vec4<f32>,
+    high: vec4<f32>,
 }
 
 @group(0) @binding(0) var<uniform> settings: Settings;
 @group(1) @binding(0) var<uniform> pass_settings: PassSettings;
+// This is only used by the particle pipelines.
+@group(2) @binding(0) var<uniform> symmetry: SymmetrySettings;
+
+// These are only used by the background pipeline, which has a different layout for group 1.
+@group(1) @binding(1) var density: texture_2d<f32>;
+@group(1) @binding(2) var density_sampler: sampler;
+@group(1) @binding(3) var<uniform> background: BackgroundSettings;
+
+// These are only used by the blit pipeline, which has a different layout for group 0.
+@group(0) @binding(1) var blit_source: texture_2d<f32>;
+@group(0) @binding(2) var blit_sampler: sampler;
 
 struct Particle {
     @location(0) pos: vec2<f32>,
     @location(1) color: vec3<f32>,
+    // The particle's radius as a multiple of the default radius.
+    @location(2) scale: f32,
+    // How visible the particle is in its trail, as a multiple of the trail's opacity.
+    @location(3) trail_alpha: f32,
 }
 
 struct VertexOutput {
@@ -46,13 +78,31 @@ struct VertexOutput {
     @location(0) color: vec3<f32>,
 }
 
-@vertex
-fn vs_main(particle: Particle, @builtin(vertex_index) idx: u32) -> VertexOutput {
-    // Half the angle between each line from the centre.
-    // This isn't a proper constant because WGSL won't let me do division there.
-    let half_circle_angle: f32 = pi / f32(num_circle_points);
+struct ParticleOutput {
+    @builtin(position) pos: vec4<f32>,
+    @location(0) color: vec3<f32>,
+    @location(1) alpha: f32,
+}
+
+/// Returns the opacity to draw a particle with in the current pass.
+fn particle_alpha(particle: Particle) -> f32 {
+    return pass_settings.opacity * mix(1.0, particle.trail_alpha, pass_settings.trail);
+}
 
-    var pos = settings.camera + particle.pos;
+/// Moves a position on the screen to where the current copy of the particles is drawn.
+fn apply_symmetry(pos: vec2<f32>) -> vec2<f32> {
+    return mat2x2<f32>(symmetry.transform_x, symmetry.transform_y) * pos;
+}
+
+/// Returns how much `State::focus_vignette` fades out a particle drawn at `screen_pos`.
+/// Keep this in sync with `vignette_opacity` in `lib.rs`.
+fn vignette(screen_pos: vec2<f32>) -> f32 {
+    return clamp(1.0 - settings.vignette * dot(screen_pos, screen_pos), 0.0, 1.0);
+}
+
+/// Applies the camera to a particle's position, wrapping it around if necessary.
+fn camera_pos(particle_pos: vec2<f32>) -> vec2<f32> {
+    var pos = settings.camera + particle_pos;
 
     if (settings.wrap != 0u) {
         if (pos.x > 1.0) {
@@ -68,6 +118,20 @@ fn vs_main(particle: Particle, @builtin(vertex_index) idx: u32) -> VertexOutput
         }
     }
 
+    return pos;
+}
+
+@vertex
+fn vs_main(particle: Particle, @builtin(vertex_index) idx: u32) -> ParticleOutput {
+    // Half the angle between each line from the centre.
+    // This isn't a proper constant because WGSL won't let me do division there.
+    let half_circle_angle: f32 = pi / f32(num_circle_points);
+
+    let pos = camera_pos(particle.pos);
+
+    let horiz_rad = settings.horiz_rad * particle.scale;
+    let vert_rad = settings.vert_rad * particle.scale;
+
     var circle_point: vec2<f32>;
 
     if (idx % 3u == 0u) {
@@ -83,14 +147,14 @@ fn vs_main(particle: Particle, @builtin(vertex_index) idx: u32) -> VertexOutput
             }
         }
 
-        circle_point = settings.circle_points[point_idx].xy;
+        circle_point = settings.circle_points[point_idx].xy * particle.scale;
     }
 
     var vertex = pos + circle_point;
 
     if (settings.wrap != 0u) {
-        if (pos.x + settings.horiz_rad > 1.0) {
-            let middle = pos.x + cos(half_circle_angle * f32(2u * (idx / 3u) + 1u)) * settings.horiz_rad;
+        if (pos.x + horiz_rad > 1.0) {
+            let middle = pos.x + cos(half_circle_angle * f32(2u * (idx / 3u) + 1u)) * horiz_rad;
             if (middle > 1.0) {
                 if (idx % 3u == 0u) {
                     vertex.x = -1.0;
@@ -104,8 +168,8 @@ fn vs_main(particle: Particle, @builtin(vertex_index) idx: u32) -> VertexOutput
                     vertex.x = min(1.0, vertex.x);
                 }
             }
-        } else if (pos.x - settings.horiz_rad < -1.0) {
-            let middle = pos.x + cos(half_circle_angle * f32(2u * (idx / 3u) + 1u)) * settings.horiz_rad;
+        } else if (pos.x - horiz_rad < -1.0) {
+            let middle = pos.x + cos(half_circle_angle * f32(2u * (idx / 3u) + 1u)) * horiz_rad;
             if (middle < -1.0) {
                 if (idx % 3u == 0u) {
                     vertex.x = 1.0;
@@ -121,8 +185,8 @@ fn vs_main(particle: Particle, @builtin(vertex_index) idx: u32) -> VertexOutput
             }
         }
 
-        if (pos.y + settings.vert_rad > 1.0) {
-            let middle = pos.y + sin(half_circle_angle * f32(2u * (idx / 3u) + 1u)) * settings.vert_rad;
+        if (pos.y + vert_rad > 1.0) {
+            let middle = pos.y + sin(half_circle_angle * f32(2u * (idx / 3u) + 1u)) * vert_rad;
             if (middle > 1.0) {
                 if (idx % 3u == 0u) {
                     vertex.y = -1.0;
@@ -136,8 +200,8 @@ fn vs_main(particle: Particle, @builtin(vertex_index) idx: u32) -> VertexOutput
                     vertex.y = min(1.0, vertex.y);
                 }
             }
-        } else if (pos.y - settings.vert_rad < -1.0) {
-            let middle = pos.y + sin(half_circle_angle * f32(2u * (idx / 3u) + 1u)) * settings.vert_rad;
+        } else if (pos.y - vert_rad < -1.0) {
+            let middle = pos.y + sin(half_circle_angle * f32(2u * (idx / 3u) + 1u)) * vert_rad;
             if (middle < -1.0) {
                 if (idx % 3u == 0u) {
                     vertex.y = 1.0;
@@ -154,13 +218,121 @@ fn vs_main(particle: Particle, @builtin(vertex_index) idx: u32) -> VertexOutput
         }
     }
 
-    var out: VertexOutput;
-    out.pos = vec4<f32>(vertex * settings.zoom, 0.0, 1.0);
+    // Fade the whole particle by where its centre is, rather than each vertex separately.
+    let centre = apply_symmetry(pos + pass_settings.tile_offset) * settings.zoom;
+
+    var out: ParticleOutput;
+    out.pos = vec4<f32>(apply_symmetry(vertex + pass_settings.tile_offset) * settings.zoom, 0.0, 1.0);
     out.color = particle.color;
+    out.alpha = particle_alpha(particle) * vignette(centre);
+    return out;
+}
+
+@fragment
+fn fs_main(@location(0) color: vec3<f32>, @location(1) alpha: f32) -> @location(0) vec4<f32> {
+    return vec4<f32>(color, alpha);
+}
+
+struct PointOutput {
+    @builtin(position) pos: vec4<f32>,
+    @location(0) color: vec3<f32>,
+    /// How much of the pixel the particle would cover if it were drawn as a circle.
+    @location(1) coverage: f32,
+    @location(2) alpha: f32,
+}
+
+@vertex
+fn vs_point(particle: Particle) -> PointOutput {
+    let pixel_radius = radius * particle.scale * settings.zoom;
+
+    var out: PointOutput;
+    out.pos = vec4<f32>(apply_symmetry(camera_pos(particle.pos) + pass_settings.tile_offset) * settings.zoom, 0.0, 1.0);
+    out.color = particle.color;
+    out.coverage = min(1.0, pi * pixel_radius * pixel_radius);
+    out.alpha = particle_alpha(particle) * vignette(out.pos.xy);
+    return out;
+}
+
+@fragment
+fn fs_point(
+    @location(0) color: vec3<f32>,
+    @location(1) coverage: f32,
+    @location(2) alpha: f32,
+) -> @location(0) vec4<f32> {
+    return vec4<f32>(color, alpha * coverage);
+}
+
+struct BackgroundOutput {
+    @builtin(position) pos: vec4<f32>,
+    /// The position in the simulation which this point on the screen shows.
+    @location(0) sim_pos: vec2<f32>,
+}
+
+@vertex
+fn vs_background(@builtin(vertex_index) idx: u32) -> BackgroundOutput {
+    // Make a triangle big enough to cover the whole screen.
+    let screen_pos = vec2<f32>(f32(idx / 2u) * 4.0 - 1.0, f32(idx % 2u) * 4.0 - 1.0);
+
+    var out: BackgroundOutput;
+    out.pos = vec4<f32>(screen_pos, 0.0, 1.0);
+    out.sim_pos = screen_pos / settings.zoom - settings.camera;
+    return out;
+}
+
+@fragment
+fn fs_background(@location(0) sim_pos: vec2<f32>) -> @location(0) vec4<f32> {
+    // Textures start from the top, so flip the y coordinate.
+    var uv = 0.5 * vec2<f32>(sim_pos.x + 1.0, 1.0 - sim_pos.y);
+
+    if (settings.wrap == 0u) {
+        // The sampler repeats, so stop it from blending in the other side of the texture.
+        let half_texel = 0.5 / vec2<f32>(textureDimensions(density));
+        uv = clamp(uv, half_texel, 1.0 - half_texel);
+    }
+
+    let amount = textureSample(density, density_sampler, uv).r;
+    return vec4<f32>(mix(background.low.rgb, background.high.rgb, amount), 1.0);
+}
+
+struct BlitOutput {
+    @builtin(position) pos: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+@vertex
+fn vs_blit(@builtin(vertex_index) idx: u32) -> BlitOutput {
+    // Make a triangle big enough to cover the whole screen.
+    let screen_pos = vec2<f32>(f32(idx / 2u) * 4.0 - 1.0, f32(idx % 2u) * 4.0 - 1.0);
+
+    var out: BlitOutput;
+    out.pos = vec4<f32>(screen_pos, 0.0, 1.0);
+    // Textures start from the top, so flip the y coordinate.
+    out.uv = 0.5 * vec2<f32>(screen_pos.x + 1.0, 1.0 - screen_pos.y);
+    return out;
+}
+
+@fragment
+fn fs_blit(@location(0) uv: vec2<f32>) -> @location(0) vec4<f32> {
+    return textureSample(blit_source, blit_sampler, uv);
+}
+
+struct BondVertex {
+    // The position of the particle the line starts from.
+    @location(0) anchor: vec2<f32>,
+    // The offset of this end of the line from `anchor`.
+    @location(1) offset: vec2<f32>,
+    @location(2) color: vec3<f32>,
+}
+
+@vertex
+fn vs_bond(vertex: BondVertex) -> VertexOutput {
+    var out: VertexOutput;
+    out.pos = vec4<f32>((camera_pos(vertex.anchor) + vertex.offset) * settings.zoom, 0.0, 1.0);
+    out.color = vertex.color;
     return out;
 }
 
 @fragment
-fn fs_main(@location(0) color: vec3<f32>) -> @location(0) vec4<f32> {
-    return vec4<f32>(color, pass_settings.opacity);
+fn fs_bond(@location(0) color: vec3<f32>) -> @location(0) vec4<f32> {
+    return vec4<f32>(color, 0.5);
 }
@@ -0,0 +1,198 @@
+//! Running a `Sim` on its own thread, for when stepping it inline with
+//! rendering is too slow.
+
+use std::sync::mpsc;
+use std::sync::mpsc::Sender;
+use std::sync::mpsc::TryRecvError;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use rand::rngs::OsRng;
+use web_time::Instant;
+
+use crate::settings::Settings;
+use crate::sim::Sim;
+use crate::sim::RADIUS;
+use crate::GpuParticle;
+use crate::MAX_PARTICLES;
+
+/// The messages sent from a `SimThread` to its worker.
+enum Command {
+    ReplaceSettings(Settings),
+    Regenerate,
+    ToggleWrap,
+    Resize(f32, f32),
+}
+
+/// A handle to a thread which steps a `Sim` at a fixed rate, passing the
+/// resulting particles to a callback after each step.
+///
+/// The thread stops when this is dropped.
+pub struct SimThread {
+    commands: Option<Sender<Command>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl SimThread {
+    /// Spawns a thread which steps `sim` `step_rate` times a second, for a
+    /// window `width`x`height` logical pixels in size. A `step_rate` of 0 is
+    /// treated as 1.
+    ///
+    /// `on_frame` is called with the particles after every step.
+    pub fn spawn<F>(mut sim: Sim, width: f32, height: f32, step_rate: u32, mut on_frame: F) -> Self
+    where
+        F: FnMut(&[GpuParticle]) + Send + 'static,
+    {
+        let (commands, receiver) = mpsc::channel();
+
+        let handle = thread::spawn(move || {
+            let mut rng = OsRng;
+            let mut size = (width, height);
+            let mut kind_radii = vec![RADIUS; sim.colors.len()];
+            let mut buffer = Box::new([GpuParticle::default(); MAX_PARTICLES]);
+
+            let step_period = Duration::from_secs(1) / step_rate.max(1);
+            let mut next_step = Instant::now();
+
+            loop {
+                loop {
+                    match receiver.try_recv() {
+                        Ok(Command::ReplaceSettings(settings)) => {
+                            match Sim::try_new(settings, &mut rng) {
+                                Ok(mut new_sim) => {
+                                    new_sim.carry_over_options_from(&sim);
+                                    sim = new_sim;
+                                    // Place the particles according to the carried-over placement.
+                                    sim.regenerate_particles(&mut rng);
+                                    kind_radii = vec![RADIUS; sim.colors.len()];
+                                }
+                                Err(e) => log::error!("failed to replace settings: {e}"),
+                            }
+                        }
+                        Ok(Command::Regenerate) => sim.regenerate_particles(&mut rng),
+                        Ok(Command::ToggleWrap) => sim.wrap = !sim.wrap,
+                        Ok(Command::Resize(width, height)) => size = (width, height),
+                        Err(TryRecvError::Empty) => break,
+                        // The `SimThread` has been dropped.
+                        Err(TryRecvError::Disconnected) => return,
+                    }
+                }
+
+                sim.step(size.0, size.1);
+                sim.apply_life_rules(size.0, size.1, &mut rng);
+                sim.apply_spawn_schedule(&mut rng);
+                sim.export_particles(&mut buffer, &kind_radii, None, None, None, None);
+                on_frame(&buffer[..sim.particles.len()]);
+
+                next_step += step_period;
+                match next_step.checked_duration_since(Instant::now()) {
+                    Some(delay) => thread::sleep(delay),
+                    // We're running behind; don't try to catch up.
+                    None => next_step = Instant::now(),
+                }
+            }
+        });
+
+        Self {
+            commands: Some(commands),
+            handle: Some(handle),
+        }
+    }
+
+    fn send(&self, command: Command) {
+        if let Some(commands) = &self.commands {
+            // If this fails, the thread has panicked, which will get reported when it's joined.
+            let _ = commands.send(command);
+        }
+    }
+
+    /// Replaces the simulation with a new one created from `settings`.
+    pub fn replace_settings(&self, settings: Settings) {
+        self.send(Command::ReplaceSettings(settings));
+    }
+
+    /// Regenerates all the particles in the simulation.
+    pub fn regenerate_particles(&self) {
+        self.send(Command::Regenerate);
+    }
+
+    /// Toggles whether particles wrap around the edges of the simulation.
+    pub fn toggle_wrap(&self) {
+        self.send(Command::ToggleWrap);
+    }
+
+    /// Sets the size of the window the simulation is being shown in, in
+    /// logical pixels.
+    pub fn resize(&self, width: f32, height: f32) {
+        self.send(Command::Resize(width, height));
+    }
+}
+
+impl Drop for SimThread {
+    fn drop(&mut self) {
+        // Dropping the sender tells the thread to stop.
+        self.commands = None;
+        if let Some(handle) = self.handle.take() {
+            if handle.join().is_err() && !thread::panicking() {
+                panic!("simulation thread panicked");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::Receiver;
+
+    use glam::Vec2;
+
+    use super::*;
+    use crate::sim::UniverseBuilder;
+
+    /// Spawns a `SimThread` stepping a single particle sitting still in the
+    /// middle of the simulation, which sends the particles' positions back
+    /// after each step.
+    fn spawn(step_rate: u32) -> (SimThread, Receiver<Vec<Vec2>>) {
+        let sim = UniverseBuilder::new(1)
+            .particle(0, Vec2::ZERO)
+            .build()
+            .unwrap();
+        let (sender, frames) = mpsc::channel();
+        let thread = SimThread::spawn(sim, 800.0, 600.0, step_rate, move |particles| {
+            let _ = sender.send(particles.iter().map(GpuParticle::pos).collect());
+        });
+        (thread, frames)
+    }
+
+    #[test]
+    fn produces_frames_and_regenerates() {
+        let (thread, frames) = spawn(1000);
+        let timeout = Duration::from_secs(5);
+        for _ in 0..10 {
+            assert_eq!(frames.recv_timeout(timeout).unwrap(), [Vec2::ZERO]);
+        }
+
+        // There's nothing to move the particle, so it only leaves the middle
+        // once it's been placed somewhere random.
+        thread.regenerate_particles();
+        let start = Instant::now();
+        loop {
+            let frame = frames.recv_timeout(timeout).unwrap();
+            assert_eq!(frame.len(), 1);
+            if frame[0] != Vec2::ZERO {
+                break;
+            }
+            assert!(start.elapsed() < timeout, "particles weren't regenerated");
+        }
+    }
+
+    #[test]
+    fn zero_step_rate_still_steps() {
+        let (_thread, frames) = spawn(0);
+        assert_eq!(
+            frames.recv_timeout(Duration::from_secs(5)).unwrap(),
+            [Vec2::ZERO]
+        );
+    }
+}